@@ -0,0 +1,282 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Attacker behaviours run against the sentinels.
+//!
+//! Each attack is a function feeding a `PureSentinel` what an attacker would send it, so that
+//! the same attack can be run at several quorum settings. The tests state at which settings
+//! an attack has to fail, and where it is known to succeed, at which it is the quorums that
+//! have to be raised. A colluding minority of a source group is run through the simulator.
+
+use rand::{Rng, XorShiftRng};
+use sodiumoxide::crypto::sign;
+use pure_sentinel::{AddResult, PureSentinel};
+use testing::{SignedClaim, SignedGroup, TestRequest};
+use SerialisedClaim;
+
+/// A request, sent by the group of `source`.
+pub type AttackRequest = TestRequest<u64>;
+
+/// Sentinel attacked.
+pub type Target = PureSentinel<AttackRequest, u64>;
+
+/// Sends each of `claims` for `request`. Returns the result of the last claim that had one.
+pub fn send_claims(target: &mut Target,
+                   request: &AttackRequest,
+                   claims: Vec<SignedClaim<u64>>,
+                   claim_quorum: usize,
+                   key_quorum: usize)
+                   -> Option<AddResult<AttackRequest, u64>> {
+    let mut last = None;
+    for claim in claims {
+        let result = target.add_claim(request.clone(), claim.claimant, claim.signature,
+                                      claim.claim, claim_quorum, key_quorum);
+        if result.is_some() {
+            last = result;
+        }
+    }
+    last
+}
+
+/// Sybil vouchers: `sybil_count` fresh names each vouch for `keys`. Returns the resolution,
+/// if the vouchers brought one about.
+pub fn sybil_vouchers(target: &mut Target,
+                      request: &AttackRequest,
+                      keys: &[(u64, sign::PublicKey)],
+                      first_sybil: u64,
+                      sybil_count: usize,
+                      key_quorum: usize)
+                      -> Option<(AttackRequest, SerialisedClaim)> {
+    (first_sybil..first_sybil + sybil_count as u64).filter_map(|sybil| {
+        target.add_keys(request.clone(), sybil, keys.to_vec(), key_quorum)
+    }).next()
+}
+
+/// Forged-key injection: a single sender lists `keys` for the claimants of `request`.
+pub fn inject_keys(target: &mut Target,
+                   request: &AttackRequest,
+                   sender: u64,
+                   keys: &[(u64, sign::PublicKey)],
+                   key_quorum: usize)
+                   -> Option<(AttackRequest, SerialisedClaim)> {
+    sybil_vouchers(target, request, keys, sender, 1, key_quorum)
+}
+
+/// Cache flooding: `count` junk requests of `claimants` unverifiable claims each, from
+/// `sources` different sources.
+pub fn flood_junk(target: &mut Target,
+                  rng: &mut XorShiftRng,
+                  count: usize,
+                  claimants: usize,
+                  sources: u64,
+                  claim_quorum: usize) {
+    for _ in 0..count {
+        let junk = AttackRequest { id: rng.gen(), source: rng.gen_range(0, sources) };
+        let forgers = SignedGroup::from_rng((0..claimants as u64).collect(), rng);
+        let _ = send_claims(target, &junk, forgers.sign_claims(&junk, &vec![0; 8]), claim_quorum,
+                            1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use eviction::EvictionPolicy;
+    use testing::{random_claim, SEED};
+    use simulation::{Faults, Network, Receiver, GROUP_SIZE, QUORUM};
+
+    const GROUP: usize = 8;
+
+    fn setup() -> (XorShiftRng, Target, AttackRequest, SignedGroup<u64>) {
+        let mut rng = XorShiftRng::from_seed(SEED);
+        let request = AttackRequest { id: rng.gen(), source: 1000 };
+        let group = SignedGroup::from_rng((0..GROUP as u64).collect(), &mut rng);
+        (rng, PureSentinel::new(), request, group)
+    }
+
+    fn claim(rng: &mut XorShiftRng) -> SerialisedClaim {
+        random_claim(16, rng)
+    }
+
+    #[test]
+    fn sybil_vouchers_need_key_quorum() {
+        for key_quorum in 1..GROUP + 2 {
+            for sybil_count in 1..GROUP + 2 {
+                let (mut rng, mut target, request, group) = setup();
+                let attackers = SignedGroup::from_rng((0..GROUP as u64).collect(), &mut rng);
+                let forged = claim(&mut rng);
+
+                let forged_claims = attackers.impersonate(&group, &request, &forged);
+                let _ = send_claims(&mut target, &request, forged_claims, GROUP, key_quorum);
+                let resolved = sybil_vouchers(&mut target, &request, &attackers.keys_as(&group),
+                                              2000, sybil_count, key_quorum);
+
+                // Sybils succeed only by outnumbering the key quorum, so it has to be set
+                // above the number of names an attacker can bring to vouch.
+                assert_eq!(resolved.is_some(), sybil_count >= key_quorum);
+            }
+        }
+    }
+
+    #[test]
+    fn injected_keys_need_key_quorum() {
+        let (mut rng, mut target, request, group) = setup();
+        let attackers = SignedGroup::from_rng((0..GROUP as u64).collect(), &mut rng);
+        let forged = claim(&mut rng);
+        let forged_claims = attackers.impersonate(&group, &request, &forged);
+        let _ = send_claims(&mut target, &request, forged_claims, GROUP, 2);
+
+        // A single sender can't vouch for keys on its own once the key quorum is above one.
+        assert!(inject_keys(&mut target, &request, 2000, &attackers.keys_as(&group),
+                            2).is_none());
+        assert!(target.is_pending(&request));
+
+        // Genuine keys from a quorum of senders still verify the genuine claims.
+        let honest = claim(&mut rng);
+        let _ = send_claims(&mut target, &request, group.sign_claims(&request, &honest), GROUP, 2);
+        assert!(sybil_vouchers(&mut target, &request, &group.keys(), 3000, 2, 2)
+                    .map_or(false, |(_, resolved)| resolved == honest));
+    }
+
+    #[test]
+    fn equivocating_claimants_counted_once() {
+        for equivocators in 0..GROUP + 1 {
+            let (mut rng, mut target, request, group) = setup();
+            let honest = claim(&mut rng);
+            let forged = claim(&mut rng);
+            let quorum = GROUP / 2 + 1;
+            let (equivocating, faithful) = group.split_at(equivocators);
+
+            // Equivocators send the forged claim first, then the honest one.
+            for claim in &[&forged, &honest] {
+                let claims = equivocating.sign_claims(&request, claim);
+                let _ = send_claims(&mut target, &request, claims, quorum, 1);
+            }
+            let claims = faithful.sign_claims(&request, &honest);
+            let _ = send_claims(&mut target, &request, claims, quorum, 1);
+            let resolved = target.add_keys(request.clone(), 2000, group.keys(), 1);
+
+            // Each claimant counts once, for the first claim it signed, so a majority quorum
+            // is never reached by two claims.
+            match resolved {
+                Some((_, ref resolved)) if *resolved == honest =>
+                    assert!(GROUP - equivocators >= quorum),
+                Some((_, ref resolved)) if *resolved == forged =>
+                    assert!(equivocators >= quorum),
+                Some(_) => panic!("Resolved to a claim no one sent"),
+                None => assert!(equivocators < quorum && GROUP - equivocators < quorum),
+            }
+
+            if GROUP - equivocators >= quorum {
+                let conflicts = target.take_conflicts();
+                assert_eq!(conflicts.len(), equivocators);
+                assert!(conflicts.iter().all(|conflict| conflict.claim == forged));
+            }
+        }
+    }
+
+    #[test]
+    fn replayed_claims_fail() {
+        for replayed in 0..GROUP + 1 {
+            let (mut rng, mut target, request, group) = setup();
+            let claim_of_other = claim(&mut rng);
+            let honest = claim(&mut rng);
+            let quorum = GROUP / 2 + 1;
+            let (replaying, rest) = group.split_at(replayed);
+
+            // Signatures of the group over the claim of another request, replayed ahead of
+            // the genuine claims for this one. They are bound to the other request, so they
+            // don't verify for this one.
+            let other = AttackRequest { id: request.id.wrapping_add(1), ..request.clone() };
+            let _ = send_claims(&mut target, &request,
+                                replaying.sign_claims(&other, &claim_of_other), quorum, 1);
+            let _ = send_claims(&mut target, &request, rest.sign_claims(&request, &honest), quorum,
+                                1);
+            let resolved = target.add_keys(request.clone(), 2000, group.keys(), 1)
+                                 .map(|(_, resolved)| resolved);
+
+            if GROUP - replayed >= quorum {
+                assert_eq!(resolved, Some(honest));
+            } else {
+                assert_eq!(resolved, None);
+            }
+        }
+    }
+
+    #[test]
+    fn junk_from_one_source_capped() {
+        let policy = EvictionPolicy { max_pending: GROUP, max_pending_per_source: 2 };
+        let (mut rng, mut target, _, _) = setup();
+        target.set_eviction_policy(policy);
+        flood_junk(&mut target, &mut rng, 100, 1, 1, GROUP);
+        assert_eq!(target.pending_count(), policy.max_pending_per_source);
+    }
+
+    #[test]
+    fn junk_from_many_sources_fails() {
+        let policy = EvictionPolicy { max_pending: GROUP, max_pending_per_source: 2 };
+        for junk_claimants in 1..GROUP + 1 {
+            let (mut rng, mut target, request, group) = setup();
+            target.set_eviction_policy(policy);
+            let honest = claim(&mut rng);
+
+            // An earlier request of the group confirms the keys of its members.
+            let earlier = AttackRequest { id: request.id.wrapping_add(1), ..request.clone() };
+            let claims = group.sign_claims(&earlier, &honest);
+            let _ = send_claims(&mut target, &earlier, claims, GROUP, 1);
+            assert!(target.add_keys(earlier, 2000, group.keys(), 1).is_some());
+
+            // Junk with as many claimants as the request, from many sources, can't evict it
+            // while it is a claim short, as none of the junk claims verify.
+            let mut claims = group.sign_claims(&request, &honest);
+            let last = claims.pop().expect("Claims of the group");
+            let _ = send_claims(&mut target, &request, claims, GROUP, 1);
+            flood_junk(&mut target, &mut rng, 10 * GROUP, junk_claimants, 1 << 32, GROUP);
+            assert!(target.pending_count() <= policy.max_pending);
+
+            match send_claims(&mut target, &request, vec![last], GROUP, 1) {
+                Some(AddResult::Resolved(_, resolved)) => assert_eq!(resolved, honest),
+                _ => panic!("expected the request to resolve"),
+            }
+        }
+    }
+
+    #[test]
+    fn colluding_minority_fails() {
+        let faults = Faults { drop_percent: 0, max_delay: 10, churn_percent: 0 };
+
+        for colluders in 0..GROUP_SIZE + 1 {
+            for seed in 1..21 {
+                let mut network = Network::new(seed, faults);
+                network.set_colluders(colluders);
+                let outcome = network.run(&mut Receiver::group());
+
+                // Colluders get their way only by making up a quorum themselves. The honest
+                // members resolve if they are one more than a quorum, as no one vouches for
+                // its own key in a GetGroupKey response.
+                if colluders >= QUORUM {
+                    assert_eq!(outcome.resolved, Some(outcome.forged_claim));
+                } else if GROUP_SIZE - colluders > QUORUM {
+                    assert_eq!(outcome.resolved, Some(outcome.claim));
+                } else {
+                    assert_eq!(outcome.resolved, None);
+                }
+            }
+        }
+    }
+}
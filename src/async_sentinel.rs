@@ -0,0 +1,497 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Futures adapters for `PureSentinel` and `KeySentinel`, built with the `async` feature.
+//!
+//! `AsyncSentinel` is a `Sink` of claims and keys, and a `Stream` of what came of them. On
+//! every tick of its timer it expires requests pending for too long and asks again for keys
+//! still missing, with the backoff of `PureSentinel::poll_key_requests`. Conflicting claims
+//! found on resolution are emitted as misbehaviour evidence.
+//!
+//! `AsyncKeySentinel` is a `Sink` of GetGroupKey responses and a `Stream` of the groups they
+//! confirm. It needs no timer, as a `KeySentinel` holds nothing that expires.
+//!
+//! Both wake the task polling their stream when a send leaves events for it, so that the
+//! stream and the sink can be driven by different tasks after `Stream::split`.
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::task::{self, Task};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::time;
+use tokio_timer::{Interval, Timer, TimerError};
+use key_sentinel::{GroupClaimTrait, IdTrait, KeySentinel};
+use limits::Rejection;
+use pure_sentinel::{AddResult, Conflict, PureSentinel, Source};
+use signature_scheme::SignatureScheme;
+use super::SerialisedClaim;
+
+/// Input accepted by `AsyncSentinel`.
+pub enum Input<Request, Name, Scheme: SignatureScheme> {
+    /// A claim, as passed to `PureSentinel::add_claim`: request, claimant, signature, claim,
+    /// claim quorum and key quorum.
+    Claim(Request, Name, Scheme::Signature, SerialisedClaim, usize, usize),
+    /// Keys, as passed to `PureSentinel::add_keys`: request, sender, keys and key quorum.
+    Keys(Request, Name, Vec<(Name, Scheme::PublicKey)>, usize),
+}
+
+/// Output of `AsyncSentinel`.
+pub enum Event<Request, Name, Signature> {
+    /// The request resolved to the claim.
+    Resolved(Request, SerialisedClaim),
+    /// A claim arrived for a request that resolved recently, to the claim given.
+    AlreadyResolved(Request, SerialisedClaim),
+    /// Public keys should be requested from the group surrounding the name.
+    RequestKeys(Name),
+    /// Keys of the claimants of the pending request are still missing, and should be
+    /// requested again.
+    RequestClaimantKeys(Request, Vec<Name>),
+    /// A claim for the request was not stored.
+    Rejected(Request, Rejection),
+    /// The request was dropped after pending for longer than the maximum age.
+    Expired(Request),
+    /// A claimant signed a claim other than the one the request resolved to.
+    Misbehaviour(Conflict<Request, Name, Signature>),
+}
+
+/// Drives a `PureSentinel` as a `Sink` of `Input` and a `Stream` of `Event`.
+pub struct AsyncSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    sentinel: PureSentinel<Request, Name, Scheme>,
+    events: VecDeque<Event<Request, Name, Scheme::Signature>>,
+    ticks: Interval,
+    max_age: ::time::Duration,
+    closed: bool,
+    waiting: Waiting,
+}
+
+impl<Request, Name, Scheme> AsyncSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    /// Wraps `sentinel`. Every `tick` of `timer`, requests pending for longer than `max_age`
+    /// are expired and missing keys requested again. `tick` has to be longer than the tick
+    /// duration of `timer`, which would otherwise consider every tick due at once.
+    pub fn new(sentinel: PureSentinel<Request, Name, Scheme>,
+               timer: &Timer,
+               tick: time::Duration,
+               max_age: ::time::Duration)
+               -> AsyncSentinel<Request, Name, Scheme> {
+        AsyncSentinel {
+            sentinel: sentinel,
+            events: VecDeque::new(),
+            ticks: timer.interval(tick),
+            max_age: max_age,
+            closed: false,
+            waiting: Waiting(None),
+        }
+    }
+
+    /// Gives access to the wrapped sentinel, to configure it.
+    pub fn sentinel(&mut self) -> &mut PureSentinel<Request, Name, Scheme> {
+        &mut self.sentinel
+    }
+
+    fn add(&mut self, input: Input<Request, Name, Scheme>) {
+        match input {
+            Input::Claim(request, claimant, signature, claim, claim_quorum, key_quorum) => {
+                let result = self.sentinel.add_claim(request.clone(), claimant, signature, claim,
+                                                     claim_quorum, key_quorum);
+                match result {
+                    Some(AddResult::RequestKeys(source)) =>
+                        self.events.push_back(Event::RequestKeys(source)),
+                    Some(AddResult::Resolved(request, claim)) =>
+                        self.events.push_back(Event::Resolved(request, claim)),
+                    Some(AddResult::AlreadyResolved(request, claim)) =>
+                        self.events.push_back(Event::AlreadyResolved(request, claim)),
+                    Some(AddResult::Rejected(rejection)) =>
+                        self.events.push_back(Event::Rejected(request, rejection)),
+                    None => (),
+                }
+            }
+            Input::Keys(request, sender, keys, key_quorum) => {
+                if let Some((request, claim)) = self.sentinel.add_keys(request, sender, keys,
+                                                                       key_quorum) {
+                    self.events.push_back(Event::Resolved(request, claim));
+                }
+            }
+        }
+
+        for conflict in self.sentinel.take_conflicts() {
+            self.events.push_back(Event::Misbehaviour(conflict));
+        }
+    }
+
+    fn tick(&mut self) {
+        for request in self.sentinel.expire_pending(self.max_age) {
+            self.events.push_back(Event::Expired(request));
+        }
+        for (request, missing) in self.sentinel.poll_key_requests() {
+            self.events.push_back(Event::RequestClaimantKeys(request, missing));
+        }
+    }
+}
+
+impl<Request, Name, Scheme> Sink for AsyncSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    type SinkItem = Input<Request, Name, Scheme>;
+    type SinkError = ();
+
+    fn start_send(&mut self, input: Self::SinkItem) -> StartSend<Self::SinkItem, ()> {
+        self.add(input);
+        if !self.events.is_empty() {
+            self.waiting.wake();
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), ()> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), ()> {
+        self.closed = true;
+        self.waiting.wake();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<Request, Name, Scheme> Stream for AsyncSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    type Item = Event<Request, Name, Scheme::Signature>;
+    type Error = TimerError;
+
+    /// Ends once the sink side is closed and all events are taken.
+    fn poll(&mut self) -> Poll<Option<Self::Item>, TimerError> {
+        while let Async::Ready(Some(())) = try!(self.ticks.poll()) {
+            self.tick();
+        }
+
+        match self.events.pop_front() {
+            Some(event) => Ok(Async::Ready(Some(event))),
+            None if self.closed => Ok(Async::Ready(None)),
+            None => {
+                self.waiting.park();
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+/// Input accepted by `AsyncKeySentinel`.
+pub enum KeyInput<Request, Name, GroupClaim, Scheme: SignatureScheme> {
+    /// A GetGroupKey response, as passed to `KeySentinel::add_identities`: request, sender,
+    /// serialised group claim, signature, group claim and quorum.
+    Response(Request, Name, SerialisedClaim, Scheme::Signature, GroupClaim, usize),
+}
+
+/// Output of `AsyncKeySentinel`.
+pub enum KeyEvent<Request, IdType> {
+    /// A quorum of responses confirmed the identities of the group the request was sent from.
+    Confirmed(Request, Vec<IdType>),
+    /// A response for the request was not stored.
+    Rejected(Request, Rejection),
+}
+
+/// Drives a `KeySentinel` as a `Sink` of `KeyInput` and a `Stream` of `KeyEvent`.
+pub struct AsyncKeySentinel<Request, Name, IdType, GroupClaim, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    sentinel: KeySentinel<Request, Name, IdType, GroupClaim, Scheme>,
+    events: VecDeque<KeyEvent<Request, IdType>>,
+    closed: bool,
+    waiting: Waiting,
+}
+
+impl<Request, Name, IdType, GroupClaim, Scheme>
+    AsyncKeySentinel<Request, Name, IdType, GroupClaim, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    /// Wraps `sentinel`.
+    pub fn new(sentinel: KeySentinel<Request, Name, IdType, GroupClaim, Scheme>)
+               -> AsyncKeySentinel<Request, Name, IdType, GroupClaim, Scheme> {
+        AsyncKeySentinel {
+            sentinel: sentinel,
+            events: VecDeque::new(),
+            closed: false,
+            waiting: Waiting(None),
+        }
+    }
+
+    /// Gives access to the wrapped sentinel, to configure it.
+    pub fn sentinel(&mut self) -> &mut KeySentinel<Request, Name, IdType, GroupClaim, Scheme> {
+        &mut self.sentinel
+    }
+}
+
+impl<Request, Name, IdType, GroupClaim, Scheme> Sink
+    for AsyncKeySentinel<Request, Name, IdType, GroupClaim, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    type SinkItem = KeyInput<Request, Name, GroupClaim, Scheme>;
+    type SinkError = ();
+
+    fn start_send(&mut self, input: Self::SinkItem) -> StartSend<Self::SinkItem, ()> {
+        let KeyInput::Response(request, sender, serialised, signature, group_claim, quorum) =
+            input;
+        let event = match self.sentinel.add_identities(request.clone(), sender, serialised,
+                                                       signature, group_claim, quorum) {
+            Ok(Some((request, identities))) => Some(KeyEvent::Confirmed(request, identities)),
+            Ok(None) => None,
+            Err(rejection) => Some(KeyEvent::Rejected(request, rejection)),
+        };
+        if let Some(event) = event {
+            self.events.push_back(event);
+            self.waiting.wake();
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), ()> {
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), ()> {
+        self.closed = true;
+        self.waiting.wake();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<Request, Name, IdType, GroupClaim, Scheme> Stream
+    for AsyncKeySentinel<Request, Name, IdType, GroupClaim, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    type Item = KeyEvent<Request, IdType>;
+    type Error = ();
+
+    /// Ends once the sink side is closed and all events are taken.
+    fn poll(&mut self) -> Poll<Option<Self::Item>, ()> {
+        match self.events.pop_front() {
+            Some(event) => Ok(Async::Ready(Some(event))),
+            None if self.closed => Ok(Async::Ready(None)),
+            None => {
+                self.waiting.park();
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+// The task last told its stream was not ready, to be woken once events arrive.
+struct Waiting(Option<Task>);
+
+impl Waiting {
+    fn park(&mut self) {
+        self.0 = Some(task::current());
+    }
+
+    fn wake(&mut self) {
+        if let Some(task) = self.0.take() {
+            task.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{stream, Future, Sink, Stream};
+    use testing::{random, random_keypair, reset_random, sign_claim};
+    use std::time;
+    use tokio_timer;
+    use key_sentinel::KeySentinel;
+    use pure_sentinel::{PureSentinel, Source};
+    use signature_scheme::Ed25519;
+    use std::sync::mpsc;
+    use std::thread;
+    use testing::{seeded_keypair, seeded_rng, SignedGroup, TestGroupClaim, TestId};
+
+    const QUORUM: usize = 3;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        name: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.name
+        }
+    }
+
+    type TestSentinel = AsyncSentinel<TestRequest, u64, Ed25519>;
+
+    fn new_sentinel(max_age: ::time::Duration) -> TestSentinel {
+        let timer = tokio_timer::wheel().tick_duration(time::Duration::from_millis(5)).build();
+        AsyncSentinel::new(PureSentinel::new(), &timer, time::Duration::from_millis(20), max_age)
+    }
+
+    #[test]
+    fn claims_in_events_out() {
+        reset_random();
+        let mut sentinel = new_sentinel(::time::Duration::hours(1));
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let claim = vec![random::<u8>(); 16];
+        let conflicting_claim = vec![random::<u8>(); 8];
+        let mut keys = Vec::new();
+
+        for name in 0..QUORUM + 1 {
+            let key_pair = random_keypair();
+            let claimed = if name == QUORUM { &conflicting_claim } else { &claim };
+            let signature = sign_claim(&request, claimed, &key_pair.1);
+            keys.push((name as u64, key_pair.0));
+            sentinel = sentinel.send(Input::Claim(request.clone(), name as u64, signature,
+                                                  claimed.clone(), QUORUM, 1))
+                               .wait().ok().unwrap();
+        }
+        sentinel = sentinel.send(Input::Keys(request.clone(), 1000, keys, 1)).wait().ok().unwrap();
+
+        let events = sentinel.take(3).collect().wait().ok().unwrap();
+        match events[0] {
+            Event::RequestKeys(source) => assert_eq!(source, request.name),
+            _ => panic!("expected key request"),
+        }
+        match events[1] {
+            Event::Resolved(ref resolved, ref resolved_claim) => {
+                assert_eq!(*resolved, request);
+                assert_eq!(*resolved_claim, claim);
+            }
+            _ => panic!("expected resolution"),
+        }
+        match events[2] {
+            Event::Misbehaviour(ref conflict) => {
+                assert_eq!(conflict.claimant, QUORUM as u64);
+                assert_eq!(conflict.claim, conflicting_claim);
+            }
+            _ => panic!("expected misbehaviour"),
+        }
+    }
+
+    #[test]
+    fn sends_wake_the_stream() {
+        reset_random();
+        // The first tick is too late to be what wakes the stream.
+        let timer = tokio_timer::wheel().tick_duration(time::Duration::from_millis(5)).build();
+        let sentinel = AsyncSentinel::<TestRequest, u64, Ed25519>::new(
+            PureSentinel::new(), &timer, time::Duration::from_secs(10),
+            ::time::Duration::hours(1));
+        let (sink, stream) = sentinel.split();
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let claim = vec![random::<u8>(); 16];
+        let key_pair = seeded_keypair(42);
+        let signature = sign_claim(&request, &claim, &key_pair.1);
+
+        // The claim is only sent once the stream has been polled and found nothing.
+        let (polled_sender, polled) = mpsc::channel();
+        let mut stream = stream;
+        let polled_stream = stream::poll_fn(move || {
+            let result = stream.poll();
+            let _ = polled_sender.send(());
+            result
+        });
+        let started = time::Instant::now();
+        let polling = thread::spawn(move || {
+            polled_stream.take(1).collect().wait().ok().unwrap().len()
+        });
+        polled.recv().unwrap();
+        let _ = sink.send(Input::Claim(request, 0, signature, claim, QUORUM, 1)).wait();
+
+        assert_eq!(polling.join().unwrap(), 1);
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn key_responses_in_groups_out() {
+        reset_random();
+        let mut rng = seeded_rng(42);
+        let group = SignedGroup::<u64>::random(QUORUM + 1, &mut rng);
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let mut sentinel: AsyncKeySentinel<TestRequest, u64, TestId<u64>, TestGroupClaim<u64>,
+                                           Ed25519> = AsyncKeySentinel::new(KeySentinel::new());
+
+        for response in group.key_responses() {
+            sentinel = sentinel.send(KeyInput::Response(request.clone(), response.sender,
+                                                        response.serialised,
+                                                        response.signature,
+                                                        response.group_claim, QUORUM))
+                               .wait().ok().unwrap();
+        }
+        let _ = sentinel.close();
+
+        let events = sentinel.collect().wait().ok().unwrap();
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            KeyEvent::Confirmed(ref confirmed, ref identities) => {
+                assert_eq!(*confirmed, request);
+                assert_eq!(identities.len(), group.len());
+            }
+            _ => panic!("expected confirmation"),
+        }
+    }
+
+    #[test]
+    fn pending_requests_expire() {
+        reset_random();
+        let sentinel = new_sentinel(::time::Duration::zero());
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let claim = vec![random::<u8>(); 16];
+        let key_pair = random_keypair();
+        let signature = sign_claim(&request, &claim, &key_pair.1);
+
+        let sentinel = sentinel.send(Input::Claim(request.clone(), 0, signature, claim, QUORUM,
+                                                  1))
+                               .wait().ok().unwrap();
+
+        let events = sentinel.filter(|event| match *event {
+                                 Event::Expired(_) => true,
+                                 _ => false,
+                             })
+                             .take(1).collect().wait().ok().unwrap();
+        match events[0] {
+            Event::Expired(ref expired) => assert_eq!(*expired, request),
+            _ => unreachable!(),
+        }
+    }
+}
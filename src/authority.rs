@@ -0,0 +1,120 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Authorities requests are sent with, and the quorums each of them requires.
+//!
+//! A request implementing `GetAuthority` can be passed to the `*_by_authority` methods of the
+//! sentinels, which look its thresholds up in a `PolicyTable` instead of taking them from the
+//! caller.
+
+use std::collections::BTreeMap;
+
+/// Number of nodes in a close group.
+pub const GROUP_SIZE: usize = 32;
+/// Number of nodes of a close group needed to reach consensus.
+pub const QUORUM_SIZE: usize = 28;
+
+/// The authority a request was sent with.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+pub enum Authority {
+    /// The close group of a client.
+    ClientManager,
+    /// The close group of a network addressable element, such as a chunk of data.
+    NaeManager,
+    /// The close group of a node.
+    NodeManager,
+    /// A single client, signing on its own.
+    Client,
+}
+
+/// Implemented by requests to tell the authority they were sent with.
+pub trait GetAuthority {
+    /// Returns the authority the request was sent with.
+    fn get_authority(&self) -> Authority;
+}
+
+/// Thresholds a request of one authority has to reach.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct QuorumPolicy {
+    /// Weight of claims that have to agree.
+    pub claim_quorum: usize,
+    /// Weight of senders that have to vouch for a key before it is used.
+    pub key_quorum: usize,
+    /// If true, claims are only accepted from claimants in the close group of the source.
+    pub close_group_only: bool,
+}
+
+/// Maps each authority to its quorum policy.
+#[derive(Clone, Debug)]
+pub struct PolicyTable {
+    policies: BTreeMap<Authority, QuorumPolicy>,
+}
+
+impl PolicyTable {
+    /// Returns the policy of `authority`.
+    pub fn get(&self, authority: Authority) -> QuorumPolicy {
+        // All authorities are present from `default` on.
+        self.policies[&authority]
+    }
+
+    /// Replaces the policy of `authority`.
+    pub fn set(&mut self, authority: Authority, policy: QuorumPolicy) {
+        let _ = self.policies.insert(authority, policy);
+    }
+}
+
+impl Default for PolicyTable {
+    /// Groups need a quorum of their members to agree, with keys vouched for by a quorum of
+    /// the group surrounding the key holder. A client's own signature suffices for it, and
+    /// `add_claim_by_authority` accepts no other.
+    fn default() -> PolicyTable {
+        let group = QuorumPolicy {
+            claim_quorum: QUORUM_SIZE,
+            key_quorum: QUORUM_SIZE,
+            close_group_only: true,
+        };
+        let client = QuorumPolicy {
+            claim_quorum: 1,
+            key_quorum: QUORUM_SIZE,
+            close_group_only: false,
+        };
+
+        let mut policies = BTreeMap::new();
+        let _ = policies.insert(Authority::ClientManager, group);
+        let _ = policies.insert(Authority::NaeManager, group);
+        let _ = policies.insert(Authority::NodeManager, group);
+        let _ = policies.insert(Authority::Client, client);
+        PolicyTable { policies: policies }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn policies_replaced_per_authority() {
+        let mut table = PolicyTable::default();
+        assert_eq!(table.get(Authority::NaeManager).claim_quorum, QUORUM_SIZE);
+        assert_eq!(table.get(Authority::Client).claim_quorum, 1);
+
+        let policy = QuorumPolicy { claim_quorum: 3, key_quorum: 2, close_group_only: false };
+        table.set(Authority::NaeManager, policy);
+        assert_eq!(table.get(Authority::NaeManager), policy);
+        assert_eq!(table.get(Authority::NodeManager).claim_quorum, QUORUM_SIZE);
+    }
+}
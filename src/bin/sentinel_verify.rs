@@ -0,0 +1,190 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Verifies sentinel artefacts and recorded traces offline.
+//!
+//! Usage: `sentinel_verify --trusted-keys KEYS --claim-quorum N --key-quorum N FILE...` or
+//! `sentinel_verify --trace FILE...`
+//!
+//! With `--trusted-keys`, each file holds a CBOR sequence of `sentinel::evidence::Artefact`s:
+//! resolution certificates, equivocation proofs and key voucher sets. Key vouchers count only
+//! if signed by a key the file KEYS lists for their sender, one line of a hexadecimal name and
+//! public key each, as `TrustedKeys::parse` reads them. Artefacts setting a claim or key quorum
+//! below the ones given fail. Every artefact is printed with whether it verified, and if not,
+//! why.
+//!
+//! With `--trace`, each file holds a trace written by a `TraceWriter`. Every entry is printed
+//! with its stamp, call and outcome, and the stamps are checked not to go back in time.
+//!
+//! Files that can't be opened or decoded, or hold nothing, are reported on the standard error.
+//! The exit status is 0 only if every file held something and all of it verified, and 2 on
+//! wrong usage.
+
+extern crate cbor;
+extern crate sentinel;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+use cbor::Decoder;
+use sentinel::evidence::{Artefact, TrustedKeys};
+use sentinel::trace;
+
+const USAGE: &'static str = "Usage: sentinel_verify --trusted-keys KEYS --claim-quorum N \
+                             --key-quorum N FILE...\n       \
+                             sentinel_verify --trace FILE...";
+
+fn report_error(message: String) {
+    let _ = writeln!(&mut io::stderr(), "{}", message);
+}
+
+fn open(path: &str) -> Option<File> {
+    match File::open(path) {
+        Ok(file) => Some(file),
+        Err(error) => {
+            report_error(format!("{}: cannot open: {}", path, error));
+            None
+        }
+    }
+}
+
+fn read_trusted_keys(path: &str, claim_quorum: usize, key_quorum: usize) -> Option<TrustedKeys> {
+    let mut file = match open(path) {
+        Some(file) => file,
+        None => return None,
+    };
+    let mut text = String::new();
+    if let Err(error) = file.read_to_string(&mut text) {
+        report_error(format!("{}: cannot read: {}", path, error));
+        return None;
+    }
+    match TrustedKeys::parse(&text, claim_quorum, key_quorum) {
+        Ok(trusted) => Some(trusted),
+        Err(error) => {
+            report_error(format!("{}: {}", path, error));
+            None
+        }
+    }
+}
+
+// Prints the verdict on each artefact of the file at `path`. Returns true if there was at
+// least one and all verified.
+fn verify_file(path: &str, trusted: &TrustedKeys) -> bool {
+    let file = match open(path) {
+        Some(file) => file,
+        None => return false,
+    };
+
+    let mut all_verified = true;
+    let mut count = 0;
+    for (index, artefact) in Decoder::from_reader(file).decode::<Artefact>().enumerate() {
+        count += 1;
+        let artefact = match artefact {
+            Ok(artefact) => artefact,
+            Err(error) => {
+                report_error(format!("{} #{}: cannot decode: {}", path, index, error));
+                return false;
+            }
+        };
+
+        let failures = artefact.verify(trusted);
+        if failures.is_empty() {
+            println!("{} #{}: {}: verified", path, index, artefact.describe());
+        } else {
+            all_verified = false;
+            println!("{} #{}: {}: FAILED", path, index, artefact.describe());
+            for failure in failures {
+                println!("    {}", failure);
+            }
+        }
+    }
+
+    if count == 0 {
+        report_error(format!("{}: no artefacts", path));
+        return false;
+    }
+    all_verified
+}
+
+// Prints each entry of the trace in the file at `path`. Returns true if there was at least
+// one and the trace is sound.
+fn check_trace(path: &str) -> bool {
+    let file = match open(path) {
+        Some(file) => file,
+        None => return false,
+    };
+
+    match trace::read_untyped_trace(file) {
+        Ok(ref entries) if entries.is_empty() => {
+            report_error(format!("{}: no trace entries", path));
+            false
+        }
+        Ok(entries) => {
+            for (index, entry) in entries.iter().enumerate() {
+                println!("{} #{}: +{}ms {} -> {}", path, index, entry.at_millis, entry.call,
+                         entry.outcome);
+            }
+            println!("{}: {} entries in order", path, entries.len());
+            true
+        }
+        Err(error) => {
+            report_error(format!("{}: {}", path, error));
+            false
+        }
+    }
+}
+
+// Returns the quorum given as `value` for the option `option`, or exits on wrong usage.
+fn quorum(option: &str, value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(quorum) if quorum > 0 => quorum,
+        _ => {
+            report_error(format!("{}: expected a positive number, got {}", option, value));
+            usage()
+        }
+    }
+}
+
+fn usage() -> ! {
+    report_error(USAGE.to_string());
+    process::exit(2);
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let results = match args.first().map(|arg| &arg[..]) {
+        Some("--trusted-keys") if args.len() > 6 && args[2] == "--claim-quorum" &&
+                                  args[4] == "--key-quorum" => {
+            let claim_quorum = quorum(&args[2], &args[3]);
+            let key_quorum = quorum(&args[4], &args[5]);
+            let trusted = match read_trusted_keys(&args[1], claim_quorum, key_quorum) {
+                Some(trusted) => trusted,
+                None => process::exit(2),
+            };
+            args[6..].iter().map(|path| verify_file(path, &trusted)).collect::<Vec<_>>()
+        }
+        Some("--trace") if args.len() > 1 => {
+            args[1..].iter().map(|path| check_trace(path)).collect::<Vec<_>>()
+        }
+        _ => usage(),
+    };
+
+    if !results.into_iter().all(|verified| verified) {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! ClientSentinel confirms messages carrying the direct authority of a single node or client.
+//!
+//! Where `PureSentinel` waits for a quorum of a group, a client message is verified on its
+//! own against the public key of its source, taken from the client's Fob. Keys that verified
+//! a message are remembered, so later messages of the same client resolve as soon as they
+//! arrive. Messages of clients whose key is not known yet are held until it is added.
+//! Resolved requests are remembered for a time window, as in `PureSentinel`, and replays of
+//! them are answered with `AddResult::AlreadyResolved`. The window is measured with the clock
+//! given to `set_clock`.
+//!
+//! A client signs its claim bound to the request, as `bound_claim` encodes them, so a
+//! captured message doesn't verify under any other request, however long ago it was sent.
+
+use lru_time_cache::LruCache;
+use std::sync::Arc;
+use time::Duration;
+use clock::{self, Clock};
+use expiring_cache::ExpiringCache;
+use pure_sentinel::{AddResult, Source, MAX_RESOLVED_COUNT, RESOLVED_EXPIRY_SECS};
+use send_get_keys::{KeyRequest, KeySink, SendGetKeys};
+use signature_scheme::{Ed25519, SignatureScheme};
+use super::{SerialisedClaim, bound_claim, serialise, verify_signature};
+
+const MAX_REQUEST_COUNT: usize = 1000;
+const MAX_CLIENT_COUNT: usize = 1000;
+/// Number of messages held per request while the key of its client is fetched.
+pub const MAX_MESSAGES_PER_REQUEST: usize = 4;
+
+// Messages of a request held until the key of its client verifies one of them.
+struct Held<Signature, PublicKey> {
+    messages: Vec<(Signature, SerialisedClaim)>,
+    // A key added for the request that verified none of the messages held then. Later
+    // messages are checked against it as they arrive, so that forged messages filling the
+    // held ones don't keep the genuine one from resolving once it is sent again.
+    key: Option<PublicKey>,
+}
+
+/// ClientSentinel is templated on an immutable Request type whose source is the client, and
+/// a Name type identifying clients.
+pub struct ClientSentinel<Request, Name, Scheme = Ed25519>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    // Keys that verified a message of their client.
+    keys: LruCache<Name, Scheme::PublicKey>,
+    pending: LruCache<Request, Held<Scheme::Signature, Scheme::PublicKey>>,
+    resolved: ExpiringCache<Request, SerialisedClaim>,
+    key_sink: Option<KeySink<Name>>,
+    clock: Arc<Clock>,
+}
+
+impl<Request, Name, Scheme> ClientSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    /// Creates a sentinel that knows no client keys yet.
+    pub fn new() -> ClientSentinel<Request, Name, Scheme> {
+        ClientSentinel {
+            keys: LruCache::with_capacity(MAX_CLIENT_COUNT),
+            pending: LruCache::with_capacity(MAX_REQUEST_COUNT),
+            resolved: ExpiringCache::new(Duration::seconds(RESOLVED_EXPIRY_SECS),
+                                         MAX_RESOLVED_COUNT),
+            key_sink: None,
+            clock: clock::system_clock(),
+        }
+    }
+
+    /// Replaces how long and how many resolved requests are remembered. Those resolved so far
+    /// are forgotten.
+    pub fn set_replay_window(&mut self, expiry: Duration, capacity: usize) {
+        self.resolved = ExpiringCache::new(expiry, capacity);
+    }
+
+    /// Replaces the clock the replay window is measured with.
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the sink client keys are asked for through, with `get_client_key`. `add_message`
+    /// then no longer returns `AddResult::RequestKeys`.
+    pub fn set_key_sink(&mut self, sink: Box<SendGetKeys<Name> + Send>) {
+        self.key_sink = Some(KeySink::new(sink));
+    }
+
+    /// This adds a message for the provided request, signed by the client the request came
+    /// from over `claim` bound to the request. At most `MAX_MESSAGES_PER_REQUEST` messages
+    /// are held per request until the key of the client is added.
+    ///
+    /// Possible results are:
+    /// * Some(AddResult::Resolved(request, serialised_claim)): indicating
+    ///   that the signature verified against the key of the client.
+    /// * Some(AddResult::RequestKeys(client)): indicating that the caller
+    ///   should fetch the key of the client and pass it to `add_key`.
+    /// * Some(AddResult::AlreadyResolved(request, serialised_claim)):
+    ///   indicating that the request resolved recently, to the claim given.
+    /// * None: indicating that the signature did not verify, or that the
+    ///   message is held until the key of the client is added.
+    pub fn add_message(&mut self,
+                       request: Request,
+                       signature: Scheme::Signature,
+                       claim: SerialisedClaim)
+                       -> Option<AddResult<Request, Name>> {
+        let now = self.clock.now();
+        if let Some(resolved_claim) = self.resolved.get(&request, now).cloned() {
+            return Some(AddResult::AlreadyResolved(request, resolved_claim));
+        }
+
+        let client = request.get_source();
+
+        if let Some(public_key) = self.keys.get(&client).cloned() {
+            return self.verify(request, &public_key, &[(signature, claim)])
+                       .map(|(request, claim)| AddResult::Resolved(request, claim));
+        }
+
+        let request_key = self.pending.get(&request).and_then(|held| held.key.clone());
+        if let Some(public_key) = request_key {
+            return self.verify(request, &public_key, &[(signature, claim)])
+                       .map(|(request, claim)| AddResult::Resolved(request, claim));
+        }
+
+        let saw_first_time = !self.pending.check(&request);
+        {
+            let held = self.pending.entry(request).or_insert_with(|| {
+                Held { messages: Vec::new(), key: None }
+            });
+            if held.messages.len() < MAX_MESSAGES_PER_REQUEST {
+                held.messages.push((signature, claim));
+            }
+        }
+
+        if !saw_first_time {
+            return None;
+        }
+
+        match self.key_sink {
+            Some(ref mut key_sink) => {
+                let _ = key_sink.send(KeyRequest::Client(client), now);
+                None
+            }
+            None => Some(AddResult::RequestKeys(client)),
+        }
+    }
+
+    /// This adds the public key of the client the request came from. The key has to be
+    /// obtained from a trusted source. When it verifies a message held for the request, the
+    /// request and the claim are returned, and the key is remembered for later messages of
+    /// the same client. Otherwise the held messages are dropped and the key is kept for
+    /// messages of this request only. Keys for requests with no messages held are ignored.
+    pub fn add_key(&mut self,
+                   request: Request,
+                   public_key: Scheme::PublicKey)
+                   -> Option<(Request, SerialisedClaim)> {
+        let messages = match self.pending.get_mut(&request) {
+            Some(held) => {
+                held.key = Some(public_key.clone());
+                held.messages.drain(..).collect::<Vec<_>>()
+            }
+            None => return None,
+        };
+
+        if let Some(ref mut key_sink) = self.key_sink {
+            key_sink.answered(&KeyRequest::Client(request.get_source()));
+        }
+
+        self.verify(request, &public_key, &messages)
+    }
+
+    // Resolves `request` to the first of `messages` whose signature verifies against
+    // `public_key`, which is then remembered for the client.
+    fn verify(&mut self,
+              request: Request,
+              public_key: &Scheme::PublicKey,
+              messages: &[(Scheme::Signature, SerialisedClaim)])
+              -> Option<(Request, SerialisedClaim)> {
+        let serialised_request = serialise(&request);
+        let verified = messages.iter().find(|&&(ref signature, ref claim)| {
+            let bound = bound_claim(&serialised_request, claim);
+            verify_signature::<Scheme>(signature, public_key, &bound).is_some()
+        });
+        let claim = match verified {
+            Some(&(_, ref claim)) => claim.clone(),
+            None => return None,
+        };
+
+        let _ = self.pending.remove(&request);
+        self.keys.add(request.get_source(), public_key.clone());
+        self.resolved.add(request.clone(), claim.clone(), self.clock.now());
+        Some((request, claim))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::{random, random_keypair, reset_random, seeded_keypair, sign_claim};
+    use pure_sentinel::{AddResult, Source, RESOLVED_EXPIRY_SECS};
+    use clock::ManualClock;
+    use std::sync::Arc;
+    use time::Duration;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        client: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.client
+        }
+    }
+
+    #[test]
+    fn client_messages_verified_directly() {
+        reset_random();
+        let mut sentinel: ClientSentinel<TestRequest, u64> = ClientSentinel::new();
+        let client = random::<u64>();
+        let key_pair = random_keypair();
+        let forger = random_keypair();
+        let request = TestRequest { core: 0, client: client };
+        let claim = vec![random::<u8>(); 16];
+        let signature = sign_claim(&request, &claim, &key_pair.1);
+
+        // A forged message first does not stop the genuine one from resolving.
+        match sentinel.add_message(request.clone(), sign_claim(&request, &claim, &forger.1),
+                                   claim.clone()) {
+            Some(AddResult::RequestKeys(source)) => assert_eq!(source, client),
+            _ => panic!("expected key request"),
+        }
+        assert!(sentinel.add_message(request.clone(), signature.clone(), claim.clone()).is_none());
+
+        assert!(sentinel.add_key(request.clone(), key_pair.0)
+            .and_then(|result| { assert_eq!(result.0, request);
+                                 assert_eq!(result.1, claim);
+                                 Some(result)
+            }).is_some());
+
+        // Replays are answered with the original result.
+        match sentinel.add_message(request.clone(), signature.clone(), claim.clone()) {
+            Some(AddResult::AlreadyResolved(resolved, resolved_claim)) => {
+                assert_eq!(resolved, request);
+                assert_eq!(resolved_claim, claim);
+            }
+            _ => panic!("expected replay to be detected"),
+        }
+
+        // The key of the client is now known.
+        let next_request = TestRequest { core: 1, client: client };
+        let next_claim = vec![random::<u8>(); 16];
+        let forged = sign_claim(&next_request, &next_claim, &forger.1);
+        assert!(sentinel.add_message(next_request.clone(), forged, next_claim.clone()).is_none());
+        match sentinel.add_message(next_request.clone(),
+                                   sign_claim(&next_request, &next_claim, &key_pair.1),
+                                   next_claim.clone()) {
+            Some(AddResult::Resolved(resolved, _)) => assert_eq!(resolved, next_request),
+            _ => panic!("expected resolution"),
+        }
+    }
+
+    #[test]
+    fn claims_replayed_under_fresh_requests_dropped() {
+        reset_random();
+        let mut sentinel: ClientSentinel<TestRequest, u64> = ClientSentinel::new();
+        let clock = ManualClock::new();
+        sentinel.set_clock(Arc::new(clock.clone()));
+        let client = random::<u64>();
+        let key_pair = seeded_keypair(39);
+        let claim = vec![random::<u8>(); 16];
+        let request = TestRequest { core: 0, client: client };
+        let signature = sign_claim(&request, &claim, &key_pair.1);
+
+        let _ = sentinel.add_message(request.clone(), signature.clone(), claim.clone());
+        assert!(sentinel.add_key(request, key_pair.0).is_some());
+
+        // The captured message doesn't verify under another request of the client, within
+        // the replay window or after it.
+        for core in 1..3 {
+            let fresh = TestRequest { core: core, client: client };
+            assert!(sentinel.add_message(fresh, signature.clone(), claim.clone()).is_none());
+        }
+        clock.advance(Duration::seconds(RESOLVED_EXPIRY_SECS + 1));
+        let fresh = TestRequest { core: 3, client: client };
+        assert!(sentinel.add_message(fresh, signature, claim).is_none());
+    }
+
+    #[test]
+    fn keys_remembered_once_verified() {
+        reset_random();
+        let mut sentinel: ClientSentinel<TestRequest, u64> = ClientSentinel::new();
+        let client = random::<u64>();
+        let key_pair = seeded_keypair(39);
+        let forger = seeded_keypair(40);
+        let claim = vec![random::<u8>(); 16];
+
+        // A key verifying none of the held messages is not remembered for the client.
+        let request = TestRequest { core: 0, client: client };
+        let forged = sign_claim(&request, &claim, &forger.1);
+        let _ = sentinel.add_message(request.clone(), forged, claim.clone());
+        assert!(sentinel.add_key(request, key_pair.0).is_none());
+        let next_request = TestRequest { core: 1, client: client };
+        match sentinel.add_message(next_request.clone(),
+                                   sign_claim(&next_request, &claim, &key_pair.1),
+                                   claim.clone()) {
+            Some(AddResult::RequestKeys(source)) => assert_eq!(source, client),
+            _ => panic!("expected key request"),
+        }
+
+        // Forged messages filling the held ones don't keep the genuine one from resolving
+        // once it is sent again.
+        let request = TestRequest { core: 2, client: client };
+        let signature = sign_claim(&request, &claim, &key_pair.1);
+        for _ in 0..MAX_MESSAGES_PER_REQUEST {
+            let forged = sign_claim(&request, &claim, &forger.1);
+            let _ = sentinel.add_message(request.clone(), forged, claim.clone());
+        }
+        assert!(sentinel.add_message(request.clone(), signature.clone(), claim.clone())
+                        .is_none());
+        assert!(sentinel.add_key(request.clone(), key_pair.0).is_none());
+        match sentinel.add_message(request.clone(), signature, claim.clone()) {
+            Some(AddResult::Resolved(resolved, _)) => assert_eq!(resolved, request),
+            _ => panic!("expected resolution"),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Time as the sentinels see it.
+//!
+//! Every expiry in the sentinels, from remembered resolutions and buffered keys to pending
+//! requests and outstanding key requests, is measured against a `Clock`. By default that is
+//! the `SystemClock`. A `ManualClock` only moves when advanced, so that tests can place
+//! claims either side of an expiry without sleeping. A sentinel is given a clock with its
+//! `set_clock`, which takes an `Arc` so that the same clock can be kept by the test and
+//! shared by several sentinels.
+
+use std::sync::{Arc, Mutex};
+use time::{Duration, SteadyTime};
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> SteadyTime;
+}
+
+/// The monotonic clock of the system.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SteadyTime {
+        SteadyTime::now()
+    }
+}
+
+/// A clock that stands still until advanced. Clones share the same time, so a test can keep
+/// one and hand another to the sentinels.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<SteadyTime>>,
+}
+
+impl ManualClock {
+    /// Creates a clock standing at the current time of the system.
+    pub fn new() -> ManualClock {
+        ManualClock { now: Arc::new(Mutex::new(SteadyTime::now())) }
+    }
+
+    /// Moves the clock and all its clones forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = match self.now.lock() {
+            Ok(now) => now,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *now = *now + duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SteadyTime {
+        match self.now.lock() {
+            Ok(now) => *now,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+}
+
+/// Returns the clock sentinels use unless given another.
+pub fn system_clock() -> Arc<Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn manual_clock_moves_when_advanced() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+        assert_eq!(shared.now(), start);
+
+        clock.advance(Duration::seconds(30));
+        assert_eq!(shared.now() - start, Duration::seconds(30));
+        assert!(SystemClock.now() - start < Duration::seconds(30));
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! DigestSentinel resolves large claims by voting on their digests.
+//!
+//! Claimants sign the SHA-256 digest of the payload rather than the payload itself. The
+//! digests are what a `PureSentinel` verifies and votes on, while the sentinel keeps a single
+//! copy of the payload per distinct digest. Once a digest resolves, the payload matching it is
+//! returned. Memory and comparison cost then no longer grow with quorum × payload size.
+
+use sodiumoxide::crypto::hash::sha256;
+use std::collections::BTreeMap;
+use limits::{ByteBudget, ClaimLimits, Rejection};
+use pure_sentinel::{AddResult, PureSentinel, Source};
+use signature_scheme::{Ed25519, SignatureScheme};
+use super::SerialisedClaim;
+
+type Map<K, V> = BTreeMap<K, V>;
+
+/// Returns the digest a claimant signs in place of `payload`.
+pub fn digest(payload: &[u8]) -> SerialisedClaim {
+    sha256::hash(payload).0.to_vec()
+}
+
+/// DigestSentinel is templated like `PureSentinel`, which it wraps.
+pub struct DigestSentinel<Request, Name, Scheme = Ed25519>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    sentinel: PureSentinel<Request, Name, Scheme>,
+    //                         +--- Digest       +--- Payload
+    //                         V                 V
+    payloads: Map<Request, Map<SerialisedClaim, SerialisedClaim>>,
+    budget: ByteBudget,
+}
+
+impl<Request, Name, Scheme> DigestSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    /// Creates a sentinel wrapping a new `PureSentinel`.
+    pub fn new() -> DigestSentinel<Request, Name, Scheme> {
+        DigestSentinel {
+            sentinel: PureSentinel::new(),
+            payloads: Map::new(),
+            budget: ByteBudget::new(ClaimLimits::default()),
+        }
+    }
+
+    /// Gives access to the wrapped sentinel, to configure its eviction policy or weights.
+    /// The claims it holds are digests.
+    pub fn pure_sentinel(&mut self) -> &mut PureSentinel<Request, Name, Scheme> {
+        &mut self.sentinel
+    }
+
+    /// Replaces the byte limits on held payloads. Payloads already held are kept even if they
+    /// exceed the new limits.
+    pub fn set_claim_limits(&mut self, claim_limits: ClaimLimits) {
+        self.budget.set_limits(claim_limits);
+    }
+
+    /// This adds a new claim for the provided request, where `signature` was made over
+    /// `digest(&payload)`. Results are as for `PureSentinel::add_claim`, with a resolved
+    /// claim being the payload whose digest a quorum signed. A late claim for a recently
+    /// resolved request is answered with `AddResult::AlreadyResolved` only if its payload
+    /// matches the resolved digest, as only the digest is remembered; otherwise it is dropped.
+    pub fn add_claim(&mut self,
+                     request: Request,
+                     claimant: Name,
+                     signature: Scheme::Signature,
+                     payload: SerialisedClaim,
+                     claim_quorum: usize,
+                     key_quorum: usize)
+                     -> Option<AddResult<Request, Name>> {
+        let digest = digest(&payload);
+
+        let stored = self.payloads.get(&request).map_or(false, |payloads| {
+            payloads.contains_key(&digest)
+        });
+
+        if !stored {
+            let request_bytes = self.held_bytes(&request);
+            if let Err(rejection) = self.check(payload.len(), request_bytes) {
+                return Some(AddResult::Rejected(rejection));
+            }
+        }
+
+        let result = self.sentinel.add_claim(request.clone(), claimant, signature,
+                                             digest.clone(), claim_quorum, key_quorum);
+
+        match result {
+            Some(AddResult::Resolved(request, digest)) => {
+                self.take_payload(request, &digest, Some(payload))
+                    .map(|(request, payload)| AddResult::Resolved(request, payload))
+            }
+            Some(AddResult::AlreadyResolved(request, resolved_digest)) => {
+                if resolved_digest == digest {
+                    Some(AddResult::AlreadyResolved(request, payload))
+                } else {
+                    None
+                }
+            }
+            result => {
+                if !stored && self.sentinel.is_pending(&request) {
+                    self.budget.charge(payload.len());
+                    let _ = self.payloads.entry(request).or_insert_with(Map::new)
+                                .insert(digest, payload);
+                }
+                result
+            }
+        }
+    }
+
+    /// This adds a new set of public signing keys for the provided request, as for
+    /// `PureSentinel::add_keys`. A resolved claim is the payload whose digest a quorum signed.
+    pub fn add_keys(&mut self,
+                    request: Request,
+                    sender: Name,
+                    keys: Vec<(Name, Scheme::PublicKey)>,
+                    key_quorum: usize)
+                    -> Option<(Request, SerialisedClaim)> {
+        match self.sentinel.add_keys(request, sender, keys, key_quorum) {
+            Some((request, digest)) => self.take_payload(request, &digest, None),
+            None => None,
+        }
+    }
+
+    /// Returns the number of payload bytes currently held.
+    pub fn payload_bytes(&self) -> usize {
+        self.budget.total()
+    }
+
+    fn check(&mut self, size: usize, request_bytes: usize) -> Result<(), Rejection> {
+        match self.budget.check(size, request_bytes) {
+            Err(Rejection::TotalBytesExceeded) => {
+                // Requests evicted by the wrapped sentinel still count against the budget
+                // until reclaimed here.
+                self.reclaim();
+                self.budget.check(size, request_bytes)
+            }
+            result => result,
+        }
+    }
+
+    // Bytes held for `request`, forgetting them if the wrapped sentinel has dropped it.
+    fn held_bytes(&mut self, request: &Request) -> usize {
+        if !self.sentinel.is_pending(request) {
+            let _ = self.release(request);
+            return 0;
+        }
+        self.payloads.get(request).map_or(0, |payloads| Self::size(payloads))
+    }
+
+    fn take_payload(&mut self,
+                    request: Request,
+                    resolved: &SerialisedClaim,
+                    fresh: Option<SerialisedClaim>)
+                    -> Option<(Request, SerialisedClaim)> {
+        let stored = self.release(&request).and_then(|mut payloads| payloads.remove(resolved));
+        // Stored payloads are keyed by their digest, but a fresh one has to be hashed. Without
+        // a payload matching the digest nothing resolves.
+        stored.or_else(|| {
+            fresh.and_then(|payload| {
+                if digest(&payload) == *resolved { Some(payload) } else { None }
+            })
+        }).map(|payload| (request, payload))
+    }
+
+    fn release(&mut self, request: &Request) -> Option<Map<SerialisedClaim, SerialisedClaim>> {
+        let payloads = self.payloads.remove(request);
+        if let Some(ref payloads) = payloads {
+            self.budget.release(Self::size(payloads));
+        }
+        payloads
+    }
+
+    fn reclaim(&mut self) {
+        let dropped = self.payloads.keys()
+                                   .filter(|request| !self.sentinel.is_pending(request))
+                                   .cloned()
+                                   .collect::<Vec<_>>();
+        for request in dropped {
+            let _ = self.release(&request);
+        }
+    }
+
+    fn size(payloads: &Map<SerialisedClaim, SerialisedClaim>) -> usize {
+        payloads.values().fold(0, |total, payload| total + payload.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::{random, random_keypair, reset_random, sign_claim};
+    use pure_sentinel::{AddResult, Source};
+
+    const QUORUM: usize = 8;
+    const PAYLOAD_SIZE: usize = 4096;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        name: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.name
+        }
+    }
+
+    fn random_payload() -> Vec<u8> {
+        (0..PAYLOAD_SIZE).map(|_| random::<u8>()).collect()
+    }
+
+    #[test]
+    fn payload_held_once_per_digest() {
+        reset_random();
+        let mut sentinel: DigestSentinel<TestRequest, u64> = DigestSentinel::new();
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let payload = random_payload();
+        let other_payload = random_payload();
+        let mut keys = Vec::new();
+
+        for index in 0..QUORUM + 1 {
+            let key_pair = random_keypair();
+            let claimant = index as u64;
+            // One claimant disagrees on the payload.
+            let claimed = if index == QUORUM { other_payload.clone() } else { payload.clone() };
+            let signature = sign_claim(&request, &digest(&claimed), &key_pair.1);
+            keys.push((claimant, key_pair.0));
+
+            match sentinel.add_claim(request.clone(), claimant, signature, claimed,
+                                     QUORUM, 1) {
+                Some(AddResult::RequestKeys(_)) => assert_eq!(index, 0),
+                None => assert!(index > 0),
+                _ => panic!("unexpected result"),
+            }
+        }
+
+        assert_eq!(sentinel.payload_bytes(), 2 * PAYLOAD_SIZE);
+
+        assert!(sentinel.add_keys(request.clone(), QUORUM as u64 + 1, keys, 1)
+            .and_then(|result| { assert_eq!(result.0, request);
+                                 assert!(result.1 == payload);
+                                 Some(result)
+            }).is_some());
+        assert_eq!(sentinel.payload_bytes(), 0);
+    }
+
+    #[test]
+    fn oversized_payload_rejected() {
+        reset_random();
+        let mut sentinel: DigestSentinel<TestRequest, u64> = DigestSentinel::new();
+        sentinel.set_claim_limits(ClaimLimits { max_claim_size: PAYLOAD_SIZE - 1,
+                                                max_request_bytes: PAYLOAD_SIZE,
+                                                max_total_bytes: PAYLOAD_SIZE });
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let payload = random_payload();
+        let key_pair = random_keypair();
+        let signature = sign_claim(&request, &digest(&payload), &key_pair.1);
+
+        match sentinel.add_claim(request, 0, signature, payload, QUORUM, 1) {
+            Some(AddResult::Rejected(Rejection::ClaimTooLarge)) => (),
+            _ => panic!("expected rejection"),
+        }
+        assert_eq!(sentinel.payload_bytes(), 0);
+    }
+
+    #[test]
+    fn fresh_payload_checked_against_digest() {
+        reset_random();
+        let mut sentinel: DigestSentinel<TestRequest, u64> = DigestSentinel::new();
+        let request = TestRequest { core: random::<usize>(), name: random::<u64>() };
+        let payload = random_payload();
+        let resolved = digest(&payload);
+
+        assert!(sentinel.take_payload(request.clone(), &resolved, Some(random_payload()))
+                        .is_none());
+        assert_eq!(sentinel.take_payload(request.clone(), &resolved, Some(payload.clone())),
+                   Some((request, payload)));
+    }
+}
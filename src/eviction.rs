@@ -0,0 +1,126 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Eviction of pending requests that are least likely to resolve.
+//!
+//! A pending request is weighed by its verified progress toward quorum (verified claims /
+//! quorum) and then by the number of distinct claimants it has heard from. A claim counts as
+//! verified once it verifies against a confirmed key of its claimant, which the sentinel
+//! checks as each claim arrives, so made-up claimants add nothing. When a sentinel is full,
+//! the weakest pending request is evicted, with the least recently active one going first
+//! among equals. This keeps a flood of junk requests, however many claimants each names,
+//! from pushing out a request whose claimants have known keys and that is only a claim or
+//! two short of resolving. Before any keys of a group are known, its requests can only be
+//! told apart from junk by their claimants.
+
+use std::cmp::{self, Ordering};
+
+/// Default maximum number of requests a sentinel keeps pending.
+pub const DEFAULT_MAX_PENDING: usize = 1000;
+
+/// Limits applied to the pending requests of a sentinel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EvictionPolicy {
+    /// Maximum number of pending requests. Once reached, the weakest pending request is
+    /// evicted to make room for a new one.
+    pub max_pending: usize,
+    /// Maximum number of pending requests a single source name can hold. Claims for further
+    /// requests from that source are dropped until one of its requests resolves or is evicted.
+    pub max_pending_per_source: usize,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> EvictionPolicy {
+        EvictionPolicy {
+            max_pending: DEFAULT_MAX_PENDING,
+            max_pending_per_source: DEFAULT_MAX_PENDING,
+        }
+    }
+}
+
+/// Progress a pending request has made toward resolution.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Weight of the claimants whose claim verified against a confirmed key.
+    pub verified: usize,
+    /// Number of distinct claimants that sent a claim.
+    pub claimants: usize,
+    /// Number of verified claims needed to resolve.
+    pub quorum: usize,
+}
+
+impl Ord for Progress {
+    fn cmp(&self, other: &Progress) -> Ordering {
+        // Compare verified / quorum of both sides without dividing.
+        let lhs = self.verified * cmp::max(other.quorum, 1);
+        let rhs = other.verified * cmp::max(self.quorum, 1);
+        match lhs.cmp(&rhs) {
+            Ordering::Equal => self.claimants.cmp(&other.claimants),
+            ordering => ordering,
+        }
+    }
+}
+
+impl PartialOrd for Progress {
+    fn partial_cmp(&self, other: &Progress) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Progress {
+    fn eq(&self, other: &Progress) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Progress { }
+
+/// Picks the candidate to evict: the one with the least progress, and among those the one
+/// with the lowest `last_seen` stamp.
+pub fn weakest<Key, Candidates>(candidates: Candidates) -> Option<Key>
+    where Candidates: Iterator<Item = (Key, Progress, u64)>
+{
+    candidates.min_by_key(|&(_, progress, last_seen)| (progress, last_seen))
+              .map(|(key, _, _)| key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn progress(verified: usize, claimants: usize, quorum: usize) -> Progress {
+        Progress { verified: verified, claimants: claimants, quorum: quorum }
+    }
+
+    #[test]
+    fn progress_ordering() {
+        assert!(progress(1, 1, 2) > progress(4, 10, 10));
+        assert!(progress(2, 3, 4) == progress(5, 3, 10));
+        assert!(progress(2, 4, 4) > progress(5, 3, 10));
+        assert!(progress(0, 2, 4) > progress(0, 1, 1));
+    }
+
+    #[test]
+    fn weakest_prefers_least_progress_then_oldest() {
+        let candidates = vec![(0u8, progress(3, 3, 4), 0u64),
+                              (1u8, progress(0, 1, 4), 5u64),
+                              (2u8, progress(0, 1, 4), 2u64),
+                              (3u8, progress(0, 2, 4), 1u64)];
+        assert_eq!(weakest(candidates.into_iter()), Some(2u8));
+        assert_eq!(weakest(Vec::<(u8, Progress, u64)>::new().into_iter()), None);
+    }
+}
@@ -0,0 +1,663 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Evidence of sentinel decisions that can be verified offline.
+//!
+//! Artefacts hold names as the bytes the application serialises them to, requests as the
+//! bytes `serialise` encodes them to, and Ed25519 keys and signatures, so that they can be
+//! checked without the types of the application, as the `sentinel_verify` binary does. Key
+//! vouchers are accumulated with the same `KeyStore` rules as in the sentinels, and
+//! signatures checked over the same bytes: claims bound to their request, as `bound_claim`
+//! encodes them.
+//!
+//! Each key voucher is signed by its sender, and only vouchers signed by a key the operator
+//! trusts for the sender, as listed in `TrustedKeys`, count toward confirming a key. Without
+//! that anchor anyone could assemble vouchers confirming keys of their own making. The quorums
+//! come from the operator as well: an artefact setting a lower quorum than `TrustedKeys`
+//! requires fails, and one setting a higher quorum is held to it.
+//!
+//! The sentinels don't assemble artefacts themselves. An application builds a
+//! `ResolutionCertificate` from the claims and keys it passed to `PureSentinel` for a
+//! resolved request, and an `EquivocationProof` from a `Conflict` together with the other
+//! claim the same claimant signed. As signatures are bound to their request, signatures
+//! collected for one request can't be passed off as a certificate or an equivocation for
+//! another. Recorded traces are not artefacts: they are
+//! decoded with the types of the sentinel recorded, and checked with its `replay`.
+
+use std::cmp;
+use std::fmt;
+use rustc_serialize::hex::FromHex;
+use sodiumoxide::crypto::sign;
+use key_store::KeyStore;
+use signature_scheme::Ed25519;
+use statistics::Weights;
+use super::{SerialisedClaim, bound_claim, serialise, verify_signature};
+
+/// Serialised name, request or claim.
+pub type Bytes = Vec<u8>;
+
+/// Keys sent by one sender, as passed to `PureSentinel::add_keys`, signed by the sender.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct KeyVoucher {
+    /// Name of the sender.
+    pub sender: Bytes,
+    /// Names with the public keys the sender vouches for.
+    pub keys: Vec<(Bytes, sign::PublicKey)>,
+    /// Signature of the sender over the bytes `KeyVoucher::signed_bytes` returns.
+    pub signature: sign::Signature,
+}
+
+/// Public keys of the senders whose vouchers the operator trusts, with the least quorums the
+/// operator accepts.
+#[derive(Clone)]
+pub struct TrustedKeys {
+    keys: Vec<(Bytes, sign::PublicKey)>,
+    claim_quorum: usize,
+    key_quorum: usize,
+}
+
+/// A line of trusted keys that failed to parse.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ParseError {
+    /// Number of the line, from one.
+    pub line: usize,
+    /// What is wrong with it.
+    pub reason: &'static str,
+}
+
+/// Key vouchers for a group, with the quorum of senders each key needs.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct KeyVoucherSet {
+    /// Number of distinct senders, other than the key holder, that have to vouch for a key.
+    pub key_quorum: usize,
+    /// The vouchers.
+    pub vouchers: Vec<KeyVoucher>,
+}
+
+/// Signatures of a quorum of claimants over the claim a request resolved to, with the key
+/// vouchers their keys were accepted on.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct ResolutionCertificate {
+    /// The request resolved, as `serialise` encodes it.
+    pub request: Bytes,
+    /// The claim it resolved to.
+    pub claim: SerialisedClaim,
+    /// Number of distinct claimants whose signature has to verify.
+    pub claim_quorum: usize,
+    /// Claimants with their signature over `claim` bound to `request`.
+    pub signatures: Vec<(Bytes, sign::Signature)>,
+    /// Vouchers for the keys of the claimants.
+    pub keys: KeyVoucherSet,
+}
+
+/// Two different claims signed by the same claimant for the same request.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct EquivocationProof {
+    /// The request claimed, as `serialise` encodes it.
+    pub request: Bytes,
+    /// The claimant that signed both claims.
+    pub claimant: Bytes,
+    /// Public key of the claimant.
+    pub public_key: sign::PublicKey,
+    /// Vouchers confirming `public_key` as a key of the claimant.
+    pub keys: KeyVoucherSet,
+    /// The first claim with the signature over it bound to the request.
+    pub first: (SerialisedClaim, sign::Signature),
+    /// The second claim with the signature over it bound to the request.
+    pub second: (SerialisedClaim, sign::Signature),
+}
+
+/// An artefact, as written to the files `sentinel_verify` reads.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub enum Artefact {
+    /// A resolution certificate.
+    Resolution(ResolutionCertificate),
+    /// An equivocation proof.
+    Equivocation(EquivocationProof),
+    /// A set of key vouchers.
+    KeyVouchers(KeyVoucherSet),
+}
+
+/// Reason an artefact failed to verify.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Failure {
+    /// No key of the name reached the key quorum.
+    KeyNotConfirmed(Bytes),
+    /// The signature of the name verified against none of its confirmed keys.
+    BadSignature(Bytes),
+    /// Fewer distinct claimants verified than the claim quorum.
+    QuorumNotReached {
+        /// Claimants whose signature verified.
+        verified: usize,
+        /// The claim quorum.
+        quorum: usize,
+    },
+    /// The artefact sets a claim quorum lower than the operator requires.
+    ClaimQuorumTooLow {
+        /// The claim quorum of the artefact.
+        quorum: usize,
+        /// The claim quorum the operator requires.
+        minimum: usize,
+    },
+    /// The artefact sets a key quorum lower than the operator requires.
+    KeyQuorumTooLow {
+        /// The key quorum of the artefact.
+        quorum: usize,
+        /// The key quorum the operator requires.
+        minimum: usize,
+    },
+    /// The two claims of an equivocation proof are the same.
+    SameClaims,
+    /// The voucher of the name is not signed by a key trusted for it.
+    UntrustedVoucher(Bytes),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::KeyNotConfirmed(ref name) =>
+                write!(formatter, "no key of {} reached the key quorum", hex(name)),
+            Failure::BadSignature(ref name) =>
+                write!(formatter, "signature of {} does not verify", hex(name)),
+            Failure::QuorumNotReached { verified, quorum } =>
+                write!(formatter, "{} of a quorum of {} claimants verified", verified, quorum),
+            Failure::ClaimQuorumTooLow { quorum, minimum } =>
+                write!(formatter, "claim quorum of {} is below the required {}", quorum, minimum),
+            Failure::KeyQuorumTooLow { quorum, minimum } =>
+                write!(formatter, "key quorum of {} is below the required {}", quorum, minimum),
+            Failure::SameClaims => write!(formatter, "both claims are the same"),
+            Failure::UntrustedVoucher(ref name) =>
+                write!(formatter, "voucher of {} is not signed by a trusted key", hex(name)),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl KeyVoucher {
+    /// Creates the voucher of `sender` for `keys`, signed with `secret_key`.
+    pub fn new(sender: Bytes,
+               keys: Vec<(Bytes, sign::PublicKey)>,
+               secret_key: &sign::SecretKey)
+               -> KeyVoucher {
+        let signature = sign::sign_detached(&KeyVoucher::signed_bytes(&sender, &keys),
+                                            secret_key);
+        KeyVoucher { sender: sender, keys: keys, signature: signature }
+    }
+
+    /// Returns the bytes the sender signs: the CBOR encoding of the sender with its keys.
+    pub fn signed_bytes(sender: &Bytes, keys: &Vec<(Bytes, sign::PublicKey)>) -> Bytes {
+        serialise(&(sender, keys))
+    }
+}
+
+impl TrustedKeys {
+    /// Trusts each sender of `keys` with the public keys listed for it, and requires artefacts
+    /// to set at least `claim_quorum` and `key_quorum`.
+    pub fn new(keys: Vec<(Bytes, sign::PublicKey)>,
+               claim_quorum: usize,
+               key_quorum: usize)
+               -> TrustedKeys {
+        TrustedKeys { keys: keys, claim_quorum: claim_quorum, key_quorum: key_quorum }
+    }
+
+    /// Parses lines of a name and a public key in hexadecimal, separated by whitespace.
+    /// Blank lines and lines starting with `#` are skipped. The quorums are as for `new`.
+    pub fn parse(text: &str,
+                 claim_quorum: usize,
+                 key_quorum: usize)
+                 -> Result<TrustedKeys, ParseError> {
+        let mut keys = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+            let error = |reason| ParseError { line: index + 1, reason: reason };
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() != 2 {
+                return Err(error("expected a name and a public key"));
+            }
+            let name = try!(fields[0].from_hex().map_err(|_| error("name is not hexadecimal")));
+            let key_bytes = try!(fields[1].from_hex()
+                                          .map_err(|_| error("key is not hexadecimal")));
+            if key_bytes.len() != sign::PUBLICKEYBYTES {
+                return Err(error("key is not an Ed25519 public key"));
+            }
+            let mut public_key = [0u8; sign::PUBLICKEYBYTES];
+            for (byte, key_byte) in public_key.iter_mut().zip(key_bytes) {
+                *byte = key_byte;
+            }
+            keys.push((name, sign::PublicKey(public_key)));
+        }
+        Ok(TrustedKeys::new(keys, claim_quorum, key_quorum))
+    }
+
+    /// Tells whether `voucher` is signed by a key trusted for its sender.
+    pub fn vouches(&self, voucher: &KeyVoucher) -> bool {
+        let signed = KeyVoucher::signed_bytes(&voucher.sender, &voucher.keys);
+        self.keys.iter()
+                 .filter(|&&(ref name, _)| *name == voucher.sender)
+                 .any(|&(_, ref public_key)| {
+                     verify_signature::<Ed25519>(&voucher.signature, public_key, &signed)
+                         .is_some()
+                 })
+    }
+}
+
+impl KeyVoucherSet {
+    /// Returns the names each sender vouched for, with their keys that reached the quorum
+    /// of trusted vouchers.
+    pub fn confirmed_keys(&self, trusted: &TrustedKeys) -> Vec<(Bytes, Vec<sign::PublicKey>)> {
+        let mut key_store = self.key_store(trusted);
+        let quorum = self.key_quorum(trusted);
+        let mut targets = self.vouchers.iter()
+                                       .flat_map(|voucher| voucher.keys.iter())
+                                       .map(|&(ref target, _)| target.clone())
+                                       .collect::<Vec<_>>();
+        targets.sort();
+        targets.dedup();
+        targets.into_iter().map(|target| {
+            let keys = key_store.get_weighted_keys(&target, &Weights::new(), quorum);
+            (target, keys)
+        }).collect()
+    }
+
+    /// Returns whether the key quorum is below the one `trusted` requires, the senders of
+    /// vouchers not signed by a trusted key, and the names vouched for without a key reaching
+    /// the quorum of trusted vouchers.
+    pub fn verify(&self, trusted: &TrustedKeys) -> Vec<Failure> {
+        let too_low = self.quorum_failure(trusted);
+        let untrusted = self.vouchers.iter()
+                                     .filter(|voucher| !trusted.vouches(voucher))
+                                     .map(|voucher| Failure::UntrustedVoucher(voucher.sender
+                                                                                     .clone()));
+        let unconfirmed = self.confirmed_keys(trusted).into_iter()
+                              .filter(|&(_, ref keys)| keys.is_empty())
+                              .map(|(target, _)| Failure::KeyNotConfirmed(target));
+        too_low.into_iter().chain(untrusted).chain(unconfirmed).collect()
+    }
+
+    // The key quorum of the set, raised to the one `trusted` requires.
+    fn key_quorum(&self, trusted: &TrustedKeys) -> usize {
+        cmp::max(self.key_quorum, trusted.key_quorum)
+    }
+
+    fn quorum_failure(&self, trusted: &TrustedKeys) -> Option<Failure> {
+        if self.key_quorum < trusted.key_quorum {
+            Some(Failure::KeyQuorumTooLow { quorum: self.key_quorum, minimum: trusted.key_quorum })
+        } else {
+            None
+        }
+    }
+
+    // Accumulates the trusted vouchers only.
+    fn key_store(&self, trusted: &TrustedKeys) -> KeyStore<Bytes, Ed25519> {
+        let mut key_store = KeyStore::new();
+        for voucher in self.vouchers.iter().filter(|voucher| trusted.vouches(voucher)) {
+            for &(ref target, ref public_key) in voucher.keys.iter() {
+                key_store.add_key(target.clone(), voucher.sender.clone(), public_key.clone());
+            }
+        }
+        key_store
+    }
+}
+
+impl ResolutionCertificate {
+    /// Returns why the certificate fails to show that a quorum of claimants with keys
+    /// confirmed by trusted vouchers signed the claim, or nothing if it does.
+    pub fn verify(&self, trusted: &TrustedKeys) -> Vec<Failure> {
+        let mut key_store = self.keys.key_store(trusted);
+        let key_quorum = self.keys.key_quorum(trusted);
+        let claim_quorum = cmp::max(self.claim_quorum, trusted.claim_quorum);
+        let mut failures = Vec::new();
+        let mut verified = Vec::new();
+        let bound = bound_claim(&self.request, &self.claim);
+
+        if self.claim_quorum < trusted.claim_quorum {
+            failures.push(Failure::ClaimQuorumTooLow {
+                quorum: self.claim_quorum,
+                minimum: trusted.claim_quorum,
+            });
+        }
+        failures.extend(self.keys.quorum_failure(trusted));
+
+        for &(ref claimant, ref signature) in self.signatures.iter() {
+            let keys = key_store.get_weighted_keys(claimant, &Weights::new(), key_quorum);
+            if keys.is_empty() {
+                failures.push(Failure::KeyNotConfirmed(claimant.clone()));
+            } else if keys.iter().any(|public_key| {
+                verify_signature::<Ed25519>(signature, public_key, &bound).is_some()
+            }) {
+                verified.push(claimant.clone());
+            } else {
+                failures.push(Failure::BadSignature(claimant.clone()));
+            }
+        }
+
+        // Each claimant counts once, however many signatures it appears with.
+        verified.sort();
+        verified.dedup();
+        if verified.len() < claim_quorum {
+            failures.push(Failure::QuorumNotReached {
+                verified: verified.len(),
+                quorum: claim_quorum,
+            });
+        }
+        failures
+    }
+}
+
+impl EquivocationProof {
+    /// Returns why the proof fails to show that the claimant, under a key confirmed by
+    /// trusted vouchers, signed two different claims for the request, or nothing if it does.
+    pub fn verify(&self, trusted: &TrustedKeys) -> Vec<Failure> {
+        let mut failures = self.keys.quorum_failure(trusted).into_iter().collect::<Vec<_>>();
+        let confirmed = self.keys.key_store(trusted)
+                                 .get_weighted_keys(&self.claimant, &Weights::new(),
+                                                    self.keys.key_quorum(trusted));
+        if !confirmed.contains(&self.public_key) {
+            failures.push(Failure::KeyNotConfirmed(self.claimant.clone()));
+        }
+        if self.first.0 == self.second.0 {
+            failures.push(Failure::SameClaims);
+        }
+        for &(ref claim, ref signature) in [&self.first, &self.second].iter() {
+            let bound = bound_claim(&self.request, claim);
+            if verify_signature::<Ed25519>(signature, &self.public_key, &bound).is_none() {
+                failures.push(Failure::BadSignature(self.claimant.clone()));
+            }
+        }
+        failures
+    }
+}
+
+impl Artefact {
+    /// Returns why the artefact fails to verify against `trusted`, or nothing if it
+    /// verifies.
+    pub fn verify(&self, trusted: &TrustedKeys) -> Vec<Failure> {
+        match *self {
+            Artefact::Resolution(ref certificate) => certificate.verify(trusted),
+            Artefact::Equivocation(ref proof) => proof.verify(trusted),
+            Artefact::KeyVouchers(ref voucher_set) => voucher_set.verify(trusted),
+        }
+    }
+
+    /// Returns a one-line description of the artefact.
+    pub fn describe(&self) -> String {
+        match *self {
+            Artefact::Resolution(ref certificate) =>
+                format!("resolution of request {} by {} signatures", hex(&certificate.request),
+                        certificate.signatures.len()),
+            Artefact::Equivocation(ref proof) =>
+                format!("equivocation of {} on request {}", hex(&proof.claimant),
+                        hex(&proof.request)),
+            Artefact::KeyVouchers(ref voucher_set) =>
+                format!("{} key vouchers", voucher_set.vouchers.len()),
+        }
+    }
+}
+
+/// Returns `bytes` in hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto::sign;
+    use pure_sentinel::PureSentinel;
+    use testing::{random_claim, seeded_keypair, seeded_rng, SignedGroup, TestRequest};
+
+    const KEY_QUORUM: usize = 2;
+
+    fn request(id: u64) -> TestRequest<u64> {
+        TestRequest { id: id, source: 1000 }
+    }
+
+    fn name(index: u64) -> Bytes {
+        vec![index as u8]
+    }
+
+    // Vouchers of senders 100 onwards for the keys of `group`, with the keys they are
+    // trusted under.
+    fn vouchers(group: &SignedGroup<u64>) -> (KeyVoucherSet, TrustedKeys) {
+        vouchers_requiring(group, group.len() - 1)
+    }
+
+    // As `vouchers`, with trusted keys requiring a claim quorum of `claim_quorum`.
+    fn vouchers_requiring(group: &SignedGroup<u64>,
+                          claim_quorum: usize)
+                          -> (KeyVoucherSet, TrustedKeys) {
+        let keys = group.keys().into_iter().map(|(index, key)| (name(index), key))
+                        .collect::<Vec<_>>();
+        let senders = (0..KEY_QUORUM as u32).map(|index| (name(100 + index as u64),
+                                                         seeded_keypair(100 + index)))
+                                             .collect::<Vec<_>>();
+        let voucher_set = KeyVoucherSet {
+            key_quorum: KEY_QUORUM,
+            vouchers: senders.iter().map(|&(ref sender, ref key_pair)| {
+                KeyVoucher::new(sender.clone(), keys.clone(), &key_pair.1)
+            }).collect(),
+        };
+        let trusted = TrustedKeys::new(senders.into_iter()
+                                              .map(|(sender, key_pair)| (sender, key_pair.0))
+                                              .collect(),
+                                       claim_quorum,
+                                       KEY_QUORUM);
+        (voucher_set, trusted)
+    }
+
+    fn certificate(group: &SignedGroup<u64>, claim: &SerialisedClaim) -> ResolutionCertificate {
+        ResolutionCertificate {
+            request: serialise(&request(7)),
+            claim: claim.clone(),
+            claim_quorum: group.len() - 1,
+            signatures: group.sign_claims(&request(7), claim).into_iter()
+                             .map(|signed| (name(signed.claimant), signed.signature))
+                             .collect(),
+            keys: vouchers(group).0,
+        }
+    }
+
+    #[test]
+    fn resolution_certificate_verified() {
+        let mut rng = seeded_rng(1);
+        let group = SignedGroup::from_rng((0..5).collect(), &mut rng);
+        let claim = random_claim(16, &mut rng);
+        let trusted = vouchers(&group).1;
+
+        let valid = certificate(&group, &claim);
+        assert!(valid.verify(&trusted).is_empty());
+        assert!(valid.keys.confirmed_keys(&trusted).iter().all(|&(_, ref keys)| keys.len() == 1));
+
+        // A forged signature fails on its own, and with it the quorum.
+        let mut forged = valid.clone();
+        forged.signatures[0].1 = forged.signatures[1].1.clone();
+        forged.signatures[2].1 = forged.signatures[1].1.clone();
+        assert_eq!(forged.verify(&trusted),
+                   vec![Failure::BadSignature(name(0)),
+                        Failure::BadSignature(name(2)),
+                        Failure::QuorumNotReached { verified: 3, quorum: 4 }]);
+
+        // Keys vouched for by too few senders are not confirmed.
+        let mut unvouched = valid.clone();
+        let _ = unvouched.keys.vouchers.pop();
+        assert_eq!(unvouched.verify(&trusted).len(), group.len() + 1);
+        assert_eq!(Artefact::KeyVouchers(unvouched.keys).verify(&trusted).len(), group.len());
+
+        // Repeated signatures of one claimant count once.
+        let mut repeated = valid;
+        let first = repeated.signatures[0].clone();
+        repeated.signatures = vec![first; 5];
+        assert_eq!(repeated.verify(&trusted),
+                   vec![Failure::QuorumNotReached { verified: 1, quorum: 4 }]);
+    }
+
+    #[test]
+    fn vouchers_anchored_to_trusted_keys() {
+        let mut rng = seeded_rng(3);
+        let group = SignedGroup::from_rng((0..5).collect(), &mut rng);
+        let claim = random_claim(16, &mut rng);
+        let (voucher_set, trusted) = vouchers(&group);
+        let valid = certificate(&group, &claim);
+        assert!(Artefact::KeyVouchers(voucher_set.clone()).verify(&trusted).is_empty());
+
+        // Without trusted keys no voucher counts, and no key is confirmed.
+        let untrusting = TrustedKeys::new(Vec::new(), group.len() - 1, KEY_QUORUM);
+        assert_eq!(voucher_set.verify(&untrusting).len(), KEY_QUORUM + group.len());
+        assert_eq!(valid.verify(&untrusting).len(), group.len() + 1);
+
+        // A voucher whose keys were changed after signing doesn't count.
+        let mut altered = voucher_set.clone();
+        altered.vouchers[0].keys[0].1 = seeded_keypair(99).0;
+        let failures = altered.verify(&trusted);
+        assert_eq!(failures[0], Failure::UntrustedVoucher(name(100)));
+
+        // Nor does one signed by a key trusted for another sender.
+        let mut impostor = voucher_set;
+        impostor.vouchers[0] = KeyVoucher::new(name(100), impostor.vouchers[0].keys.clone(),
+                                               &seeded_keypair(101).1);
+        assert_eq!(impostor.verify(&trusted)[0], Failure::UntrustedVoucher(name(100)));
+    }
+
+    #[test]
+    fn trusted_keys_parsed() {
+        let key = seeded_keypair(100).0;
+        let text = format!("# Senders\n\n64 {}\n", hex(&key.0));
+        let trusted = TrustedKeys::parse(&text, 3, 2).unwrap();
+        assert_eq!(trusted.keys, vec![(vec![0x64], key)]);
+        assert_eq!((trusted.claim_quorum, trusted.key_quorum), (3, 2));
+
+        assert_eq!(TrustedKeys::parse("64", 3, 2).err().map(|error| error.line), Some(1));
+        assert_eq!(TrustedKeys::parse("\n64 zz", 3, 2).err().map(|error| error.line), Some(2));
+        assert!(TrustedKeys::parse("64 0102", 3, 2).is_err());
+    }
+
+    #[test]
+    fn quorums_set_by_operator() {
+        let mut rng = seeded_rng(5);
+        let group = SignedGroup::from_rng((0..5).collect(), &mut rng);
+        let claim = random_claim(16, &mut rng);
+        let (voucher_set, trusted) = vouchers(&group);
+
+        // Quorums lowered in the artefact fail, and the signatures are still held to the
+        // quorums of the operator.
+        let mut lowered = certificate(&group, &claim);
+        lowered.claim_quorum = 1;
+        lowered.keys.key_quorum = 1;
+        lowered.signatures.truncate(1);
+        let _ = lowered.keys.vouchers.pop();
+        assert_eq!(lowered.verify(&trusted),
+                   vec![Failure::ClaimQuorumTooLow { quorum: 1, minimum: 4 },
+                        Failure::KeyQuorumTooLow { quorum: 1, minimum: KEY_QUORUM },
+                        Failure::KeyNotConfirmed(name(0)),
+                        Failure::QuorumNotReached { verified: 0, quorum: 4 }]);
+        let mut lowered_keys = voucher_set;
+        lowered_keys.key_quorum = 1;
+        assert_eq!(lowered_keys.verify(&trusted),
+                   vec![Failure::KeyQuorumTooLow { quorum: 1, minimum: KEY_QUORUM }]);
+
+        // A quorum higher than the operator requires holds the artefact to it.
+        let lenient = vouchers_requiring(&group, 1).1;
+        let mut raised = certificate(&group, &claim);
+        raised.claim_quorum = group.len() + 1;
+        assert_eq!(raised.verify(&lenient),
+                   vec![Failure::QuorumNotReached { verified: 5, quorum: 6 }]);
+    }
+
+    #[test]
+    fn equivocation_proof_verified() {
+        let mut rng = seeded_rng(2);
+        let group = SignedGroup::from_rng(vec![0u64], &mut rng);
+        let (voucher_set, trusted) = vouchers(&group);
+        let claims = (0..2).map(|_| random_claim(16, &mut rng)).collect::<Vec<_>>();
+        let signed = claims.iter().map(|claim| {
+            group.sign_claims(&request(7), claim).remove(0)
+        }).collect::<Vec<_>>();
+        let proof = EquivocationProof {
+            request: serialise(&request(7)),
+            claimant: name(0),
+            public_key: group.keys()[0].1.clone(),
+            keys: voucher_set,
+            first: (claims[0].clone(), signed[0].signature.clone()),
+            second: (claims[1].clone(), signed[1].signature.clone()),
+        };
+        assert!(Artefact::Equivocation(proof.clone()).verify(&trusted).is_empty());
+
+        let mut same = proof.clone();
+        same.second = same.first.clone();
+        assert_eq!(same.verify(&trusted), vec![Failure::SameClaims]);
+
+        let mut swapped = proof.clone();
+        swapped.second.1 = swapped.first.1.clone();
+        assert_eq!(swapped.verify(&trusted), vec![Failure::BadSignature(name(0))]);
+
+        // Claims signed for another request are no proof for this one.
+        let mut other_request = proof.clone();
+        other_request.request = serialise(&request(8));
+        assert_eq!(other_request.verify(&trusted), vec![Failure::BadSignature(name(0)),
+                                                        Failure::BadSignature(name(0))]);
+
+        // Nor are claims signed under a key nobody trusted vouched for.
+        let mut unvouched = proof;
+        let forger = seeded_keypair(99);
+        unvouched.public_key = forger.0;
+        unvouched.first.1 = sign::sign_detached(&bound_claim(&unvouched.request,
+                                                             &unvouched.first.0),
+                                                &forger.1);
+        unvouched.second.1 = sign::sign_detached(&bound_claim(&unvouched.request,
+                                                              &unvouched.second.0),
+                                                 &forger.1);
+        assert_eq!(unvouched.verify(&trusted), vec![Failure::KeyNotConfirmed(name(0))]);
+    }
+
+    #[test]
+    fn conflicts_make_equivocation_proofs() {
+        let mut rng = seeded_rng(4);
+        let group = SignedGroup::from_rng((0..4).collect(), &mut rng);
+        let (voucher_set, trusted) = vouchers(&group);
+        let honest = random_claim(16, &mut rng);
+        let forged = random_claim(16, &mut rng);
+        let honest_claims = group.sign_claims(&request(7), &honest);
+        let forged_claim = group.sign_claims(&request(7), &forged).remove(0);
+
+        // The first member signs the forged claim ahead of the honest one.
+        let mut sentinel: PureSentinel<TestRequest<u64>, u64> = PureSentinel::new();
+        for signed in Some(forged_claim).into_iter().chain(honest_claims.iter().cloned()) {
+            let _ = sentinel.add_claim(request(7), signed.claimant, signed.signature,
+                                       signed.claim, 3, 1);
+        }
+        assert!(sentinel.add_keys(request(7), 2000, group.keys(), 1).is_some());
+
+        let conflict = sentinel.take_conflicts().remove(0);
+        let proof = EquivocationProof {
+            request: serialise(&conflict.request),
+            claimant: name(conflict.claimant),
+            public_key: group.keys()[0].1,
+            keys: voucher_set,
+            first: (conflict.claim, conflict.signature),
+            second: (honest, honest_claims[0].signature),
+        };
+        assert!(proof.verify(&trusted).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use lru_time_cache::LruCache;
+use time::{Duration, SteadyTime};
+
+// An LRU cache whose entries expire a fixed duration after they were added, measured with the
+// time passed in by the caller rather than the wall clock `lru_time_cache` reads. Expired
+// entries are treated as absent and removed when next looked up; until then they count
+// towards the capacity like any other entry.
+pub struct ExpiringCache<K, V> where K: PartialOrd + Ord + Clone {
+    cache: LruCache<K, (SteadyTime, V)>,
+    expiry: Duration,
+}
+
+impl<K, V> ExpiringCache<K, V> where K: PartialOrd + Ord + Clone {
+    pub fn new(expiry: Duration, capacity: usize) -> ExpiringCache<K, V> {
+        ExpiringCache {
+            cache: LruCache::with_capacity(capacity),
+            expiry: expiry,
+        }
+    }
+
+    pub fn add(&mut self, key: K, value: V, now: SteadyTime) {
+        self.cache.add(key, (now, value));
+    }
+
+    pub fn get(&mut self, key: &K, now: SteadyTime) -> Option<&V> {
+        self.get_mut(key, now).map(|value| &*value)
+    }
+
+    pub fn get_mut(&mut self, key: &K, now: SteadyTime) -> Option<&mut V> {
+        let expired = match self.cache.get(key) {
+            Some(&(added, _)) => now - added > self.expiry,
+            None => return None,
+        };
+        if expired {
+            let _ = self.cache.remove(key);
+            return None;
+        }
+        self.cache.get_mut(key).map(|&mut (_, ref mut value)| value)
+    }
+
+    pub fn check(&mut self, key: &K, now: SteadyTime) -> bool {
+        self.get(key, now).is_some()
+    }
+
+    // Returns the value of `key`, first adding the one made by `make` if there is none.
+    pub fn get_or_add_with<F>(&mut self, key: K, now: SteadyTime, make: F) -> &mut V
+        where F: FnOnce() -> V
+    {
+        if !self.check(&key, now) {
+            self.add(key.clone(), make(), now);
+        }
+        self.get_mut(&key, now).expect("Entry added above")
+    }
+
+    pub fn remove(&mut self, key: &K, now: SteadyTime) -> Option<V> {
+        match self.cache.remove(key) {
+            Some((added, value)) => if now - added > self.expiry { None } else { Some(value) },
+            None => None,
+        }
+    }
+
+    pub fn forget(&mut self, key: &K) {
+        let _ = self.cache.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::{Duration, SteadyTime};
+
+    #[test]
+    fn entries_expire_with_the_time_given() {
+        let start = SteadyTime::now();
+        let mut cache = ExpiringCache::new(Duration::seconds(30), 2);
+        cache.add(1, "one", start);
+
+        assert_eq!(cache.get(&1, start + Duration::seconds(29)), Some(&"one"));
+        assert!(cache.check(&1, start + Duration::seconds(30)));
+        assert!(!cache.check(&1, start + Duration::seconds(31)));
+        assert!(!cache.check(&1, start));
+
+        cache.add(2, "two", start);
+        assert_eq!(cache.remove(&2, start + Duration::seconds(31)), None);
+        assert_eq!(*cache.get_or_add_with(3, start, || "three"), "three");
+        assert_eq!(cache.remove(&3, start), Some("three"));
+    }
+}
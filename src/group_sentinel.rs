@@ -0,0 +1,379 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! GroupSentinel runs the whole group consensus flow described in `docs/sentinel.md`.
+//!
+//! 1. Messages of a group arrive through `add_message` and are accumulated by a
+//!    `PureSentinel`. The first message of a request yields `GroupResult::GetGroupKey`, which
+//!    the caller sends to the group surrounding the source, preserving the request.
+//! 2. GetGroupKey responses arrive through `add_key_response`. Responses to requests not
+//!    asked for are ignored. The others are accumulated by a `KeySentinel` until a quorum of
+//!    them confirms the group.
+//! 3. The group identities listed by a quorum of the verified responses are handed to the
+//!    `PureSentinel` as keys of that request alone, and the messages are verified against
+//!    them. Once a quorum of them agree, `GroupResult::Resolved` is returned. A group can't
+//!    vouch for the keys of names outside it, as its keys are not used for other requests.
+
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use key_sentinel::{GroupClaimTrait, IdTrait, KeySentinel};
+use limits::Rejection;
+use pure_sentinel::{AddResult, PureSentinel, Source};
+use signature_scheme::{Ed25519, SignatureScheme};
+use super::SerialisedClaim;
+
+/// Result of adding a message or a key response.
+pub enum GroupResult<Request, Name> {
+    /// The caller should send a GetGroupKey for the request to the group surrounding the
+    /// name, and pass the responses to `add_key_response`.
+    GetGroupKey(Request, Name),
+    /// The request resolved to the claim, confirmed by a quorum of its source group.
+    Resolved(Request, SerialisedClaim),
+    /// The request resolved recently, to the claim given.
+    AlreadyResolved(Request, SerialisedClaim),
+    /// The message or key response was not stored because it would exceed the claim limits.
+    Rejected(Rejection),
+}
+
+/// GroupSentinel is templated like `KeySentinel`, which together with a `PureSentinel` it
+/// drives.
+pub struct GroupSentinel<Request, Name, IdType, GroupClaim, Scheme = Ed25519>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    pure_sentinel: PureSentinel<Request, Name, Scheme>,
+    key_sentinel: KeySentinel<Request, Name, IdType, GroupClaim, Scheme>,
+    // Requests we sent a GetGroupKey for, whose group is not confirmed yet. Requests the
+    // `PureSentinel` has dropped since are pruned, so this holds about as many as it does.
+    awaiting_keys: BTreeSet<Request>,
+}
+
+impl<Request, Name, IdType, GroupClaim, Scheme>
+    GroupSentinel<Request, Name, IdType, GroupClaim, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    /// Creates a group sentinel with a new `PureSentinel` and `KeySentinel`.
+    pub fn new() -> GroupSentinel<Request, Name, IdType, GroupClaim, Scheme> {
+        GroupSentinel {
+            pure_sentinel: PureSentinel::new(),
+            key_sentinel: KeySentinel::new(),
+            awaiting_keys: BTreeSet::new(),
+        }
+    }
+
+    /// Gives access to the `PureSentinel` messages are accumulated by, to configure it.
+    pub fn pure_sentinel(&mut self) -> &mut PureSentinel<Request, Name, Scheme> {
+        &mut self.pure_sentinel
+    }
+
+    /// Gives access to the `KeySentinel` key responses are accumulated by, to configure it.
+    pub fn key_sentinel(&mut self) -> &mut KeySentinel<Request, Name, IdType, GroupClaim, Scheme> {
+        &mut self.key_sentinel
+    }
+
+    /// This adds a message of the group surrounding the source of `request`, sent by
+    /// `claimant`, which signed `claim`. `quorum` messages have to agree for the request to
+    /// resolve. Keys added to the `PureSentinel` directly need `quorum` senders as well.
+    pub fn add_message(&mut self,
+                       request: Request,
+                       claimant: Name,
+                       signature: Scheme::Signature,
+                       claim: SerialisedClaim,
+                       quorum: usize)
+                       -> Option<GroupResult<Request, Name>> {
+        match self.pure_sentinel.add_claim(request.clone(), claimant, signature, claim, quorum,
+                                           quorum) {
+            Some(AddResult::RequestKeys(source)) => {
+                self.await_keys(request.clone());
+                Some(GroupResult::GetGroupKey(request, source))
+            }
+            Some(AddResult::Resolved(request, claim)) => {
+                let _ = self.awaiting_keys.remove(&request);
+                Some(GroupResult::Resolved(request, claim))
+            }
+            Some(AddResult::AlreadyResolved(request, claim)) =>
+                Some(GroupResult::AlreadyResolved(request, claim)),
+            Some(AddResult::Rejected(rejection)) => Some(GroupResult::Rejected(rejection)),
+            None => None,
+        }
+    }
+
+    /// This adds a GetGroupKey response for `request` from `sender`, which signed
+    /// `serialised`, the serialised form of `group_claim`. `quorum` responses have to confirm
+    /// the group before its keys are used to verify the messages.
+    pub fn add_key_response(&mut self,
+                            request: Request,
+                            sender: Name,
+                            serialised: SerialisedClaim,
+                            signature: Scheme::Signature,
+                            group_claim: GroupClaim,
+                            quorum: usize)
+                            -> Option<GroupResult<Request, Name>> {
+        // Only accumulate responses if we have called for them, and the messages are still
+        // held.
+        if !self.awaiting_keys.contains(&request) {
+            return None;
+        }
+        if !self.pure_sentinel.is_pending(&request) {
+            let _ = self.awaiting_keys.remove(&request);
+            return None;
+        }
+
+        let identities = match self.key_sentinel.add_identities(request, sender, serialised,
+                                                                signature, group_claim, quorum) {
+            Ok(Some(confirmed)) => confirmed,
+            Ok(None) => return None,
+            Err(rejection) => return Some(GroupResult::Rejected(rejection)),
+        };
+
+        let (request, identities) = identities;
+        let _ = self.awaiting_keys.remove(&request);
+
+        let keys = identities.iter()
+                             .map(|id| (id.name(), id.public_key()))
+                             .collect::<Vec<_>>();
+
+        // The confirmed group vouches for the keys of its members in this request only.
+        self.pure_sentinel.add_request_keys(request, keys)
+            .map(|(request, claim)| GroupResult::Resolved(request, claim))
+    }
+
+    /// Returns the number of requests whose GetGroupKey responses are still awaited.
+    pub fn awaiting_count(&self) -> usize {
+        self.awaiting_keys.len()
+    }
+
+    // Notes that keys for `request` were called for. Once twice as many requests are awaited
+    // as the `PureSentinel` holds, the ones it has evicted or expired are forgotten, which
+    // keeps the cost of pruning constant per request.
+    fn await_keys(&mut self, request: Request) {
+        let _ = self.awaiting_keys.insert(request);
+        if self.awaiting_keys.len() <= 2 * self.pure_sentinel.pending_count() {
+            return;
+        }
+
+        let dropped = self.awaiting_keys.iter()
+                                        .filter(|request| !self.pure_sentinel.is_pending(request))
+                                        .cloned()
+                                        .collect::<Vec<_>>();
+        for request in dropped {
+            let _ = self.awaiting_keys.remove(&request);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::{random, random_keypair, reset_random, sign_claim};
+    use sodiumoxide::crypto::sign;
+    use key_sentinel::{GroupClaimTrait, IdTrait};
+    use eviction::EvictionPolicy;
+    use pure_sentinel::Source;
+    use testing::seeded_keypair;
+
+    const QUORUM: usize = 5;
+    const MAX_PENDING: usize = 4;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        group: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.group
+        }
+    }
+
+    #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct TestIdType {
+        name: u64,
+        public_key: [u8; sign::PUBLICKEYBYTES],
+    }
+
+    impl IdTrait<u64> for TestIdType {
+        fn name(&self) -> u64 {
+            self.name
+        }
+
+        fn public_key(&self) -> sign::PublicKey {
+            sign::PublicKey(self.public_key)
+        }
+    }
+
+    #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct TestGroupClaim {
+        identities: Vec<TestIdType>,
+    }
+
+    impl GroupClaimTrait<TestIdType> for TestGroupClaim {
+        fn group_identities(&self) -> Vec<TestIdType> {
+            self.identities.clone()
+        }
+    }
+
+    #[test]
+    fn group_message_flow() {
+        reset_random();
+        let mut sentinel: GroupSentinel<TestRequest, u64, TestIdType, TestGroupClaim> =
+            GroupSentinel::new();
+        let request = TestRequest { core: random::<usize>(), group: 1000 };
+        let claim = vec![random::<u8>(); 16];
+        let group_claim_bytes = vec![random::<u8>(); 16];
+
+        // Key sentinel needs one more member than the quorum, as no one vouches for itself.
+        let members = (0..QUORUM + 1).map(|name| (name as u64, random_keypair()))
+                                     .collect::<Vec<_>>();
+        let group_claim = TestGroupClaim {
+            identities: members.iter()
+                               .map(|&(name, ref key_pair)| {
+                                   TestIdType { name: name, public_key: (key_pair.0).0 }
+                               })
+                               .collect(),
+        };
+
+        // Unsolicited key responses are ignored.
+        let (name, ref key_pair) = members[0];
+        assert!(sentinel.add_key_response(request.clone(), name, group_claim_bytes.clone(),
+                                          sign::sign_detached(&group_claim_bytes, &key_pair.1),
+                                          group_claim.clone(), QUORUM).is_none());
+
+        for (index, &(name, ref key_pair)) in members.iter().enumerate() {
+            let signature = sign_claim(&request, &claim, &key_pair.1);
+            match sentinel.add_message(request.clone(), name, signature, claim.clone(), QUORUM) {
+                Some(GroupResult::GetGroupKey(asked, group)) => {
+                    assert_eq!(index, 0);
+                    assert_eq!(asked, request);
+                    assert_eq!(group, request.group);
+                }
+                None => assert!(index > 0),
+                _ => panic!("unexpected result"),
+            }
+        }
+
+        for (index, &(name, ref key_pair)) in members.iter().enumerate() {
+            let signature = sign::sign_detached(&group_claim_bytes, &key_pair.1);
+            match sentinel.add_key_response(request.clone(), name, group_claim_bytes.clone(),
+                                            signature, group_claim.clone(), QUORUM) {
+                Some(GroupResult::Resolved(resolved, resolved_claim)) => {
+                    assert_eq!(index, QUORUM);
+                    assert_eq!(resolved, request);
+                    assert_eq!(resolved_claim, claim);
+                    return;
+                }
+                None => assert!(index < QUORUM),
+                _ => panic!("unexpected result"),
+            }
+        }
+        panic!("expected resolution");
+    }
+
+    // Returns one member more than the quorum, named from `first`, with their group claim.
+    fn group(first: u64) -> (Vec<(u64, (sign::PublicKey, sign::SecretKey))>, TestGroupClaim) {
+        let members = (first..first + QUORUM as u64 + 1).map(|name| (name, random_keypair()))
+                                                          .collect::<Vec<_>>();
+        let group_claim = TestGroupClaim {
+            identities: members.iter()
+                               .map(|&(name, ref key_pair)| {
+                                   TestIdType { name: name, public_key: (key_pair.0).0 }
+                               })
+                               .collect(),
+        };
+        (members, group_claim)
+    }
+
+    #[test]
+    fn keys_confirmed_for_their_request_only() {
+        reset_random();
+        let mut sentinel: GroupSentinel<TestRequest, u64, TestIdType, TestGroupClaim> =
+            GroupSentinel::new();
+        let claim = vec![random::<u8>(); 16];
+        let group_claim_bytes = vec![random::<u8>(); 16];
+        let (colluders, mut group_claim) = group(0);
+        let (impostors, forged) = group(100);
+
+        // The colluding group lists keys of its own making for the members of another group.
+        group_claim.identities.extend(forged.identities);
+        let request = TestRequest { core: 0, group: 1000 };
+        for &(name, ref key_pair) in colluders.iter() {
+            let signature = sign_claim(&request, &claim, &key_pair.1);
+            let _ = sentinel.add_message(request.clone(), name, signature, claim.clone(),
+                                         QUORUM);
+        }
+        let resolved = colluders.iter().filter_map(|&(name, ref key_pair)| {
+            let signature = sign::sign_detached(&group_claim_bytes, &key_pair.1);
+            sentinel.add_key_response(request.clone(), name, group_claim_bytes.clone(),
+                                      signature, group_claim.clone(), QUORUM)
+        }).count();
+        assert_eq!(resolved, 1);
+
+        // Messages forged under those keys for a request of the other group don't resolve.
+        let victim_request = TestRequest { core: 1, group: 2000 };
+        for &(name, ref key_pair) in impostors.iter() {
+            let signature = sign_claim(&victim_request, &claim, &key_pair.1);
+            match sentinel.add_message(victim_request.clone(), name, signature, claim.clone(),
+                                       QUORUM) {
+                Some(GroupResult::GetGroupKey(..)) | None => (),
+                _ => panic!("expected the forged messages to wait for keys"),
+            }
+        }
+        assert!(sentinel.pure_sentinel().is_pending(&victim_request));
+    }
+
+    #[test]
+    fn awaited_requests_pruned_with_pending() {
+        reset_random();
+        let mut sentinel: GroupSentinel<TestRequest, u64, TestIdType, TestGroupClaim> =
+            GroupSentinel::new();
+        sentinel.pure_sentinel().set_eviction_policy(EvictionPolicy {
+            max_pending: MAX_PENDING,
+            max_pending_per_source: MAX_PENDING,
+        });
+        let claim = vec![random::<u8>(); 16];
+        let key_pair = seeded_keypair(38);
+        let signature = sign::sign_detached(&claim, &key_pair.1);
+
+        for core in 0..10 * MAX_PENDING {
+            let request = TestRequest { core: core, group: core as u64 };
+            match sentinel.add_message(request, 0, signature.clone(), claim.clone(), QUORUM) {
+                Some(GroupResult::GetGroupKey(..)) => (),
+                _ => panic!("expected GetGroupKey"),
+            }
+            assert!(sentinel.awaiting_count() <= 2 * MAX_PENDING);
+        }
+
+        // Responses for evicted requests are ignored.
+        let evicted = TestRequest { core: 0, group: 0 };
+        let group_claim = TestGroupClaim {
+            identities: vec![TestIdType { name: 0, public_key: (key_pair.0).0 }],
+        };
+        let group_claim_bytes = vec![random::<u8>(); 16];
+        assert!(sentinel.add_key_response(evicted, 0, group_claim_bytes.clone(),
+                                          sign::sign_detached(&group_claim_bytes, &key_pair.1),
+                                          group_claim, 1).is_none());
+        assert_eq!(sentinel.key_sentinel().metrics().claims_received, 0);
+    }
+}
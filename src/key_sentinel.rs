@@ -16,13 +16,16 @@
 // relating to use of the SAFE Network Software.
 
 use lru_time_cache::LruCache;
-use sodiumoxide::crypto::sign;
 use std::collections::{BTreeMap, BTreeSet};
+use authority::{GetAuthority, PolicyTable};
 use key_store::KeyStore;
+use limits::{ByteBudget, ClaimLimits, Rejection};
+use metrics::KeySentinelMetrics;
+use signature_scheme::{Ed25519, SignatureScheme};
+use trace::{self, Divergence, KeyCall, KeyOutcome, Recorder, TraceEntry};
 use std::marker::PhantomData;
 use std::fmt::Debug;
 use super::{SerialisedClaim, verify_signature};
-use wrappers::SignW;
 
 #[allow(dead_code)]
 const MAX_REQUEST_COUNT: usize = 1000;
@@ -30,9 +33,9 @@ const MAX_REQUEST_COUNT: usize = 1000;
 type Map<K,V> = BTreeMap<K,V>;
 type Set<V>   = BTreeSet<V>;
 
-pub trait IdTrait<NameType> {
+pub trait IdTrait<NameType, Scheme = Ed25519> where Scheme: SignatureScheme {
     fn name(&self) -> NameType;
-    fn public_key(&self) -> sign::PublicKey;
+    fn public_key(&self) -> Scheme::PublicKey;
 }
 
 pub trait GroupClaimTrait<IdTrait> {
@@ -40,38 +43,137 @@ pub trait GroupClaimTrait<IdTrait> {
 }
 
 #[allow(dead_code)]
-pub struct KeySentinel<Request, Name, IdType, GroupClaim>
+pub struct KeySentinel<Request, Name, IdType, GroupClaim, Scheme = Ed25519>
     where Request: Eq + PartialOrd + Ord + Clone,
           Name: Eq + PartialOrd + Ord + Clone + Debug,
-          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name>,
-          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
 {
-    cache: LruCache<Request, (KeyStore<Name>, Map<Name, Set<(GroupClaim, SerialisedClaim, SignW)>>)>,
+    cache: LruCache<Request, (KeyStore<Name, Scheme>, Map<Name, Vec<Signed<GroupClaim, Scheme>>>)>,
+    // Bytes of serialised claims held per request.
+    bytes: Map<Request, usize>,
+    budget: ByteBudget,
+    policies: PolicyTable,
+    metrics: KeySentinelMetrics,
+    recorder: Option<KeyRecorder<Request, Name, IdType, GroupClaim, Scheme>>,
     phantom: PhantomData<IdType>,
 }
 
-impl<Request, Name, IdType, GroupClaim> KeySentinel<Request, Name, IdType, GroupClaim>
+/// Recorder of the calls made into a `KeySentinel`.
+pub type KeyRecorder<Request, Name, IdType, GroupClaim, Scheme> =
+    Box<Recorder<KeyTraceCall<Request, Name, GroupClaim, Scheme>, KeyOutcome<Request, IdType>>
+        + Send>;
+
+/// A call into a `KeySentinel` of signature scheme `Scheme`.
+pub type KeyTraceCall<Request, Name, GroupClaim, Scheme> =
+    KeyCall<Request, Name, <Scheme as SignatureScheme>::Signature, GroupClaim>;
+
+// A group claim with the serialised form its sender signed.
+type Signed<GroupClaim, Scheme> = (GroupClaim,
+                                   SerialisedClaim,
+                                   <Scheme as SignatureScheme>::Signature);
+
+impl<Request, Name, IdType, GroupClaim, Scheme> KeySentinel<Request, Name, IdType, GroupClaim, Scheme>
     where Request: Eq + PartialOrd + Ord + Clone,
           Name:    Eq + PartialOrd + Ord + Clone + Debug,
-          IdType:  Eq + PartialOrd + Ord + Clone + IdTrait<Name>,
-          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>, {
+          IdType:  Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme, {
 
     #[allow(dead_code)]
-    pub fn new() -> KeySentinel<Request, Name, IdType, GroupClaim> {
-        KeySentinel { cache: LruCache::with_capacity(MAX_REQUEST_COUNT), phantom: PhantomData }
+    pub fn new() -> KeySentinel<Request, Name, IdType, GroupClaim, Scheme> {
+        KeySentinel {
+            cache: LruCache::with_capacity(MAX_REQUEST_COUNT),
+            bytes: Map::new(),
+            budget: ByteBudget::new(ClaimLimits::default()),
+            policies: PolicyTable::default(),
+            metrics: KeySentinelMetrics::default(),
+            recorder: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Replaces the byte limits on held claims. Claims already held are kept even if they
+    /// exceed the new limits.
+    pub fn set_claim_limits(&mut self, claim_limits: ClaimLimits) {
+        self.budget.set_limits(claim_limits);
     }
 
+    /// Replaces the quorum policies `add_identities_by_authority` uses.
+    pub fn set_policies(&mut self, policies: PolicyTable) {
+        self.policies = policies;
+    }
+
+    /// Sets the recorder every call to `add_identities` is passed to, with its outcome.
+    pub fn set_recorder(&mut self,
+                        recorder: KeyRecorder<Request, Name, IdType, GroupClaim, Scheme>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Feeds the calls of `trace` into the sentinel, which should be fresh and configured as
+    /// the recorded one was, and returns the calls whose outcome differs from the recorded
+    /// one.
+    pub fn replay(&mut self,
+                  trace: Vec<TraceEntry<KeyTraceCall<Request, Name, GroupClaim, Scheme>,
+                                        KeyOutcome<Request, IdType>>>)
+                  -> Vec<Divergence<KeyOutcome<Request, IdType>>> {
+        trace::diverging(trace, |_, call| {
+            match call {
+                KeyCall::AddIdentities(request, sender, serialised, signature, claim,
+                                       quorum_size) => {
+                    KeyOutcome::of(&self.add_identities(request, sender, serialised, signature,
+                                                        claim, quorum_size))
+                }
+            }
+        })
+    }
+
+    /// Adds the group claim of `sender` for `request`. Once claims of a quorum of senders
+    /// verify, the identities listed by a quorum of them are returned.
     #[allow(dead_code)]
     pub fn add_identities(&mut self,
                           request: Request,
                           sender: Name,
                           serialised: SerialisedClaim,
-                          signature: sign::Signature,
+                          signature: Scheme::Signature,
                           claim: GroupClaim,
                           quorum_size: usize)
-                          -> Option<(Request, Vec<IdType>)> {
+                          -> Result<Option<(Request, Vec<IdType>)>, Rejection> {
+        let call = self.recorder.as_ref().map(|_| {
+            KeyCall::AddIdentities(request.clone(), sender.clone(), serialised.clone(),
+                                   signature.clone(), claim.clone(), quorum_size)
+        });
+        let result = self.accumulate_identities(request, sender, serialised, signature, claim,
+                                                quorum_size);
+        if let (Some(call), Some(recorder)) = (call, self.recorder.as_mut()) {
+            recorder.record(&call, &KeyOutcome::of(&result));
+        }
+        result
+    }
+
+    fn accumulate_identities(&mut self,
+                             request: Request,
+                             sender: Name,
+                             serialised: SerialisedClaim,
+                             signature: Scheme::Signature,
+                             claim: GroupClaim,
+                             quorum_size: usize)
+                             -> Result<Option<(Request, Vec<IdType>)>, Rejection> {
+        self.metrics.claims_received += 1;
+
+        // A claim sent again adds nothing, so it isn't held or charged again.
+        if self.holds(&request, &sender, &serialised, &signature) {
+            return Ok(None);
+        }
+
+        if let Err(rejection) = self.charge(&request, serialised.len()) {
+            self.metrics.claims_rejected.count(&rejection);
+            return Err(rejection);
+        }
 
         let retval = {
+            let metrics = &mut self.metrics;
             let keys_and_claims = self.cache.entry(request.clone())
                             .or_insert_with(||(KeyStore::new(), Map::new()));
 
@@ -80,50 +182,168 @@ impl<Request, Name, IdType, GroupClaim> KeySentinel<Request, Name, IdType, Group
 
             for id in claim.group_identities() {
                 keys.add_key(id.name(), sender.clone(), id.public_key());
+                metrics.vouchers_received += 1;
             }
 
-            claims.entry(sender).or_insert_with(||Set::new())
-                .insert((claim, serialised, SignW(signature)));
+            claims.entry(sender).or_insert_with(||Vec::new())
+                .push((claim, serialised, signature));
 
-            Self::try_selecting_group(keys, claims, quorum_size)
+            Self::try_selecting_group(keys, claims, quorum_size,
+                                      &mut metrics.signature_verifications,
+                                      &mut metrics.claims_rejected.bad_signature)
                 .map(|ids|(request, ids))
         };
 
-        retval.map(|(request, ids)| {
+        Ok(retval.map(|(request, ids)| {
+            self.metrics.resolutions += 1;
             self.cache.remove(&request);
+            self.release(&request);
             (request, ids)
-        })
+        }))
     }
 
-    fn try_selecting_group(key_store: &mut KeyStore<Name>,
-                           claims: &Map<Name, Set<(GroupClaim, SerialisedClaim, SignW)>>,
-                           quorum_size: usize)
-                           -> Option<Vec<IdType>> {
+    /// Adds identities as `add_identities` does, with the quorum given by the key quorum of
+    /// the authority of the request.
+    pub fn add_identities_by_authority(&mut self,
+                                       request: Request,
+                                       sender: Name,
+                                       serialised: SerialisedClaim,
+                                       signature: Scheme::Signature,
+                                       claim: GroupClaim)
+                                       -> Result<Option<(Request, Vec<IdType>)>, Rejection>
+        where Request: GetAuthority
+    {
+        let quorum_size = self.policies.get(request.get_authority()).key_quorum;
+        self.add_identities(request, sender, serialised, signature, claim, quorum_size)
+    }
+
+    /// Returns the counters of the sentinel, with the requests and bytes it currently holds.
+    pub fn metrics(&self) -> KeySentinelMetrics {
+        KeySentinelMetrics {
+            pending_requests: self.cache.len(),
+            pending_bytes: self.budget.total(),
+            ..self.metrics.clone()
+        }
+    }
+
+    // Tells whether `sender` already sent the same signed claim for `request`.
+    fn holds(&mut self,
+             request: &Request,
+             sender: &Name,
+             serialised: &SerialisedClaim,
+             signature: &Scheme::Signature)
+             -> bool {
+        let signature_data = Scheme::signature_data(signature);
+        self.cache.get(request)
+            .and_then(|&(_, ref claims)| claims.get(sender))
+            .map_or(false, |claims| {
+                claims.iter().any(|&(_, ref held, ref held_signature)| {
+                    held == serialised && Scheme::signature_data(held_signature) == signature_data
+                })
+            })
+    }
+
+    // Accounts for a claim of `size` bytes held for `request`, or rejects it.
+    fn charge(&mut self, request: &Request, size: usize) -> Result<(), Rejection> {
+        let request_bytes = self.held_bytes(request);
+
+        match self.budget.check(size, request_bytes) {
+            Err(Rejection::TotalBytesExceeded) => {
+                // Requests dropped by the cache still count against the budget until
+                // reclaimed here.
+                self.reclaim();
+                try!(self.budget.check(size, request_bytes));
+            }
+            result => try!(result),
+        }
+
+        self.budget.charge(size);
+        *self.bytes.entry(request.clone()).or_insert(0) += size;
+        Ok(())
+    }
+
+    // Bytes held for `request`, forgetting them if the cache has since dropped the request.
+    fn held_bytes(&mut self, request: &Request) -> usize {
+        if self.cache.check(request) {
+            self.bytes.get(request).cloned().unwrap_or(0)
+        } else {
+            self.release(request);
+            0
+        }
+    }
+
+    fn release(&mut self, request: &Request) {
+        if let Some(bytes) = self.bytes.remove(request) {
+            self.budget.release(bytes);
+        }
+    }
+
+    fn reclaim(&mut self) {
+        let dropped = self.bytes.keys()
+                                .filter(|request| !self.cache.check(request))
+                                .cloned()
+                                .collect::<Vec<_>>();
+        for request in dropped {
+            self.release(&request);
+        }
+    }
 
+    fn try_selecting_group(key_store: &mut KeyStore<Name, Scheme>,
+                           claims: &Map<Name, Vec<Signed<GroupClaim, Scheme>>>,
+                           quorum_size: usize,
+                           verifications: &mut u64,
+                           bad_signatures: &mut u64)
+                           -> Option<Vec<IdType>> {
+        // Senders with confirmed keys none of whose claims verified.
+        let mut unverified = 0;
         let verified_claims = claims.iter().filter_map(|(name, claims)| {
             for &(ref claim, ref serialised, ref signature) in claims {
-                if Self::verify_claim(name, key_store, serialised, &(signature.0), quorum_size) {
+                if Self::verify_claim(name, key_store, serialised, signature, quorum_size,
+                                      verifications) {
                     return Some(claim);
                 }
             }
+            if !key_store.get_accumulated_keys(name, quorum_size).is_empty() {
+                unverified += 1;
+            }
             None
         }).collect::<Vec<_>>();
 
         if verified_claims.len() < quorum_size {
             return None;
         }
+        *bad_signatures += unverified;
 
-        Some(verified_claims.iter().flat_map(|claim| claim.group_identities()).collect())
+        Some(Self::listed_by_quorum(verified_claims, quorum_size))
+    }
+
+    // Only identities listed by a quorum of the verified claims are confirmed. Taking the
+    // union would let a single member of the group, or anyone it colludes with, slip in keys
+    // of its own making for names of other groups. Each identity is listed once.
+    fn listed_by_quorum(verified_claims: Vec<&GroupClaim>, quorum_size: usize) -> Vec<IdType> {
+        let mut listed = Map::new();
+        for claim in verified_claims {
+            for id in claim.group_identities().into_iter().collect::<Set<_>>() {
+                *listed.entry(id).or_insert(0) += 1;
+            }
+        }
+
+        listed.into_iter()
+              .filter(|&(_, count)| count >= quorum_size)
+              .map(|(id, _)| id)
+              .collect()
     }
 
     fn verify_claim(author: &Name,
-                    key_store: &mut KeyStore<Name>,
+                    key_store: &mut KeyStore<Name, Scheme>,
                     serialised: &SerialisedClaim,
-                    signature: &sign::Signature,
-                    quorum_size: usize)
+                    signature: &Scheme::Signature,
+                    quorum_size: usize,
+                    verifications: &mut u64)
                     -> bool {
         for public_key in key_store.get_accumulated_keys(&author, quorum_size) {
-            if verify_signature(signature, &public_key, serialised).is_some() {
+            *verifications += 1;
+            if verify_signature::<Scheme>(signature, &public_key, serialised).is_some() {
                 return true;
             }
         }
@@ -134,8 +354,9 @@ impl<Request, Name, IdType, GroupClaim> KeySentinel<Request, Name, IdType, Group
 #[cfg(test)]
 mod test {
     use super::*;
-    use rand::random;
+    use testing::{random, random_keypair, reset_random, seeded_keypair};
     use sodiumoxide::crypto::sign;
+    use limits::{ClaimLimits, Rejection};
 
     const MESSAGE_SIZE: usize = 4;
     const QUORUM: usize = 10;
@@ -198,6 +419,7 @@ mod test {
 
     #[test]
     fn key_sentinel() {
+        reset_random();
         let mut sentinel: KeySentinel<TestRequest, TestName, TestIdType, TestGroupClaim> =
             KeySentinel::new();
 
@@ -207,7 +429,7 @@ mod test {
         let mut signatures = Vec::new();
 
         for i in 0..QUORUM + 1 {
-            let key_pair = sign::gen_keypair();
+            let key_pair = random_keypair();
 
             names.push(TestName(i as u32));
             pubs.push(key_pair.0);
@@ -231,7 +453,7 @@ mod test {
                                                 random_message.clone(),
                                                 signatures[index].clone(),
                                                 group_claim,
-                                                QUORUM).is_none());
+                                                QUORUM).unwrap().is_none());
                 continue;
             }
 
@@ -240,7 +462,145 @@ mod test {
                                             random_message.clone(),
                                             signatures[QUORUM].clone(),
                                             group_claim,
-                                            QUORUM).is_some());
+                                            QUORUM).unwrap().is_some());
+        }
+
+        let metrics = sentinel.metrics();
+        assert_eq!(metrics.claims_received, QUORUM as u64 + 1);
+        assert_eq!(metrics.vouchers_received, ((QUORUM + 1) * (QUORUM + 1)) as u64);
+        assert_eq!(metrics.resolutions, 1);
+        assert_eq!(metrics.pending_bytes, 0);
+    }
+
+    #[test]
+    fn only_identities_listed_by_quorum_confirmed() {
+        reset_random();
+        let mut sentinel: KeySentinel<TestRequest, TestName, TestIdType, TestGroupClaim> =
+            KeySentinel::new();
+        let message = generate_random_message();
+        let key_pairs = (0..QUORUM + 2).map(|i| seeded_keypair(i as u32)).collect::<Vec<_>>();
+        let identities = key_pairs.iter().enumerate()
+                                  .map(|(i, key_pair)| TestIdType { name: TestName(i as u32),
+                                                                    public_key: (key_pair.0).0 })
+                                  .collect::<Vec<_>>();
+        let request = TestRequest::new(random::<usize>(), TestName((QUORUM + 2) as u32));
+
+        // The first sender lists a key of its own making for another member, and a member
+        // of its own making.
+        let mut forged = identities.clone();
+        forged[1].public_key = (seeded_keypair(98).0).0;
+        forged.push(TestIdType { name: TestName(99), public_key: (seeded_keypair(99).0).0 });
+
+        let mut confirmed = None;
+        for index in 0..QUORUM + 2 {
+            let listed = if index == 0 { forged.clone() } else { identities.clone() };
+            let signature = sign::sign_detached(&message, &key_pairs[index].1);
+            confirmed = sentinel.add_identities(request.clone(), TestName(index as u32),
+                                                message.clone(), signature,
+                                                TestGroupClaim::new(listed), QUORUM).unwrap();
+            if confirmed.is_some() {
+                break;
+            }
+        }
+
+        let (resolved, ids) = confirmed.expect("Group confirmed");
+        assert_eq!(resolved, request);
+        assert!(ids.len() >= QUORUM);
+        assert!(ids.iter().all(|id| identities.contains(id)));
+        assert!(!ids.contains(&forged[1]));
+        assert!(!ids.contains(&forged[QUORUM + 2]));
+    }
+
+    #[test]
+    fn bad_signatures_counted_in_metrics() {
+        reset_random();
+        let mut sentinel: KeySentinel<TestRequest, TestName, TestIdType, TestGroupClaim> =
+            KeySentinel::new();
+        let message = generate_random_message();
+        let key_pairs = (0..QUORUM + 1).map(|i| seeded_keypair(i as u32)).collect::<Vec<_>>();
+        let identities = key_pairs.iter().enumerate()
+                                  .map(|(i, key_pair)| TestIdType { name: TestName(i as u32),
+                                                                    public_key: (key_pair.0).0 })
+                                  .collect::<Vec<_>>();
+        let request = TestRequest::new(random::<usize>(), TestName((QUORUM + 1) as u32));
+
+        // The last sender signs with a key other than the one listed for it.
+        let bad_signature = sign::sign_detached(&message, &seeded_keypair(99).1);
+        assert!(sentinel.add_identities(request.clone(), TestName(QUORUM as u32),
+                                        message.clone(), bad_signature,
+                                        TestGroupClaim::new(identities.clone()),
+                                        QUORUM).unwrap().is_none());
+        for index in 0..QUORUM {
+            let signature = sign::sign_detached(&message, &key_pairs[index].1);
+            let result = sentinel.add_identities(request.clone(), TestName(index as u32),
+                                                 message.clone(), signature,
+                                                 TestGroupClaim::new(identities.clone()),
+                                                 QUORUM).unwrap();
+            assert_eq!(result.is_some(), index + 1 == QUORUM);
+        }
+
+        let metrics = sentinel.metrics();
+        assert_eq!(metrics.claims_rejected.bad_signature, 1);
+        assert_eq!(metrics.claims_rejected.total(), 1);
+    }
+
+    #[test]
+    fn claims_over_byte_limits_rejected() {
+        reset_random();
+        let mut sentinel: KeySentinel<TestRequest, TestName, TestIdType, TestGroupClaim> =
+            KeySentinel::new();
+        sentinel.set_claim_limits(ClaimLimits { max_claim_size: MESSAGE_SIZE,
+                                                max_request_bytes: 2 * MESSAGE_SIZE,
+                                                max_total_bytes: 3 * MESSAGE_SIZE });
+        let key_pair = random_keypair();
+        let group_claim = TestGroupClaim::new(Vec::new());
+        let request = TestRequest::new(random::<usize>(), TestName(0));
+        let other_request = TestRequest::new(random::<usize>(), TestName(1));
+        let mut add = |request: &TestRequest, message: Vec<u8>| {
+            let signature = sign::sign_detached(&message, &key_pair.1);
+            sentinel.add_identities(request.clone(), TestName(random::<u32>()), message,
+                                    signature, group_claim.clone(), QUORUM)
+        };
+
+        let mut large_message = generate_random_message();
+        large_message.push(0);
+        assert_eq!(add(&request, large_message).err(), Some(Rejection::ClaimTooLarge));
+
+        assert!(add(&request, generate_random_message()).is_ok());
+        assert!(add(&request, generate_random_message()).is_ok());
+        assert_eq!(add(&request, generate_random_message()).err(),
+                   Some(Rejection::RequestBytesExceeded));
+
+        assert!(add(&other_request, generate_random_message()).is_ok());
+        assert_eq!(add(&other_request, generate_random_message()).err(),
+                   Some(Rejection::TotalBytesExceeded));
+    }
+
+    #[test]
+    fn resent_claims_held_once() {
+        reset_random();
+        let mut sentinel: KeySentinel<TestRequest, TestName, TestIdType, TestGroupClaim> =
+            KeySentinel::new();
+        sentinel.set_claim_limits(ClaimLimits { max_claim_size: MESSAGE_SIZE,
+                                                max_request_bytes: 2 * MESSAGE_SIZE,
+                                                max_total_bytes: 2 * MESSAGE_SIZE });
+        let key_pair = random_keypair();
+        let request = TestRequest::new(random::<usize>(), TestName(0));
+        let message = generate_random_message();
+        let signature = sign::sign_detached(&message, &key_pair.1);
+
+        for _ in 0..10 {
+            assert!(sentinel.add_identities(request.clone(), TestName(1), message.clone(),
+                                            signature.clone(), TestGroupClaim::new(Vec::new()),
+                                            QUORUM).is_ok());
         }
+        assert_eq!(sentinel.metrics().pending_bytes, MESSAGE_SIZE);
+
+        // The same claim under another signature is held, as it may be the genuine one.
+        let other_signature = sign::sign_detached(&message, &random_keypair().1);
+        assert!(sentinel.add_identities(request.clone(), TestName(1), message.clone(),
+                                        other_signature, TestGroupClaim::new(Vec::new()),
+                                        QUORUM).is_ok());
+        assert_eq!(sentinel.metrics().pending_bytes, 2 * MESSAGE_SIZE);
     }
 }
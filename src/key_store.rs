@@ -15,46 +15,66 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use sodiumoxide::crypto::sign;
 use lru_time_cache::LruCache;
 use std::collections::{BTreeMap, BTreeSet};
+use metrics::KeyStoreMetrics;
+use signature_scheme::{Ed25519, SignatureScheme};
+use statistics::Weights;
 
 const NAME_CAPACITY: usize = 1000;
 
-// FIXME: We only work with KeyData and not PublicKey directly
-// because PublicKey doesn't derive from Ord in the current version of
-// sodiumdioxide library. Once that library is bumped to version 0.0.6
-// or above, we should be able to get rid of KeyData and the re-wrapping.
-type KeyData   = [u8; sign::PUBLICKEYBYTES];
 type Map<A, B> = BTreeMap<A,B>;
 type Set<A>    = BTreeSet<A>;
 
-#[derive(Clone)]
-pub struct KeyStore<Name>
-    where Name: Eq + PartialOrd + Ord + Clone
+pub struct KeyStore<Name, Scheme = Ed25519>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
 {
-    //              +--- Target            +--- Sender
-    //              V                      V
-    cache: LruCache<Name, Map<KeyData, Set<Name>>>,
+    //              +--- Target                                    +--- Sender
+    //              V                                              V
+    cache: LruCache<Name, Map<Scheme::KeyData, (Scheme::PublicKey, Set<Name>)>>,
+    metrics: KeyStoreMetrics,
 }
 
-impl<Name> KeyStore<Name> where Name: Eq + PartialOrd + Ord + Clone {
-    pub fn new() -> KeyStore<Name> {
-        KeyStore { cache: LruCache::with_capacity(NAME_CAPACITY) }
+impl<Name, Scheme> Clone for KeyStore<Name, Scheme>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    fn clone(&self) -> KeyStore<Name, Scheme> {
+        KeyStore { cache: self.cache.clone(), metrics: self.metrics.clone() }
+    }
+}
+
+impl<Name, Scheme> KeyStore<Name, Scheme>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    pub fn new() -> KeyStore<Name, Scheme> {
+        KeyStore {
+            cache: LruCache::with_capacity(NAME_CAPACITY),
+            metrics: KeyStoreMetrics::default(),
+        }
     }
 
-    pub fn add_key(&mut self, target: Name, sender: Name, key: sign::PublicKey) {
+    pub fn add_key(&mut self, target: Name, sender: Name, key: Scheme::PublicKey) {
         // No self signing.
         if target == sender {
+            self.metrics.self_vouchers_ignored += 1;
             return;
         }
 
-        let new_map = || Map::<KeyData, Set<Name>>::new();
-        let new_set = || Set::<Name>::new();
+        let new_map = || Map::<Scheme::KeyData, (Scheme::PublicKey, Set<Name>)>::new();
 
-        self.cache.entry(target).or_insert_with(new_map)
-                  .entry(key.0).or_insert_with(new_set)
-                  .insert(sender);
+        if self.cache.entry(target).or_insert_with(new_map)
+                     .entry(Scheme::key_data(&key)).or_insert_with(|| (key, Set::new()))
+                     .1.insert(sender) {
+            self.metrics.vouchers_stored += 1;
+        }
+    }
+
+    /// Returns the counters of the store, with the number of targets it holds keys for.
+    pub fn metrics(&self) -> KeyStoreMetrics {
+        KeyStoreMetrics { targets: self.cache.len(), ..self.metrics.clone() }
     }
 
     #[allow(dead_code)]
@@ -67,19 +87,34 @@ impl<Name> KeyStore<Name> where Name: Eq + PartialOrd + Ord + Clone {
     pub fn get_accumulated_keys(&mut self,
                                 target: &Name,
                                 quorum_size: usize)
-                                -> Vec<sign::PublicKey> {
+                                -> Vec<Scheme::PublicKey> {
         // Create temp variable to workaround a borrow checker bug
         // http://blog.ezyang.com/2013/12/two-bugs-in-the-borrow-checker-every-rust-developer-should-know-about/
         self.cache.get(target)
             .iter().flat_map(|keys| Self::pick_where_quorum_reached(keys, quorum_size))
-            .cloned().map(sign::PublicKey)
+            .cloned()
+            .collect::<_>()
+    }
+
+    /// Returns a vector of keys belonging to `target`, for whom we've received the key
+    /// from senders whose summed weight reaches `quorum_weight`.
+    pub fn get_weighted_keys(&mut self,
+                             target: &Name,
+                             weights: &Weights<Name>,
+                             quorum_weight: usize)
+                             -> Vec<Scheme::PublicKey> {
+        self.cache.get(target)
+            .iter().flat_map(|keys| keys.values().filter_map(|&(ref key, ref sender_set)| {
+                if weights.total(sender_set.iter()) >= quorum_weight { Some(key) } else { None }
+            }))
+            .cloned()
             .collect::<_>()
     }
 
-    fn pick_where_quorum_reached<'a>(keys: &'a Map<KeyData, Set<Name>>,
+    fn pick_where_quorum_reached<'a>(keys: &'a Map<Scheme::KeyData, (Scheme::PublicKey, Set<Name>)>,
                                      quorum: usize)
-                                     -> Vec<&'a KeyData> {
-        keys.iter().filter_map(|(key, sender_set)| {
+                                     -> Vec<&'a Scheme::PublicKey> {
+        keys.values().filter_map(|&(ref key, ref sender_set)| {
             if sender_set.len() >= quorum { Some(key) } else { None }
         }).collect::<_>()
     }
@@ -89,7 +124,8 @@ impl<Name> KeyStore<Name> where Name: Eq + PartialOrd + Ord + Clone {
 mod test {
     use super::*;
     use sodiumoxide::crypto::sign;
-    use rand::random;
+    use testing::{random, reset_random};
+    use statistics::Weights;
 
     type NameType = u8;
     const QUORUM: usize = 6;
@@ -110,6 +146,7 @@ mod test {
 
     #[test]
     fn quorum_reached() {
+        reset_random();
         let target: NameType = 0;
         let mut ks = KeyStore::<NameType>::new();
         let valid_key = random_key();
@@ -129,6 +166,7 @@ mod test {
 
     #[test]
     fn no_self_sign() {
+        reset_random();
         let target: NameType = 0;
         let mut ks = KeyStore::<NameType>::new();
         let valid_key = random_key();
@@ -144,6 +182,7 @@ mod test {
 
     #[test]
     fn successful_attack() {
+        reset_random();
         let target: NameType = 0;
         let mut ks = KeyStore::<NameType>::new();
         let valid_key1 = random_key();
@@ -172,4 +211,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn weighted_quorum_reached() {
+        reset_random();
+        let target: NameType = 0;
+        let mut ks = KeyStore::<NameType>::new();
+        let mut weights = Weights::new();
+        let valid_key = random_key();
+
+        add_noise(&mut ks, target, 1000);
+
+        weights.set(1, QUORUM - 2);
+        ks.add_key(target, 1, valid_key);
+        assert!(ks.get_weighted_keys(&target, &weights, QUORUM).is_empty());
+        assert!(!ks.get_weighted_keys(&target, &weights, QUORUM - 2).is_empty());
+
+        ks.add_key(target, 2, valid_key);
+        assert!(ks.get_weighted_keys(&target, &weights, QUORUM).is_empty());
+
+        ks.add_key(target, 3, valid_key);
+        assert_eq!(ks.get_weighted_keys(&target, &weights, QUORUM).len(), 1);
+        assert!(ks.get_accumulated_keys(&target, QUORUM).is_empty());
+    }
 }
@@ -53,16 +53,23 @@
          missing_debug_implementations)]
 
 extern crate rustc_serialize;
-extern crate accumulator;
 extern crate lru_time_cache;
 extern crate sodiumoxide;
 extern crate cbor;
 extern crate rand;
+extern crate time;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio_timer;
+#[cfg(feature = "bls")]
+extern crate bls12_381;
+#[cfg(feature = "bls")]
+extern crate sha2;
 
-use sodiumoxide::crypto;
-use sodiumoxide::crypto::sign::verify_detached;
-use sodiumoxide::crypto::sign::PublicKey;
-use sodiumoxide::crypto::sign::Signature;
+use cbor::Encoder;
+use rustc_serialize::Encodable;
+use signature_scheme::SignatureScheme;
 
 pub type SerialisedClaim = Vec<u8>;
 
@@ -70,17 +77,58 @@ pub type SerialisedClaim = Vec<u8>;
 /// The claims made must be identical and cryptographically signed.
 pub mod pure_sentinel;
 mod key_store;
+mod expiring_cache;
 pub mod key_sentinel;
-mod wrappers;
-mod refresh_sentinel;
-mod statistics;
+pub mod eviction;
+pub mod limits;
+pub mod metrics;
+pub mod clock;
+pub mod trace;
+pub mod evidence;
+pub mod wire;
+pub mod signature_scheme;
+pub mod threshold_sentinel;
+#[cfg(feature = "bls")]
+pub mod threshold_bls;
+pub mod digest_sentinel;
+pub mod send_get_keys;
+pub mod group_sentinel;
+pub mod client_sentinel;
+pub mod authority;
+pub mod sharded;
+#[cfg(feature = "async")]
+pub mod async_sentinel;
+pub mod refresh_sentinel;
+#[cfg(test)]
+mod simulation;
+#[cfg(test)]
+mod adversary;
+pub mod statistics;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
-fn verify_signature(signature: &Signature,
-                    public_key: &PublicKey,
-                    claim: &SerialisedClaim)
-                    -> Option<SerialisedClaim> {
+/// Returns `value` encoded as CBOR, the bytes a request is serialised to before its claims
+/// are bound to it.
+pub fn serialise<T: Encodable>(value: &T) -> Vec<u8> {
+    let mut encoder = Encoder::from_memory();
+    encoder.encode(&[value]).expect("Encoding to memory doesn't fail");
+    encoder.as_bytes().to_vec()
+}
+
+/// Returns the bytes a claimant signs to make `claim` for the request serialised as
+/// `request`: the two encoded together, so that the signature can't be replayed for another
+/// request.
+pub fn bound_claim(request: &[u8], claim: &[u8]) -> Vec<u8> {
+    serialise(&(request, claim))
+}
 
-    match crypto::sign::verify_detached(&signature, claim, public_key) {
+fn verify_signature<Scheme>(signature: &Scheme::Signature,
+                            public_key: &Scheme::PublicKey,
+                            claim: &SerialisedClaim)
+                            -> Option<SerialisedClaim>
+    where Scheme: SignatureScheme
+{
+    match Scheme::verify(&signature, public_key, claim) {
         true => Some(claim.clone()),
         false => None,
     }
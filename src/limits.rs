@@ -0,0 +1,132 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Memory limits on the claims a sentinel holds while requests are pending.
+//!
+//! Every copy of a claim is kept until its request resolves, so the limits are expressed in
+//! bytes of serialised claims: per claim, per request and across the whole sentinel.
+
+/// Default maximum size of a single serialised claim.
+pub const DEFAULT_MAX_CLAIM_SIZE: usize = 1024 * 1024;
+/// Default maximum number of claim bytes held for a single request.
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 64 * 1024 * 1024;
+/// Default maximum number of claim bytes held across all pending requests.
+pub const DEFAULT_MAX_TOTAL_BYTES: usize = 512 * 1024 * 1024;
+
+/// Byte limits on the claims held by a sentinel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClaimLimits {
+    /// Maximum size of a single serialised claim.
+    pub max_claim_size: usize,
+    /// Maximum number of claim bytes held for a single request.
+    pub max_request_bytes: usize,
+    /// Maximum number of claim bytes held across all pending requests.
+    pub max_total_bytes: usize,
+}
+
+impl Default for ClaimLimits {
+    fn default() -> ClaimLimits {
+        ClaimLimits {
+            max_claim_size: DEFAULT_MAX_CLAIM_SIZE,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            max_total_bytes: DEFAULT_MAX_TOTAL_BYTES,
+        }
+    }
+}
+
+/// Reason a claim was rejected without being stored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable)]
+pub enum Rejection {
+    /// The claim alone is larger than `max_claim_size`.
+    ClaimTooLarge,
+    /// Storing the claim would take its request over `max_request_bytes`.
+    RequestBytesExceeded,
+    /// Storing the claim would take the sentinel over `max_total_bytes`.
+    TotalBytesExceeded,
+}
+
+/// Tracks the bytes held by a sentinel against its `ClaimLimits`.
+pub struct ByteBudget {
+    limits: ClaimLimits,
+    total: usize,
+}
+
+impl ByteBudget {
+    /// Creates an empty budget.
+    pub fn new(limits: ClaimLimits) -> ByteBudget {
+        ByteBudget { limits: limits, total: 0 }
+    }
+
+    /// Replaces the limits. Bytes already held are kept even if they exceed the new limits.
+    pub fn set_limits(&mut self, limits: ClaimLimits) {
+        self.limits = limits;
+    }
+
+    /// Number of bytes currently held.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Checks whether a claim of `claim_size` bytes may be added to a request already
+    /// holding `request_bytes` bytes.
+    pub fn check(&self, claim_size: usize, request_bytes: usize) -> Result<(), Rejection> {
+        if claim_size > self.limits.max_claim_size {
+            return Err(Rejection::ClaimTooLarge);
+        }
+        if request_bytes.saturating_add(claim_size) > self.limits.max_request_bytes {
+            return Err(Rejection::RequestBytesExceeded);
+        }
+        if self.total.saturating_add(claim_size) > self.limits.max_total_bytes {
+            return Err(Rejection::TotalBytesExceeded);
+        }
+        Ok(())
+    }
+
+    /// Accounts for `bytes` newly held.
+    pub fn charge(&mut self, bytes: usize) {
+        self.total += bytes;
+    }
+
+    /// Accounts for `bytes` no longer held.
+    pub fn release(&mut self, bytes: usize) {
+        self.total = self.total.saturating_sub(bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn budget_checks_each_limit() {
+        let mut budget = ByteBudget::new(ClaimLimits { max_claim_size: 10,
+                                                       max_request_bytes: 25,
+                                                       max_total_bytes: 40 });
+
+        assert_eq!(budget.check(11, 0), Err(Rejection::ClaimTooLarge));
+        assert_eq!(budget.check(10, 16), Err(Rejection::RequestBytesExceeded));
+        assert_eq!(budget.check(10, 15), Ok(()));
+
+        budget.charge(35);
+        assert_eq!(budget.check(6, 0), Err(Rejection::TotalBytesExceeded));
+        assert_eq!(budget.check(5, 0), Ok(()));
+
+        budget.release(35);
+        assert_eq!(budget.total(), 0);
+        assert_eq!(budget.check(10, 0), Ok(()));
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Snapshots of the activity of the sentinels, for export to monitoring.
+//!
+//! Each sentinel keeps counters of what it has seen since it was created, and returns them
+//! from `metrics()` together with gauges of what it currently holds. Counters only grow;
+//! rates are left to the monitoring system, which can take the difference of two snapshots.
+
+use time::Duration;
+use limits::Rejection;
+
+/// Upper bounds in milliseconds of the buckets of the time-to-resolve histogram. A last
+/// bucket counts everything slower.
+pub const TIME_TO_RESOLVE_BUCKETS_MILLIS: [i64; 8] = [10, 50, 100, 500, 1000, 5000, 10000, 60000];
+
+/// Counts of durations falling into buckets of increasing upper bounds.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Histogram {
+    bounds: Vec<i64>,
+    counts: Vec<u64>,
+    sum_millis: i64,
+}
+
+impl Histogram {
+    /// Creates an empty histogram with buckets up to each of `bounds_millis`, which have to
+    /// be increasing, and one for everything above.
+    pub fn new(bounds_millis: &[i64]) -> Histogram {
+        Histogram {
+            bounds: bounds_millis.to_vec(),
+            counts: vec![0; bounds_millis.len() + 1],
+            sum_millis: 0,
+        }
+    }
+
+    /// Counts `duration` in the first bucket it does not exceed.
+    pub fn observe(&mut self, duration: Duration) {
+        let millis = duration.num_milliseconds();
+        let index = self.bounds.iter().position(|bound| millis <= *bound)
+                               .unwrap_or(self.bounds.len());
+        self.counts[index] += 1;
+        self.sum_millis = self.sum_millis.saturating_add(millis);
+    }
+
+    /// Returns the upper bound and count of each bucket, lowest first. The last bucket has no
+    /// upper bound.
+    pub fn buckets(&self) -> Vec<(Option<i64>, u64)> {
+        self.bounds.iter().map(|bound| Some(*bound)).chain(Some(None))
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the number of durations counted.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().fold(0, |total, count| total + count)
+    }
+
+    /// Returns the sum of the durations counted, in milliseconds.
+    pub fn sum_millis(&self) -> i64 {
+        self.sum_millis
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new(&TIME_TO_RESOLVE_BUCKETS_MILLIS)
+    }
+}
+
+/// Number of claims rejected for each reason.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct RejectionCounts {
+    /// Claims rejected with `Rejection::ClaimTooLarge`.
+    pub claim_too_large: u64,
+    /// Claims rejected with `Rejection::RequestBytesExceeded`.
+    pub request_bytes_exceeded: u64,
+    /// Claims rejected with `Rejection::TotalBytesExceeded`.
+    pub total_bytes_exceeded: u64,
+    /// Claims of claimants whose keys were confirmed but whose signatures didn't verify
+    /// against them, counted once each claimant when the request resolves.
+    pub bad_signature: u64,
+}
+
+impl RejectionCounts {
+    /// Counts one more claim rejected with `rejection`.
+    pub fn count(&mut self, rejection: &Rejection) {
+        match *rejection {
+            Rejection::ClaimTooLarge => self.claim_too_large += 1,
+            Rejection::RequestBytesExceeded => self.request_bytes_exceeded += 1,
+            Rejection::TotalBytesExceeded => self.total_bytes_exceeded += 1,
+        }
+    }
+
+    /// Returns the number of claims rejected for any reason.
+    pub fn total(&self) -> u64 {
+        self.claim_too_large + self.request_bytes_exceeded + self.total_bytes_exceeded +
+        self.bad_signature
+    }
+}
+
+/// Activity of a key store.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct KeyStoreMetrics {
+    /// Vouchers stored, each a sender vouching for a key of a target for the first time.
+    pub vouchers_stored: u64,
+    /// Vouchers ignored because the sender vouched for its own key.
+    pub self_vouchers_ignored: u64,
+    /// Gauge of the targets keys are held for.
+    pub targets: usize,
+}
+
+/// Activity of a `PureSentinel`.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct PureSentinelMetrics {
+    /// Claims passed to `add_claim`.
+    pub claims_received: u64,
+    /// Claims whose signature verified on the resolution of their request, including
+    /// conflicting ones.
+    pub claims_verified: u64,
+    /// Claims rejected by the claim limits or for a bad signature, by reason.
+    pub claims_rejected: RejectionCounts,
+    /// Claims of new requests dropped because their source held too many pending requests.
+    pub claims_dropped: u64,
+    /// Claims for requests that had already resolved.
+    pub claims_already_resolved: u64,
+    /// Requests resolved.
+    pub resolutions: u64,
+    /// Verified claims that differed from the claim their request resolved to.
+    pub forks: u64,
+    /// Pending requests evicted to make room for new ones.
+    pub evictions: u64,
+    /// Pending requests dropped by `expire_pending`.
+    pub expirations: u64,
+    /// Signatures checked against a public key, successfully or not.
+    pub signature_verifications: u64,
+    /// Time from the first claim of a request to its resolution.
+    pub time_to_resolve: Histogram,
+    /// Gauge of the requests awaiting resolution.
+    pub pending_requests: usize,
+    /// Gauge of the bytes of claims held for pending requests.
+    pub pending_bytes: usize,
+    /// Activity of the key store the sentinel verifies claims with. It may be shared with
+    /// other sentinels.
+    pub key_store: KeyStoreMetrics,
+}
+
+/// Activity of a `KeySentinel`.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct KeySentinelMetrics {
+    /// Group claims passed to `add_identities`.
+    pub claims_received: u64,
+    /// Group claims rejected by the claim limits or for a bad signature, by reason.
+    pub claims_rejected: RejectionCounts,
+    /// Key vouchers taken from the identities of group claims, one per identity.
+    pub vouchers_received: u64,
+    /// Requests whose group was confirmed.
+    pub resolutions: u64,
+    /// Signatures checked against a public key, successfully or not.
+    pub signature_verifications: u64,
+    /// Gauge of the requests awaiting confirmation of their group.
+    pub pending_requests: usize,
+    /// Gauge of the bytes of group claims held.
+    pub pending_bytes: usize,
+}
+
+/// Activity of a `RefreshSentinel`.
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct RefreshSentinelMetrics {
+    /// Values passed to `add`.
+    pub values_received: u64,
+    /// Additions that reached the quorum of their key.
+    pub resolutions: u64,
+    /// Gauge of the keys values are accumulated for.
+    pub entries: usize,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::Duration;
+
+    #[test]
+    fn durations_counted_in_buckets() {
+        let mut histogram = Histogram::new(&[10, 100]);
+        histogram.observe(Duration::milliseconds(10));
+        histogram.observe(Duration::milliseconds(11));
+        histogram.observe(Duration::milliseconds(50));
+        histogram.observe(Duration::seconds(1));
+
+        assert_eq!(histogram.buckets(), vec![(Some(10), 1), (Some(100), 2), (None, 1)]);
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum_millis(), 1071);
+        assert_eq!(Histogram::default().buckets().len(),
+                   TIME_TO_RESOLVE_BUCKETS_MILLIS.len() + 1);
+    }
+}
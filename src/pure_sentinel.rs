@@ -32,16 +32,67 @@
 //! can be one or higher.
 //! The claims_threshold specifies a minimal threshold on the number of verified claims before
 //! pure sentinel will attempt to merge these verified claims.
+//!
+//! Pending requests are held within the limits set with `set_eviction_policy` and
+//! `set_claim_limits`, and resolved ones are remembered for the window set with
+//! `set_replay_window`. Weights, key requests, quorum policies per authority, the clock and
+//! the recording of calls are configured with the other `set_` methods.
 
-use super::SerialisedClaim;
+use super::{SerialisedClaim, bound_claim, serialise};
 
-use sodiumoxide::crypto::sign::PublicKey;
-use sodiumoxide::crypto::sign::Signature;
-use accumulator::Accumulator;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use rustc_serialize::Encodable;
+use time::{Duration, SteadyTime};
+use authority::{Authority, GetAuthority, PolicyTable};
+use clock::{self, Clock, ManualClock};
+use eviction::{self, EvictionPolicy, Progress};
+use expiring_cache::ExpiringCache;
 use key_store::KeyStore;
-use statistics::Frequency;
+use limits::{ByteBudget, ClaimLimits, Rejection};
+use metrics::PureSentinelMetrics;
+use send_get_keys::{KeyRequest, KeySink, SendGetKeys};
+use signature_scheme::{Ed25519, SignatureScheme};
+use statistics::{Frequency, Weights};
+use trace::{self, Divergence, PureCall, PureOutcome, Recorder, TraceEntry};
+
+type Map<K, V> = BTreeMap<K, V>;
+type Set<V>    = BTreeSet<V>;
+
+/// Number of resolved requests remembered to answer late claims.
+pub const MAX_RESOLVED_COUNT: usize = 1000;
+/// Seconds a resolved request is remembered for.
+pub const RESOLVED_EXPIRY_SECS: i64 = 600;
+/// Number of requests keys can be buffered for before their first claim arrives.
+pub const MAX_EARLY_KEYS_COUNT: usize = 100;
+/// Number of senders whose keys are buffered per request.
+pub const MAX_EARLY_KEYS_PER_REQUEST: usize = 32;
+/// Seconds keys are buffered for before their first claim arrives.
+pub const EARLY_KEYS_EXPIRY_SECS: i64 = 60;
+/// Milliseconds before missing keys of a pending request are first requested again.
+pub const KEY_REQUEST_DELAY_MILLIS: i64 = 1000;
+/// Upper bound in seconds on the delay between repeated key requests.
+pub const MAX_KEY_REQUEST_DELAY_SECS: i64 = 60;
+/// Number of conflicts held until collected with `take_conflicts`.
+pub const MAX_CONFLICT_COUNT: usize = 1000;
+
+/// Recorder of the calls made into a `PureSentinel`.
+pub type PureRecorder<Request, Name, Scheme> =
+    Box<Recorder<PureTraceCall<Request, Name, Scheme>, PureOutcome<Request, Name>> + Send>;
 
-pub trait Source<Name> where Name: Eq + PartialOrd + Ord  + Clone {
+/// A call into a `PureSentinel` of signature scheme `Scheme`.
+pub type PureTraceCall<Request, Name, Scheme> = PureCall<Request,
+                                                          Name,
+                                                          <Scheme as SignatureScheme>::Signature,
+                                                          <Scheme as SignatureScheme>::PublicKey>;
+
+// Keys sent ahead of the claims, with their senders.
+type EarlyKeys<Name, PublicKey> = Vec<(Name, Vec<(Name, PublicKey)>)>;
+
+/// A request sent by the group surrounding its source. Claims are signed bound to the request
+/// as `bound_claim` encodes it, so requests have to be `Encodable`.
+pub trait Source<Name>: Encodable where Name: Eq + PartialOrd + Ord  + Clone {
     fn get_source(&self) -> Name;
 }
 
@@ -51,44 +102,267 @@ pub enum AddResult<Request, Name>
 {
     RequestKeys(Name),
     Resolved(Request, SerialisedClaim),
+    AlreadyResolved(Request, SerialisedClaim),
+    Rejected(Rejection),
+}
+
+/// A verified claim of `claimant` for `request` that differs from the claim the request
+/// resolved to.
+pub struct Conflict<Request, Name, Signature> {
+    /// The resolved request.
+    pub request: Request,
+    /// The claimant that signed the conflicting claim.
+    pub claimant: Name,
+    /// Signature of the claimant over `claim` bound to `request`, as `bound_claim` encodes it.
+    pub signature: Signature,
+    /// The conflicting claim.
+    pub claim: SerialisedClaim,
+    /// The claim the request resolved to.
+    pub resolved_claim: SerialisedClaim,
+}
+
+// Claims accumulated for a request that has not resolved yet.
+struct PendingRequest<Name, Scheme>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    claims: Vec<(Name, Scheme::Signature, SerialisedClaim)>,
+    claimants: Set<Name>,
+    // Bytes of serialised claims held.
+    bytes: usize,
+    // Claimants whose claim verified, checked as it arrives if their keys are confirmed by
+    // then, and on each resolve attempt.
+    verified: Set<Name>,
+    // Keys added with `add_request_keys`, which verify the claims of this request only.
+    keys: Map<Name, Vec<Scheme::PublicKey>>,
+    claim_quorum: usize,
+    key_quorum: usize,
+    last_seen: u64,
+    first_seen: SteadyTime,
+    // When missing keys are due to be requested again, and the delay after that.
+    next_key_request: SteadyTime,
+    key_request_delay: Duration,
+}
+
+impl<Name, Scheme> PendingRequest<Name, Scheme>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    fn new(claim_quorum: usize,
+           key_quorum: usize,
+           key_request_delay: Duration,
+           now: SteadyTime)
+           -> PendingRequest<Name, Scheme> {
+        PendingRequest {
+            claims: Vec::new(),
+            claimants: Set::new(),
+            bytes: 0,
+            verified: Set::new(),
+            keys: Map::new(),
+            claim_quorum: claim_quorum,
+            key_quorum: key_quorum,
+            last_seen: 0,
+            first_seen: now,
+            next_key_request: now + key_request_delay,
+            key_request_delay: key_request_delay,
+        }
+    }
+
+    fn progress(&self, weights: &Weights<Name>) -> Progress {
+        Progress {
+            verified: weights.total(self.verified.iter()),
+            claimants: self.claimants.len(),
+            quorum: self.claim_quorum,
+        }
+    }
+}
+
+/// Public signing keys shared between sentinels, such as the shards of a `ShardedSentinel`.
+/// Keys added through one sentinel are then used to verify claims held by all of them.
+pub struct SharedKeyStore<Name, Scheme = Ed25519>(Arc<Mutex<KeyStore<Name, Scheme>>>)
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme;
+
+impl<Name, Scheme> SharedKeyStore<Name, Scheme>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    /// Creates an empty key store.
+    pub fn new() -> SharedKeyStore<Name, Scheme> {
+        SharedKeyStore(Arc::new(Mutex::new(KeyStore::new())))
+    }
+
+    // Lookups reorder the cache, so even they need exclusive access. The lock is only held
+    // while keys are added or looked up, never while signatures are verified.
+    fn lock(&self) -> MutexGuard<KeyStore<Name, Scheme>> {
+        match self.0.lock() {
+            Ok(key_store) => key_store,
+            // Each key is added in a single step, so the store is consistent regardless.
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl<Name, Scheme> Clone for SharedKeyStore<Name, Scheme>
+    where Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    fn clone(&self) -> SharedKeyStore<Name, Scheme> {
+        SharedKeyStore(self.0.clone())
+    }
 }
 
 /// PureSentinel is templated on an immutable Request type, a mergeable Claim type.
 /// It further takes a Name type to identify claimants.
-/// Signature and PublicSignKey type are auxiliary types to handle a user-chosen
-/// cryptographic signing scheme.
-pub struct PureSentinel<Request, Name>
+/// The Scheme type handles a user-chosen cryptographic signing scheme and
+/// defaults to Ed25519.
+pub struct PureSentinel<Request, Name, Scheme = Ed25519>
     where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
-          Name: Eq + PartialOrd + Ord + Clone
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
 {
-    claim_accumulator: Accumulator<Request, (Name, Signature, SerialisedClaim)>,
-    key_store: KeyStore<Name>,
-    claim_quroum_size: Option<usize>,
+    pending: Map<Request, PendingRequest<Name, Scheme>>,
+    pending_per_source: Map<Name, usize>,
+    resolved: ExpiringCache<Request, SerialisedClaim>,
+    early_keys: ExpiringCache<Request, EarlyKeys<Name, Scheme::PublicKey>>,
+    // Tells whether a sender is in the close group of a source.
+    close_group_check: Option<Box<Fn(&Name, &Name) -> bool + Send>>,
+    key_sink: Option<KeySink<Name>>,
+    policies: PolicyTable,
+    conflicts: Vec<Conflict<Request, Name, Scheme::Signature>>,
+    metrics: PureSentinelMetrics,
+    key_store: SharedKeyStore<Name, Scheme>,
+    weights: Weights<Name>,
+    eviction_policy: EvictionPolicy,
+    budget: ByteBudget,
+    key_request_delay: Duration,
+    max_key_request_delay: Duration,
+    clock: Arc<Clock>,
+    recorder: Option<PureRecorder<Request, Name, Scheme>>,
+    // Stamps pending requests with their last activity, to break ties on eviction.
+    sequence: u64,
 }
 
-impl<Request, Name>
-    PureSentinel<Request, Name>
+impl<Request, Name, Scheme>
+    PureSentinel<Request, Name, Scheme>
     where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
-          Name: Eq + PartialOrd + Ord + Clone {
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme {
     /// This creates a new pure sentinel that will collect a minimal claim_threshold number
     /// of verified claims before attempting to merge these claims.
     /// To obtain a verified claim PureSentinel needs to have received a matching public
     /// signing key. Each such a public signing key needs keys_threshold confirmations
     /// for it to be considered valid and used for verifying the signature
     /// of the corresponding claim.
-    pub fn new() -> PureSentinel<Request, Name> {
+    pub fn new() -> PureSentinel<Request, Name, Scheme> {
+        PureSentinel::with_key_store(SharedKeyStore::new())
+    }
+
+    /// Creates a pure sentinel that verifies claims with, and adds keys to, `key_store`.
+    pub fn with_key_store(key_store: SharedKeyStore<Name, Scheme>)
+                          -> PureSentinel<Request, Name, Scheme> {
         PureSentinel {
-            claim_accumulator: Accumulator::new(0),
-            key_store: KeyStore::new(),
-            // TODO: Would be nice if claim_accumulator gave us this value.
-            // But there is no api for that yet.
-            claim_quroum_size: None,
+            pending: Map::new(),
+            pending_per_source: Map::new(),
+            resolved: ExpiringCache::new(Duration::seconds(RESOLVED_EXPIRY_SECS),
+                                         MAX_RESOLVED_COUNT),
+            early_keys: ExpiringCache::new(Duration::seconds(EARLY_KEYS_EXPIRY_SECS),
+                                           MAX_EARLY_KEYS_COUNT),
+            close_group_check: None,
+            key_sink: None,
+            policies: PolicyTable::default(),
+            conflicts: Vec::new(),
+            metrics: PureSentinelMetrics::default(),
+            key_store: key_store,
+            weights: Weights::new(),
+            eviction_policy: EvictionPolicy::default(),
+            budget: ByteBudget::new(ClaimLimits::default()),
+            key_request_delay: Duration::milliseconds(KEY_REQUEST_DELAY_MILLIS),
+            max_key_request_delay: Duration::seconds(MAX_KEY_REQUEST_DELAY_SECS),
+            clock: clock::system_clock(),
+            recorder: None,
+            sequence: 0,
         }
     }
 
+    /// Replaces the weights of claimants and key senders. Quorums passed to `add_claim` and
+    /// `add_keys` are then thresholds on summed weight. By default every name carries a
+    /// weight of one.
+    pub fn set_weights(&mut self, weights: Weights<Name>) {
+        self.weights = weights;
+    }
+
+    /// Replaces the limits on pending requests. When a new request needs room, the pending
+    /// request with the least progress is evicted, ranked by the weight of its claimants
+    /// verified against confirmed keys before the number of its claimants. New requests of a
+    /// source holding `max_pending_per_source` already are dropped. Requests already pending
+    /// are only evicted once a new request needs room.
+    pub fn set_eviction_policy(&mut self, eviction_policy: EvictionPolicy) {
+        self.eviction_policy = eviction_policy;
+    }
+
+    /// Replaces the byte limits on held claims. While only the total would be exceeded, the
+    /// weakest other pending requests are evicted to make room for a claim, and otherwise it
+    /// is rejected. Claims already held are kept even if they exceed the new limits.
+    pub fn set_claim_limits(&mut self, claim_limits: ClaimLimits) {
+        self.budget.set_limits(claim_limits);
+    }
+
+    /// Replaces how long and how many resolved requests are remembered. Claims for a request
+    /// remembered are answered with `AddResult::AlreadyResolved` instead of starting a new
+    /// round of accumulation. Requests resolved so far are forgotten.
+    pub fn set_replay_window(&mut self, expiry: Duration, capacity: usize) {
+        self.resolved = ExpiringCache::new(expiry, capacity);
+    }
+
+    /// Replaces the clock the sentinel measures time with. Requests pending, resolved or
+    /// asked keys for so far keep the times they were stamped with by the previous clock, so
+    /// the clock is best set before the first claim.
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets the recorder every call to `add_claim`, `add_keys`, `add_request_keys` and
+    /// `expire_pending` is passed to, with its outcome. A recorded trace is fed back into a
+    /// fresh sentinel with `replay`.
+    pub fn set_recorder(&mut self, recorder: PureRecorder<Request, Name, Scheme>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Replaces the delay before missing keys are first requested again, and the bound the
+    /// delay doubles up to on each repeat. Applies to requests seen from now on.
+    pub fn set_key_request_backoff(&mut self, delay: Duration, max_delay: Duration) {
+        self.key_request_delay = delay;
+        self.max_key_request_delay = max_delay;
+    }
+
+    /// Sets the check `add_keys` uses to accept keys for requests not seen yet, and
+    /// `add_claim_by_authority` uses for policies accepting only the close group. It is called
+    /// with the source of the request and the sender of the keys or claim, and should return
+    /// true only if the sender is in the close group of the source. Without a check, such keys
+    /// and claims are ignored.
+    pub fn set_close_group_check(&mut self,
+                                 close_group_check: Box<Fn(&Name, &Name) -> bool + Send>) {
+        self.close_group_check = Some(close_group_check);
+    }
+
+    /// Sets the sink keys are asked for through: `get_group_key` for the source of a new
+    /// request and `get_client_key` for each claimant lacking a key. `add_claim` then no
+    /// longer returns `AddResult::RequestKeys` and `poll_key_requests` returns nothing, as
+    /// their key requests are sent through the sink.
+    pub fn set_key_sink(&mut self, sink: Box<SendGetKeys<Name> + Send>) {
+        self.key_sink = Some(KeySink::new(sink));
+    }
+
+    /// Replaces the quorum policies `add_claim_by_authority` and `add_keys_by_authority` use.
+    pub fn set_policies(&mut self, policies: PolicyTable) {
+        self.policies = policies;
+    }
+
     /// This adds a new claim for the provided request. The claimant name and
     /// the signature provided will be used to verify the claim with the keys
-    /// that are independently retrieved. When an added claim leads to the
+    /// that are independently retrieved. The signature has to be over the claim
+    /// bound to the request, as `bound_claim` encodes them. When an added claim leads to the
     /// resolution of the request, the request and the claim are returned.
     /// All resolved claims have to be identical. Otherwise None is returned.
     ///
@@ -97,140 +371,701 @@ impl<Request, Name>
     ///   that the claim has been successfully resolved.
     /// * Some(AddResult::RequestKeys(target)): indicating that the caller
     ///   should request public keys from the group surrounding the target.
-    /// * None: indicating that no resolve was possible yet.
+    /// * Some(AddResult::AlreadyResolved(request, serialised_claim)):
+    ///   indicating that the request resolved recently, to the claim given.
+    /// * Some(AddResult::Rejected(reason)): indicating that the claim was
+    ///   not stored because it would exceed the claim limits, even with the
+    ///   weakest other pending requests evicted.
+    /// * None: indicating that no resolve was possible yet, that keys sent
+    ///   ahead of the claim were applied, or that the claim was dropped
+    ///   because its source holds too many pending requests.
     pub fn add_claim(&mut self,
                      request: Request,
                      claimant: Name, // Node which sent the message
-                     signature: Signature,
+                     signature: Scheme::Signature,
                      claim: SerialisedClaim,
                      claim_quorum: usize,
                      key_quorum: usize)
                      -> Option<AddResult<Request, Name>> {
+        let call = self.recorder.as_ref().map(|_| {
+            PureCall::AddClaim(request.clone(), claimant.clone(), signature.clone(),
+                               claim.clone(), claim_quorum, key_quorum)
+        });
+        let result = self.accumulate_claim(request, claimant, signature, claim, claim_quorum,
+                                           key_quorum);
+        if let Some(call) = call {
+            self.record(&call, &PureOutcome::of_claim(&result));
+        }
+        result
+    }
 
-        let saw_first_time = !self.claim_accumulator.contains_key(&request);
+    fn accumulate_claim(&mut self,
+                        request: Request,
+                        claimant: Name,
+                        signature: Scheme::Signature,
+                        claim: SerialisedClaim,
+                        claim_quorum: usize,
+                        key_quorum: usize)
+                        -> Option<AddResult<Request, Name>> {
+        self.metrics.claims_received += 1;
+        let now = self.clock.now();
 
-        self.claim_quroum_size = Some(claim_quorum);
-        self.claim_accumulator.set_quorum_size(claim_quorum);
+        if let Some(resolved_claim) = self.resolved.get(&request, now).cloned() {
+            self.metrics.claims_already_resolved += 1;
+            return Some(AddResult::AlreadyResolved(request, resolved_claim));
+        }
 
-        self.claim_accumulator
-            .add(request.clone(), (claimant, signature, claim))
-            .and_then(|(request, claims)|
-                      self.resolve(request, claims, claim_quorum, key_quorum))
-            .map(|(request, serialised_claim)| {
-                AddResult::Resolved(request, serialised_claim)
-            }).or_else(|| {
-                if saw_first_time {
-                    Some(AddResult::RequestKeys(request.get_source()))
-                } else {
-                    None
-                }
-            })
+        let saw_first_time = !self.pending.contains_key(&request);
+
+        if saw_first_time && self.source_full(&request) {
+            self.metrics.claims_dropped += 1;
+            return None;
+        }
+
+        if let Err(rejection) = self.make_room(&request, claim.len()) {
+            self.metrics.claims_rejected.count(&rejection);
+            return Some(AddResult::Rejected(rejection));
+        }
+
+        if saw_first_time {
+            self.admit(&request);
+        }
+
+        self.sequence += 1;
+        self.budget.charge(claim.len());
+
+        let keys_buffered = saw_first_time && self.apply_early_keys(&request, key_quorum);
+        let bound = bound_claim(&serialise(&request), &claim);
+        let verified = self.verify_single_claim(&request, &claimant, &signature, &bound,
+                                                key_quorum);
+
+        let key_request_delay = self.key_request_delay;
+        let claims = {
+            let pending = self.pending.entry(request.clone()).or_insert_with(|| {
+                PendingRequest::new(claim_quorum, key_quorum, key_request_delay, now)
+            });
+            pending.claim_quorum = claim_quorum;
+            pending.key_quorum = key_quorum;
+            pending.last_seen = self.sequence;
+            let _ = pending.claimants.insert(claimant.clone());
+            if verified {
+                let _ = pending.verified.insert(claimant.clone());
+            }
+            pending.bytes += claim.len();
+            pending.claims.push((claimant, signature, claim));
+
+            if self.weights.total(pending.claimants.iter()) >= claim_quorum {
+                Some(pending.claims.clone())
+            } else {
+                None
+            }
+        };
+
+        let resolved = match claims {
+            Some(claims) => self.resolve(request.clone(), claims, claim_quorum, key_quorum),
+            None => None,
+        };
+
+        match resolved {
+            Some((request, serialised_claim)) =>
+                Some(AddResult::Resolved(request, serialised_claim)),
+            None if saw_first_time && !keys_buffered => self.request_keys(request.get_source()),
+            None => None,
+        }
     }
 
     /// This adds a new set of public_signing_keys for the provided request.
-    /// If the request is not known yet by pure sentinel, the added keys will be ignored.
+    /// If the request is not known yet by pure sentinel, the added keys are buffered until
+    /// its first claim arrives, for up to `EARLY_KEYS_EXPIRY_SECS`, provided the sender
+    /// passes the close group check. Otherwise,
+    /// or if the request has already resolved, the added keys will be ignored.
     /// When the added set of keys leads to the resolution of the request,
     /// the request and the verified and merged claim is returned.
     /// Otherwise None is returned.
     pub fn add_keys(&mut self,
                     request: Request,
                     sender: Name,
-                    keys: Vec<(Name, PublicKey)>,
+                    keys: Vec<(Name, Scheme::PublicKey)>,
                     key_quorum: usize)
                     -> Option<(Request, SerialisedClaim)> {
-        // We don't want to store keys for requests we haven't received yet because
-        // we couldn't have requested those keys. So someone is probably trying
-        // something silly.
-        if !self.claim_accumulator.contains_key(&request) {
-            return None;
+        let call = self.recorder.as_ref().map(|_| {
+            PureCall::AddKeys(request.clone(), sender.clone(), keys.clone(), key_quorum)
+        });
+        let result = self.apply_keys(request, sender, keys, key_quorum);
+        if let Some(call) = call {
+            self.record(&call, &PureOutcome::of_keys(&result));
         }
+        result
+    }
 
-        let claim_quorum = match self.claim_quroum_size {
-            Some(q) => q,
+    fn apply_keys(&mut self,
+                  request: Request,
+                  sender: Name,
+                  keys: Vec<(Name, Scheme::PublicKey)>,
+                  key_quorum: usize)
+                  -> Option<(Request, SerialisedClaim)> {
+        let (claims, claim_quorum) = match self.pending.get(&request) {
+            Some(pending) => (pending.claims.clone(), pending.claim_quorum),
             None => {
-                // Should have been assigned in `add_claim` function and we know
-                // it was called due to the above check.
-                debug_assert!(false);
+                self.buffer_early_keys(request, sender, keys);
                 return None;
             }
         };
 
-        for (target, public_key) in keys {
-            self.key_store.add_key(target, sender.clone(), public_key);
+        let targets = keys.iter().map(|&(ref target, _)| target.clone()).collect();
+        {
+            let mut key_store = self.key_store.lock();
+            for (target, public_key) in keys {
+                key_store.add_key(target, sender.clone(), public_key);
+            }
+        }
+        self.answer_confirmed(&request, targets, key_quorum);
+
+        self.resolve(request, claims, claim_quorum, key_quorum)
+    }
+
+    /// This adds keys confirmed for `request` alone, such as the identities of its source
+    /// group confirmed by a `KeySentinel`. They verify the claims of the request without a
+    /// key quorum, aren't added to the key store, and are dropped with the request, so that
+    /// whoever confirmed them can't vouch for the same names in other requests. Keys for a
+    /// request that is not pending are ignored. Returns the resolution as `add_keys` does.
+    pub fn add_request_keys(&mut self,
+                            request: Request,
+                            keys: Vec<(Name, Scheme::PublicKey)>)
+                            -> Option<(Request, SerialisedClaim)> {
+        let call = self.recorder.as_ref().map(|_| {
+            PureCall::AddRequestKeys(request.clone(), keys.clone())
+        });
+        let result = self.apply_request_keys(request, keys);
+        if let Some(call) = call {
+            self.record(&call, &PureOutcome::of_keys(&result));
+        }
+        result
+    }
+
+    fn apply_request_keys(&mut self,
+                          request: Request,
+                          keys: Vec<(Name, Scheme::PublicKey)>)
+                          -> Option<(Request, SerialisedClaim)> {
+        let (claims, claim_quorum, key_quorum) = match self.pending.get_mut(&request) {
+            Some(pending) => {
+                for (target, public_key) in keys {
+                    pending.keys.entry(target).or_insert_with(Vec::new).push(public_key);
+                }
+                (pending.claims.clone(), pending.claim_quorum, pending.key_quorum)
+            }
+            None => return None,
+        };
+
+        self.resolve(request, claims, claim_quorum, key_quorum)
+    }
+
+    /// Returns the claimants of a pending request for whom no key has been accumulated yet.
+    /// Their claims can't be verified until keys for them are added.
+    pub fn missing_keys(&mut self, request: &Request) -> Vec<Name> {
+        let (claimants, key_quorum) = match self.pending.get(request) {
+            Some(pending) => (pending.claimants.clone(), pending.key_quorum),
+            None => return Vec::new(),
+        };
+
+        claimants.into_iter().filter(|claimant| {
+            self.confirmed_keys(request, claimant, key_quorum).is_empty()
+        }).collect()
+    }
+
+    /// Returns the pending requests due to have their missing keys requested again, together
+    /// with the claimants lacking keys, or sends their key requests through the sink. The
+    /// delay before a request is due again doubles each time, up to the bound set with
+    /// `set_key_request_backoff`.
+    pub fn poll_key_requests(&mut self) -> Vec<(Request, Vec<Name>)> {
+        let now = self.clock.now();
+        let due = self.pending.iter()
+                              .filter(|&(_, pending)| pending.next_key_request <= now)
+                              .map(|(request, _)| request.clone())
+                              .collect::<Vec<_>>();
+
+        let max_delay = self.max_key_request_delay;
+        let key_requests = due.into_iter().filter_map(|request| {
+            if let Some(pending) = self.pending.get_mut(&request) {
+                pending.next_key_request = now + pending.key_request_delay;
+                pending.key_request_delay = cmp::min(pending.key_request_delay * 2, max_delay);
+            }
+
+            let missing = self.missing_keys(&request);
+            if missing.is_empty() { None } else { Some((request, missing)) }
+        }).collect::<Vec<_>>();
+
+        match self.key_sink {
+            Some(ref mut key_sink) => {
+                for (_, missing) in key_requests {
+                    for claimant in missing {
+                        let _ = key_sink.send(KeyRequest::Client(claimant), now);
+                    }
+                }
+                Vec::new()
+            }
+            None => key_requests,
+        }
+    }
+
+    /// Returns the number of requests currently awaiting resolution.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns true if claims for `request` are held awaiting resolution.
+    pub fn is_pending(&self, request: &Request) -> bool {
+        self.pending.contains_key(request)
+    }
+
+    /// Returns the number of bytes of serialised claims currently held.
+    pub fn pending_bytes(&self) -> usize {
+        self.budget.total()
+    }
+
+    /// Drops the requests whose first claim arrived more than `max_age` ago without them
+    /// resolving, and returns them.
+    pub fn expire_pending(&mut self, max_age: Duration) -> Vec<Request> {
+        let now = self.clock.now();
+        let expired = self.pending.iter()
+                                  .filter(|&(_, pending)| now - pending.first_seen > max_age)
+                                  .map(|(request, _)| request.clone())
+                                  .collect::<Vec<_>>();
+        for request in expired.iter() {
+            self.remove_pending(request);
+        }
+        self.metrics.expirations += expired.len() as u64;
+        if self.recorder.is_some() {
+            self.record(&PureCall::ExpirePending(max_age.num_milliseconds()),
+                        &PureOutcome::Expired(expired.clone()));
+        }
+        expired
+    }
+
+    /// Feeds the calls of `trace` into the sentinel, which should be fresh and configured as
+    /// the recorded one was, and returns the calls whose outcome differs from the recorded
+    /// one. The sentinel is given a manual clock, advanced to the time of each call.
+    pub fn replay(&mut self,
+                  trace: Vec<TraceEntry<PureTraceCall<Request, Name, Scheme>,
+                                        PureOutcome<Request, Name>>>)
+                  -> Vec<Divergence<PureOutcome<Request, Name>>> {
+        let clock = ManualClock::new();
+        self.set_clock(Arc::new(clock.clone()));
+        let mut elapsed = 0;
+        trace::diverging(trace, |at_millis, call| {
+            clock.advance(Duration::milliseconds(at_millis - elapsed));
+            elapsed = at_millis;
+            match call {
+                PureCall::AddClaim(request, claimant, signature, claim, claim_quorum,
+                                   key_quorum) => {
+                    PureOutcome::of_claim(&self.add_claim(request, claimant, signature, claim,
+                                                          claim_quorum, key_quorum))
+                }
+                PureCall::AddKeys(request, sender, keys, key_quorum) =>
+                    PureOutcome::of_keys(&self.add_keys(request, sender, keys, key_quorum)),
+                PureCall::AddRequestKeys(request, keys) =>
+                    PureOutcome::of_keys(&self.add_request_keys(request, keys)),
+                PureCall::ExpirePending(max_age_millis) => {
+                    PureOutcome::Expired(self.expire_pending(
+                        Duration::milliseconds(max_age_millis)))
+                }
+            }
+        })
+    }
+
+    /// Returns the counters of claims, resolutions, forks and evictions, with a histogram of
+    /// the time requests took to resolve, the requests and bytes currently held and the
+    /// activity of the key store.
+    pub fn metrics(&self) -> PureSentinelMetrics {
+        PureSentinelMetrics {
+            pending_requests: self.pending.len(),
+            pending_bytes: self.budget.total(),
+            key_store: self.key_store.lock().metrics(),
+            ..self.metrics.clone()
+        }
+    }
+
+    /// Returns the conflicts recorded since the last call, oldest first. When a request
+    /// resolves, each claimant whose verified claim differs from the resolved one is recorded
+    /// as a `Conflict`, up to `MAX_CONFLICT_COUNT` held.
+    pub fn take_conflicts(&mut self) -> Vec<Conflict<Request, Name, Scheme::Signature>> {
+        ::std::mem::replace(&mut self.conflicts, Vec::new())
+    }
+
+    fn record(&mut self,
+              call: &PureTraceCall<Request, Name, Scheme>,
+              outcome: &PureOutcome<Request, Name>) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record(call, outcome);
+        }
+    }
+
+    // Asks for the keys of the group surrounding `source`, through the sink if there is one.
+    fn request_keys(&mut self, source: Name) -> Option<AddResult<Request, Name>> {
+        match self.key_sink {
+            Some(ref mut key_sink) => {
+                let _ = key_sink.send(KeyRequest::Group(source), self.clock.now());
+                None
+            }
+            None => Some(AddResult::RequestKeys(source)),
+        }
+    }
+
+    // Tells the key sink that the keys of `targets` confirmed by `key_quorum` have been
+    // answered, and the group of `request` once all of them are. Until then the requests stay
+    // outstanding, so that further claims don't ask again while responses are arriving.
+    fn answer_confirmed(&mut self, request: &Request, targets: Vec<Name>, key_quorum: usize) {
+        if self.key_sink.is_none() {
+            return;
+        }
+
+        let confirmed = {
+            let mut key_store = self.key_store.lock();
+            targets.iter().filter(|target| {
+                !key_store.get_weighted_keys(target, &self.weights, key_quorum).is_empty()
+            }).cloned().collect::<Vec<_>>()
+        };
+
+        if let Some(ref mut key_sink) = self.key_sink {
+            if confirmed.len() == targets.len() {
+                key_sink.answered(&KeyRequest::Group(request.get_source()));
+            }
+            for target in confirmed {
+                key_sink.answered(&KeyRequest::Client(target));
+            }
+        }
+    }
+
+    // We don't want to store keys for requests we haven't received yet unless they come
+    // from the close group of the source, or someone is probably trying something silly.
+    fn buffer_early_keys(&mut self,
+                         request: Request,
+                         sender: Name,
+                         keys: Vec<(Name, Scheme::PublicKey)>) {
+        let now = self.clock.now();
+        if self.resolved.check(&request, now) {
+            return;
+        }
+
+        if !self.in_close_group(&request.get_source(), &sender).unwrap_or(false) {
+            return;
+        }
+
+        let buffered = self.early_keys.get_or_add_with(request, now, Vec::new);
+        if buffered.len() < MAX_EARLY_KEYS_PER_REQUEST &&
+           !buffered.iter().any(|&(ref buffered_sender, _)| *buffered_sender == sender) {
+            buffered.push((sender, keys));
+        }
+    }
+
+    // Tells whether `sender` is in the close group of `source`, if a check is set.
+    fn in_close_group(&self, source: &Name, sender: &Name) -> Option<bool> {
+        self.close_group_check.as_ref().map(|close_group_check| close_group_check(source, sender))
+    }
+
+    // Adds the keys buffered for `request` to the key store. Returns true if they came from
+    // senders weighing at least `key_quorum`, so that they can confirm keys without asking.
+    fn apply_early_keys(&mut self, request: &Request, key_quorum: usize) -> bool {
+        match self.early_keys.remove(request, self.clock.now()) {
+            Some(buffered) => {
+                let senders_weight = self.weights.total(buffered.iter().map(|keys| &keys.0));
+                let mut key_store = self.key_store.lock();
+                for (sender, keys) in buffered {
+                    for (target, public_key) in keys {
+                        key_store.add_key(target, sender.clone(), public_key);
+                    }
+                }
+                senders_weight >= key_quorum
+            }
+            None => false,
+        }
+    }
+
+    // Tells whether the source of `request` already holds as many pending requests as the
+    // eviction policy allows.
+    fn source_full(&self, request: &Request) -> bool {
+        self.pending_per_source.get(&request.get_source()).map_or(false, |count| {
+            *count >= self.eviction_policy.max_pending_per_source
+        })
+    }
+
+    // Checks a claim of `claim_size` bytes for `request` against the claim limits. While
+    // only the total is exceeded, the weakest other pending requests are evicted to make
+    // room, so that requests nobody completes can't hold the budget.
+    fn make_room(&mut self, request: &Request, claim_size: usize) -> Result<(), Rejection> {
+        let request_bytes = self.pending.get(request).map_or(0, |pending| pending.bytes);
+        loop {
+            match self.budget.check(claim_size, request_bytes) {
+                Err(Rejection::TotalBytesExceeded) => {
+                    match self.weakest_pending(Some(request)) {
+                        Some(victim) => self.evict(&victim),
+                        None => return Err(Rejection::TotalBytesExceeded),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Makes room for a request seen for the first time, evicting the weakest pending
+    // requests while the eviction policy holds no more.
+    fn admit(&mut self, request: &Request) {
+        while self.pending.len() >= self.eviction_policy.max_pending {
+            match self.weakest_pending(None) {
+                Some(victim) => self.evict(&victim),
+                None => break,
+            }
+        }
+
+        *self.pending_per_source.entry(request.get_source()).or_insert(0) += 1;
+    }
+
+    // The pending request with the least progress, other than `spared`.
+    fn weakest_pending(&self, spared: Option<&Request>) -> Option<Request> {
+        eviction::weakest(self.pending.iter()
+                                      .filter(|&(request, _)| Some(request) != spared)
+                                      .map(|(request, pending)| {
+                                          (request, pending.progress(&self.weights),
+                                           pending.last_seen)
+                                      })).cloned()
+    }
+
+    fn evict(&mut self, request: &Request) {
+        self.remove_pending(request);
+        self.metrics.evictions += 1;
+    }
+
+    fn remove_pending(&mut self, request: &Request) {
+        match self.pending.remove(request) {
+            Some(pending) => self.budget.release(pending.bytes),
+            None => return,
         }
 
-        self.claim_accumulator.get(&request)
-            .and_then(|(request, claims)| {
-                self.resolve(request, claims, claim_quorum, key_quorum)
-            })
+        let source = request.get_source();
+        let source_done = match self.pending_per_source.get_mut(&source) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+
+        if source_done {
+            let _ = self.pending_per_source.remove(&source);
+        }
     }
 
     /// Verify is only concerned with checking the signatures of the serialised claims.
     /// To achieve this it pairs up a set of signed claims and a set of public signing keys.
+    /// Signatures are over the claims bound to `request`, as `bound_claim` encodes them.
+    /// Only the first verified claim of each claimant is kept, so that a claimant's
+    /// weight is counted once.
     fn verify(&mut self,
-              claims: &Vec<(Name, Signature, SerialisedClaim)>,
+              request: &Request,
+              claims: &Vec<(Name, Scheme::Signature, SerialisedClaim)>,
               key_quorum: usize)
-              -> Vec<SerialisedClaim> {
+              -> Vec<(Name, SerialisedClaim)> {
+        let serialised_request = serialise(request);
+        let mut claimants = Set::new();
         claims.iter().filter_map(|&(ref name, ref signature, ref body)| {
-                self.verify_single_claim(name, signature, body, key_quorum)
+                if claimants.contains(name) {
+                    return None;
+                }
+                let bound = bound_claim(&serialised_request, body);
+                if self.verify_single_claim(request, name, signature, &bound, key_quorum) {
+                    let _ = claimants.insert(name.clone());
+                    Some((name.clone(), body.clone()))
+                } else {
+                    None
+                }
             }).collect()
     }
 
     fn verify_single_claim(&mut self,
+                           request: &Request,
                            name: &Name,
-                           signature: &Signature,
-                           body: &SerialisedClaim,
+                           signature: &Scheme::Signature,
+                           bound: &SerialisedClaim,
                            key_quorum: usize)
-                           -> Option<SerialisedClaim> {
-        for public_key in self.key_store.get_accumulated_keys(&name, key_quorum) {
-            match super::verify_signature(&signature, &public_key, &body) {
-                Some(body) => return Some(body),
-                None => continue,
+                           -> bool {
+        for public_key in self.confirmed_keys(request, name, key_quorum) {
+            self.metrics.signature_verifications += 1;
+            if super::verify_signature::<Scheme>(&signature, &public_key, &bound).is_some() {
+                return true;
             }
         }
-        None
+        false
+    }
+
+    // The keys of `name` reaching `key_quorum` in the key store, with those added for
+    // `request` only.
+    fn confirmed_keys(&self,
+                      request: &Request,
+                      name: &Name,
+                      key_quorum: usize)
+                      -> Vec<Scheme::PublicKey> {
+        let mut public_keys = self.key_store.lock().get_weighted_keys(name, &self.weights,
+                                                                       key_quorum);
+        if let Some(keys) = self.pending.get(request).and_then(|pending| pending.keys.get(name)) {
+            public_keys.extend(keys.iter().cloned());
+        }
+        public_keys
+    }
+
+    // Counts the claimants with confirmed keys none of whose claims verified against them.
+    fn count_bad_signatures(&mut self,
+                            request: &Request,
+                            claims: &Vec<(Name, Scheme::Signature, SerialisedClaim)>,
+                            verified_claims: &Vec<(Name, SerialisedClaim)>,
+                            key_quorum: usize) {
+        let verified = verified_claims.iter().map(|&(ref name, _)| name).collect::<Set<_>>();
+        let unverified = claims.iter()
+                               .map(|&(ref name, _, _)| name)
+                               .filter(|name| !verified.contains(name))
+                               .collect::<Set<_>>();
+        let bad = unverified.into_iter().filter(|name| {
+            !self.confirmed_keys(request, name, key_quorum).is_empty()
+        }).count();
+        self.metrics.claims_rejected.bad_signature += bad as u64;
     }
 
     fn squash(&self,
-              verified_claims: Vec<SerialisedClaim>,
+              verified_claims: Vec<(Name, SerialisedClaim)>,
               quorum_size: usize)
               -> Option<SerialisedClaim> {
-        if verified_claims.len() < quorum_size {
+        if self.verified_weight(&verified_claims) < quorum_size {
             // Can't squash: not enough claims.
             return None;
         }
 
         let mut frequency = Frequency::new();
 
-        for verified_claim in verified_claims {
-            frequency.update(&verified_claim)
+        for (claimant, verified_claim) in verified_claims {
+            frequency.update_weighted(&verified_claim, self.weights.get(&claimant))
         }
 
-        let mut iter = frequency.sort_by_highest().into_iter()
-            .filter(|&(_, ref count)| *count >= quorum_size)
-            .map(|(resolved_claim, _)| resolved_claim);
+        let agreed = frequency.at_least(quorum_size);
+        let mut iter = agreed.iter().map(|&(resolved_claim, _)| resolved_claim);
 
-        let retval = iter.next().map(|a| a.clone());
+        // Claimants may split their weight so that no claim reaches the quorum.
+        let retval = iter.next().cloned();
 
-        // In debug mode we expect no adversaries.
-        debug_assert!(retval.is_some(),      "Frequency returned less than one result");
+        // Two claims can only both reach a quorum of at most half the verified weight.
         debug_assert!(iter.next().is_none(), "Frequency returned more than one result");
 
         retval
     }
 
+    fn verified_weight(&self, verified_claims: &Vec<(Name, SerialisedClaim)>) -> usize {
+        self.weights.total(verified_claims.iter().map(|&(ref claimant, _)| claimant))
+    }
+
     fn resolve(&mut self,
                request: Request,
-               claims: Vec<(Name, Signature, SerialisedClaim)>,
+               claims: Vec<(Name, Scheme::Signature, SerialisedClaim)>,
                claim_quorum: usize,
                key_quorum: usize)
                -> Option<(Request, SerialisedClaim)> {
-        let verified_claims = self.verify(&claims, key_quorum);
-        self.squash(verified_claims, claim_quorum)
-            .map(|c| {
-                self.claim_accumulator.delete(&request);
-                (request, c)
-            })
+        let verified_claims = self.verify(&request, &claims, key_quorum);
+
+        if let Some(pending) = self.pending.get_mut(&request) {
+            pending.verified = verified_claims.iter().map(|&(ref name, _)| name.clone()).collect();
+        }
+
+        match self.squash(verified_claims.clone(), claim_quorum) {
+            Some(claim) => {
+                let now = self.clock.now();
+                if let Some(pending) = self.pending.get(&request) {
+                    self.metrics.time_to_resolve.observe(now - pending.first_seen);
+                }
+                self.metrics.resolutions += 1;
+                self.metrics.claims_verified += verified_claims.len() as u64;
+                self.count_bad_signatures(&request, &claims, &verified_claims, key_quorum);
+                self.remove_pending(&request);
+                self.record_conflicts(&request, &claims, verified_claims, &claim);
+                self.resolved.add(request.clone(), claim.clone(), now);
+                Some((request, claim))
+            }
+            None => None,
+        }
+    }
+
+    fn record_conflicts(&mut self,
+                        request: &Request,
+                        claims: &Vec<(Name, Scheme::Signature, SerialisedClaim)>,
+                        verified_claims: Vec<(Name, SerialisedClaim)>,
+                        resolved_claim: &SerialisedClaim) {
+        for (claimant, claim) in verified_claims {
+            if claim == *resolved_claim {
+                continue;
+            }
+
+            self.metrics.forks += 1;
+            if self.conflicts.len() >= MAX_CONFLICT_COUNT {
+                continue;
+            }
+
+            let signature = claims.iter()
+                                  .find(|&&(ref name, _, ref body)| {
+                                      *name == claimant && *body == claim
+                                  })
+                                  .map(|&(_, ref signature, _)| signature.clone());
+
+            if let Some(signature) = signature {
+                self.conflicts.push(Conflict {
+                    request: request.clone(),
+                    claimant: claimant,
+                    signature: signature,
+                    claim: claim,
+                    resolved_claim: resolved_claim.clone(),
+                });
+            }
+        }
+    }
+}
+
+impl<Request, Name, Scheme>
+    PureSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name> + GetAuthority,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme {
+    /// This adds a new claim as `add_claim` does, with the quorums given by the policy of
+    /// the authority of the request. If the policy only accepts claims from the close group
+    /// of the source, claims from other claimants are dropped and None is returned. Without a
+    /// close group check set no claimant can be shown to be in the close group, so all claims
+    /// under such a policy are dropped. A client signs its requests on its own, so claims for
+    /// requests of `Authority::Client` are dropped unless the claimant is the source.
+    pub fn add_claim_by_authority(&mut self,
+                                  request: Request,
+                                  claimant: Name,
+                                  signature: Scheme::Signature,
+                                  claim: SerialisedClaim)
+                                  -> Option<AddResult<Request, Name>> {
+        let authority = request.get_authority();
+        let policy = self.policies.get(authority);
+
+        if authority == Authority::Client && claimant != request.get_source() {
+            return None;
+        }
+
+        if policy.close_group_only &&
+           !self.in_close_group(&request.get_source(), &claimant).unwrap_or(false) {
+            return None;
+        }
+
+        self.add_claim(request, claimant, signature, claim, policy.claim_quorum,
+                       policy.key_quorum)
+    }
+
+    /// This adds a new set of public signing keys as `add_keys` does, with the key quorum
+    /// given by the policy of the authority of the request.
+    pub fn add_keys_by_authority(&mut self,
+                                 request: Request,
+                                 sender: Name,
+                                 keys: Vec<(Name, Scheme::PublicKey)>)
+                                 -> Option<(Request, SerialisedClaim)> {
+        let key_quorum = self.policies.get(request.get_authority()).key_quorum;
+        self.add_keys(request, sender, keys, key_quorum)
     }
 }
 
@@ -240,14 +1075,23 @@ mod test {
     extern crate rustc_serialize;
     use super::*;
 
-    use rand::random;
+    use testing::{random, random_keypair, reset_random, seeded_keypair, sign_claim, MockScheme,
+                  TraceGetKeys};
     use sodiumoxide::crypto;
+    use authority::{GetAuthority, PolicyTable, QuorumPolicy};
+    use clock::ManualClock;
+    use eviction::EvictionPolicy;
+    use limits::{ClaimLimits, Rejection};
+    use statistics::Weights;
+    use send_get_keys::KeyRequest;
+    use std::sync::{Arc, Mutex};
+    use time::Duration;
     use SerialisedClaim;
 
     const NAMESIZE: usize = 64;
     const QUORUM: usize = 10;
 
-    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
     pub struct TestName {
         pub data: Vec<u8>,
     }
@@ -260,7 +1104,7 @@ mod test {
         TestName { data: arr.to_vec() }
     }
 
-    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
     struct TestRequest {
         core: usize,
         name: TestName,
@@ -291,6 +1135,7 @@ mod test {
 
     #[test]
     fn one_request_and_one_key() {
+        reset_random();
         let quorum_size = 1usize;
         let mut name_key_pairs = Vec::new();
         let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
@@ -298,8 +1143,8 @@ mod test {
         let request = TestRequest::new(random::<usize>(), name.clone());
         let claim = TestClaim { value: random::<usize>() };
         let serialised_claim = claim.serialise();
-        let key_pair = crypto::sign::gen_keypair();
-        let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+        let key_pair = random_keypair();
+        let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
         let climant_name = generate_random_name();
         name_key_pairs.push((climant_name.clone(), key_pair.0.clone()));
 
@@ -311,7 +1156,7 @@ mod test {
                     assert_eq!(request.get_source(), source_name);
                     Some(source_name)
                 }
-                AddResult::Resolved(_, _) => None
+                _ => None
             }).is_some());
 
         // One key is required should pass
@@ -327,13 +1172,14 @@ mod test {
 
     #[test]
     fn request_and_its_duplicate_added() {
+        reset_random();
         let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
         let name = generate_random_name();
         let request = TestRequest::new(random::<usize>(), name.clone());
         let claim = TestClaim { value: random::<usize>() };
         let serialised_claim = claim.serialise();
-        let key_pair = crypto::sign::gen_keypair();
-        let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+        let key_pair = random_keypair();
+        let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
         let climant_name = generate_random_name();
 
         // first claim added should return AddResult::RequestKeys
@@ -342,7 +1188,7 @@ mod test {
             .and_then(|result| match result {
                 AddResult::RequestKeys(source_name) => {
                      assert_eq!(request.get_source(), source_name); Some(source_name) },
-                AddResult::Resolved(_, _) => None
+                _ => None
             }).is_some());
 
         // same claim added for the second time none to be returned
@@ -352,6 +1198,7 @@ mod test {
 
     #[test]
     fn threshold_claims_requests_added_with_no_keys() {
+        reset_random();
         let mut name_key_pairs = Vec::new();
         let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
         let name = generate_random_name();
@@ -359,8 +1206,8 @@ mod test {
         let claim = TestClaim { value: random::<usize>() };
         let serialised_claim = claim.serialise();
         for index in 0..QUORUM {
-            let key_pair = crypto::sign::gen_keypair();
-            let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+            let key_pair = random_keypair();
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
             let climant_name = generate_random_name();
             name_key_pairs.push((climant_name.clone(), key_pair.0.clone()));
             assert!(pure_sentinel.add_claim(request.clone(), climant_name, signature.clone(),
@@ -371,13 +1218,14 @@ mod test {
                                                              assert_eq!(index, 0usize);
                                                              true
                                                             },
-                    AddResult::Resolved(_, _) => false
+                    _ => false
                 }));
         }
     }
 
     #[test]
     fn requests_added_with_various_key_size() {
+        reset_random();
         let mut name_key_pairs = Vec::new();
         let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
         let name = generate_random_name();
@@ -385,8 +1233,8 @@ mod test {
         let claim = TestClaim { value: random::<usize>() };
         let serialised_claim = claim.serialise();
         for index in 0..QUORUM {
-            let key_pair = crypto::sign::gen_keypair();
-            let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+            let key_pair = random_keypair();
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
             let climant_name = generate_random_name();
             name_key_pairs.push((climant_name.clone(), key_pair.0.clone()));
             assert!(pure_sentinel.add_claim(request.clone(), climant_name, signature.clone(),
@@ -397,7 +1245,7 @@ mod test {
                         assert_eq!(index, 0usize);
                         true
                     },
-                    AddResult::Resolved(_, _) => false
+                    _ => false
                 }));
         }
 
@@ -419,4 +1267,664 @@ mod test {
         assert!(pure_sentinel.add_keys(request, generate_random_name(), name_key_pairs,
                                        QUORUM).is_none());
     }
+
+    #[test]
+    fn near_complete_request_survives_multi_claimant_flood() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        pure_sentinel.set_eviction_policy(EvictionPolicy { max_pending: QUORUM,
+                                                           max_pending_per_source: QUORUM });
+        let source = generate_random_name();
+        let claimants = (0..QUORUM).map(|_| (generate_random_name(), random_keypair()))
+                                   .collect::<Vec<_>>();
+        let keys = claimants.iter()
+                            .map(|&(ref name, ref key_pair)| (name.clone(), key_pair.0))
+                            .collect::<Vec<_>>();
+        let add_claims = |pure_sentinel: &mut PureSentinel<TestRequest, TestName>,
+                          request: &TestRequest,
+                          claimants: &[(TestName, (crypto::sign::PublicKey,
+                                                   crypto::sign::SecretKey))],
+                          claim: &SerialisedClaim| {
+            claimants.iter().filter_map(|&(ref name, ref key_pair)| {
+                let signature = sign_claim(request, claim, &key_pair.1);
+                pure_sentinel.add_claim(request.clone(), name.clone(), signature, claim.clone(),
+                                        QUORUM, 1)
+            }).last()
+        };
+
+        // An earlier request of the group confirms the keys of its members.
+        let earlier = TestRequest::new(0, source.clone());
+        let claim = TestClaim { value: random::<usize>() }.serialise();
+        let _ = add_claims(&mut pure_sentinel, &earlier, &claimants, &claim);
+        assert!(pure_sentinel.add_keys(earlier, generate_random_name(), keys, 1).is_some());
+
+        // The next request is one claim short of its quorum.
+        let request = TestRequest::new(1, source);
+        let _ = add_claims(&mut pure_sentinel, &request, &claimants[1..], &claim);
+
+        // Junk requests with as many made-up claimants, from as many sources, can't show any
+        // verified claim, so they only evict one another.
+        for _ in 0..10 * QUORUM {
+            let junk = TestRequest::new(random::<usize>(), generate_random_name());
+            let forgers = (1..QUORUM).map(|_| (generate_random_name(), random_keypair()))
+                                     .collect::<Vec<_>>();
+            let _ = add_claims(&mut pure_sentinel, &junk, &forgers, &claim);
+            assert!(pure_sentinel.pending_count() <= QUORUM);
+        }
+        assert_eq!(pure_sentinel.metrics().evictions, 9 * QUORUM as u64 + 1);
+
+        match add_claims(&mut pure_sentinel, &request, &claimants[..1], &claim) {
+            Some(AddResult::Resolved(resolved, resolved_claim)) => {
+                assert_eq!(resolved, request);
+                assert_eq!(resolved_claim, claim);
+            }
+            _ => panic!("expected the request to resolve"),
+        }
+    }
+
+    #[test]
+    fn pending_requests_capped_per_source() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        pure_sentinel.set_eviction_policy(EvictionPolicy { max_pending: 100,
+                                                           max_pending_per_source: 2 });
+        let source = generate_random_name();
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let key_pair = random_keypair();
+        let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+
+        for index in 0..4 {
+            let request = TestRequest::new(index, source.clone());
+            let result = pure_sentinel.add_claim(request, generate_random_name(),
+                                                 signature.clone(), serialised_claim.clone(),
+                                                 QUORUM, QUORUM);
+            assert_eq!(result.is_some(), index < 2);
+        }
+
+        assert_eq!(pure_sentinel.pending_count(), 2);
+
+        // Other sources are unaffected.
+        let request = TestRequest::new(0, generate_random_name());
+        assert!(pure_sentinel.add_claim(request, generate_random_name(), signature,
+                                        serialised_claim, QUORUM, QUORUM).is_some());
+        assert_eq!(pure_sentinel.pending_count(), 3);
+    }
+
+    #[test]
+    fn claims_over_byte_limits_rejected() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        pure_sentinel.set_claim_limits(ClaimLimits { max_claim_size: 10,
+                                                     max_request_bytes: 20,
+                                                     max_total_bytes: 30 });
+        let key_pair = random_keypair();
+        let rejection = |result: Option<AddResult<TestRequest, TestName>>| match result {
+            Some(AddResult::Rejected(rejection)) => Some(rejection),
+            _ => None,
+        };
+
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let large_claim = vec![0u8; 11];
+        let signature = sign_claim(&request, &large_claim, &key_pair.1);
+        assert_eq!(rejection(pure_sentinel.add_claim(request.clone(), generate_random_name(),
+                                                     signature, large_claim, QUORUM, QUORUM)),
+                   Some(Rejection::ClaimTooLarge));
+        assert_eq!(pure_sentinel.pending_count(), 0);
+
+        let claim = vec![0u8; 10];
+        let signature = sign_claim(&request, &claim, &key_pair.1);
+        for _ in 0..2 {
+            assert!(rejection(pure_sentinel.add_claim(request.clone(), generate_random_name(),
+                                                      signature.clone(), claim.clone(),
+                                                      QUORUM, QUORUM)).is_none());
+        }
+        assert_eq!(rejection(pure_sentinel.add_claim(request.clone(), generate_random_name(),
+                                                     signature.clone(), claim.clone(),
+                                                     QUORUM, QUORUM)),
+                   Some(Rejection::RequestBytesExceeded));
+
+        // A request alone over the total limit has nothing to make room with.
+        pure_sentinel.set_claim_limits(ClaimLimits { max_claim_size: 10,
+                                                     max_request_bytes: 40,
+                                                     max_total_bytes: 30 });
+        assert!(rejection(pure_sentinel.add_claim(request.clone(), generate_random_name(),
+                                                  signature.clone(), claim.clone(),
+                                                  QUORUM, QUORUM)).is_none());
+        assert_eq!(rejection(pure_sentinel.add_claim(request, generate_random_name(),
+                                                     signature, claim, QUORUM, QUORUM)),
+                   Some(Rejection::TotalBytesExceeded));
+        assert_eq!(pure_sentinel.pending_bytes(), 30);
+    }
+
+    #[test]
+    fn byte_pressure_evicts_weakest() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        pure_sentinel.set_claim_limits(ClaimLimits { max_claim_size: 10,
+                                                     max_request_bytes: 20,
+                                                     max_total_bytes: 40 });
+        let key_pair = random_keypair();
+        let junk = vec![0u8; 10];
+        let junk_signature = crypto::sign::sign_detached(&junk, &key_pair.1);
+
+        // Junk requests fill the whole budget.
+        let junk_requests = (0..4).map(|_| {
+            TestRequest::new(random::<usize>(), generate_random_name())
+        }).collect::<Vec<_>>();
+        for junk_request in junk_requests.iter() {
+            assert!(pure_sentinel.add_claim(junk_request.clone(), generate_random_name(),
+                                            junk_signature.clone(), junk.clone(), 2, 1)
+                                 .is_some());
+        }
+        assert_eq!(pure_sentinel.pending_bytes(), 40);
+
+        // A genuine request still gets its claims held, at the expense of the oldest junk.
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let claim = vec![1u8; 10];
+        let mut keys = Vec::new();
+        for _ in 0..2 {
+            let claimant = random_keypair();
+            let name = generate_random_name();
+            let signature = sign_claim(&request, &claim, &claimant.1);
+            match pure_sentinel.add_claim(request.clone(), name.clone(), signature,
+                                          claim.clone(), 2, 1) {
+                Some(AddResult::Rejected(_)) => panic!("expected junk to be evicted"),
+                _ => (),
+            }
+            keys.push((name, claimant.0));
+        }
+        assert_eq!(pure_sentinel.pending_bytes(), 40);
+        assert!(!pure_sentinel.is_pending(&junk_requests[0]));
+        assert!(!pure_sentinel.is_pending(&junk_requests[1]));
+        assert_eq!(pure_sentinel.metrics().evictions, 2);
+
+        assert_eq!(pure_sentinel.add_keys(request.clone(), generate_random_name(), keys, 1),
+                   Some((request, claim)));
+    }
+
+    #[test]
+    fn weighted_claimants_resolve() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let mut weights = Weights::new();
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let mut claimants = Vec::new();
+
+        // Two established claimants carry the weight of a whole quorum.
+        for _ in 0..2 {
+            let key_pair = random_keypair();
+            let climant_name = generate_random_name();
+            weights.set(climant_name.clone(), QUORUM / 2);
+            claimants.push((climant_name, key_pair));
+        }
+
+        // Key senders only carry their default weight of one.
+        pure_sentinel.set_weights(weights);
+
+        for &(ref climant_name, ref key_pair) in claimants.iter() {
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+            let _ = pure_sentinel.add_claim(request.clone(), climant_name.clone(), signature,
+                                            serialised_claim.clone(), QUORUM, 2);
+        }
+
+        let keys = claimants.iter()
+                            .map(|&(ref name, ref key_pair)| (name.clone(), key_pair.0.clone()))
+                            .collect::<Vec<_>>();
+
+        assert!(pure_sentinel.add_keys(request.clone(), generate_random_name(),
+                                       keys.clone(), 2).is_none());
+        assert!(pure_sentinel.add_keys(request.clone(), generate_random_name(), keys, 2)
+            .and_then(|result| { assert_eq!(result.1, serialised_claim);
+                                 assert_eq!(result.0, request);
+                                 Some(result)
+            }).is_some());
+    }
+
+    #[test]
+    fn late_claims_answered_with_resolved_claim() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let mut name_key_pairs = Vec::new();
+
+        for _ in 0..QUORUM {
+            let key_pair = random_keypair();
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+            let climant_name = generate_random_name();
+            name_key_pairs.push((climant_name.clone(), key_pair.0.clone()));
+            let _ = pure_sentinel.add_claim(request.clone(), climant_name, signature,
+                                            serialised_claim.clone(), QUORUM, 1);
+        }
+
+        assert!(pure_sentinel.add_keys(request.clone(), generate_random_name(),
+                                       name_key_pairs.clone(), 1).is_some());
+
+        // A late claim, even a conflicting one, neither restarts accumulation nor asks for
+        // keys again.
+        let key_pair = random_keypair();
+        let late_claim = TestClaim { value: random::<usize>() }.serialise();
+        let signature = sign_claim(&request, &late_claim, &key_pair.1);
+        assert!(pure_sentinel.add_claim(request.clone(), generate_random_name(), signature,
+                                        late_claim, QUORUM, 1)
+            .and_then(|result| match result {
+                AddResult::AlreadyResolved(resolved, claim) => {
+                    assert_eq!(resolved, request);
+                    assert_eq!(claim, serialised_claim);
+                    Some(claim)
+                }
+                _ => None
+            }).is_some());
+        assert_eq!(pure_sentinel.pending_count(), 0);
+        assert!(pure_sentinel.add_keys(request, generate_random_name(), name_key_pairs,
+                                       1).is_none());
+    }
+
+    #[test]
+    fn activity_counted_in_metrics() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let forked_claim = TestClaim { value: random::<usize>() }.serialise();
+        let mut name_key_pairs = Vec::new();
+
+        for index in 0..QUORUM + 1 {
+            let claim = if index < QUORUM { &serialised_claim } else { &forked_claim };
+            let key_pair = random_keypair();
+            let signature = sign_claim(&request, claim, &key_pair.1);
+            let claimant_name = generate_random_name();
+            name_key_pairs.push((claimant_name.clone(), key_pair.0.clone()));
+            let _ = pure_sentinel.add_claim(request.clone(), claimant_name, signature,
+                                            claim.clone(), QUORUM, 1);
+        }
+        assert_eq!(pure_sentinel.metrics().pending_requests, 1);
+
+        assert!(pure_sentinel.add_keys(request.clone(), generate_random_name(),
+                                       name_key_pairs.clone(), 1).is_some());
+        let key_pair = random_keypair();
+        let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+        let _ = pure_sentinel.add_claim(request, generate_random_name(), signature,
+                                        serialised_claim, QUORUM, 1);
+
+        let metrics = pure_sentinel.metrics();
+        assert_eq!(metrics.claims_received, QUORUM as u64 + 2);
+        assert_eq!(metrics.claims_verified, QUORUM as u64 + 1);
+        assert_eq!(metrics.claims_already_resolved, 1);
+        assert_eq!(metrics.claims_rejected.total(), 0);
+        assert_eq!(metrics.resolutions, 1);
+        assert_eq!(metrics.forks, 1);
+        assert!(metrics.signature_verifications >= QUORUM as u64 + 1);
+        assert_eq!(metrics.time_to_resolve.count(), 1);
+        assert_eq!(metrics.pending_requests, 0);
+        assert_eq!(metrics.pending_bytes, 0);
+        assert_eq!(metrics.key_store.vouchers_stored, QUORUM as u64 + 1);
+        assert_eq!(metrics.key_store.targets, QUORUM + 1);
+    }
+
+    #[test]
+    fn bad_signatures_counted_in_metrics() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let mut name_key_pairs = Vec::new();
+
+        // The last claimant signs with a key other than the one vouched for it.
+        for index in 0..QUORUM + 1 {
+            let key_pair = seeded_keypair(index as u32);
+            let signing_key = if index < QUORUM { key_pair.1 } else { seeded_keypair(99).1 };
+            let signature = sign_claim(&request, &serialised_claim, &signing_key);
+            let claimant_name = generate_random_name();
+            name_key_pairs.push((claimant_name.clone(), key_pair.0));
+            let _ = pure_sentinel.add_claim(request.clone(), claimant_name, signature,
+                                            serialised_claim.clone(), QUORUM, 1);
+        }
+
+        assert!(pure_sentinel.add_keys(request, generate_random_name(), name_key_pairs,
+                                       1).is_some());
+        let metrics = pure_sentinel.metrics();
+        assert_eq!(metrics.claims_verified, QUORUM as u64);
+        assert_eq!(metrics.claims_rejected.bad_signature, 1);
+        assert_eq!(metrics.claims_rejected.total(), 1);
+    }
+
+    #[test]
+    fn early_keys_from_close_group_applied() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let close_group = generate_random_name();
+        let expected = close_group.clone();
+        pure_sentinel.set_close_group_check(Box::new(move |_, sender| *sender == expected));
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let claimants = (0..QUORUM).map(|_| (generate_random_name(),
+                                             random_keypair()))
+                                   .collect::<Vec<_>>();
+        let keys = claimants.iter()
+                            .map(|&(ref name, ref key_pair)| (name.clone(), key_pair.0.clone()))
+                            .collect::<Vec<_>>();
+
+        // Keys from outside the close group are not buffered.
+        let other_request = TestRequest::new(random::<usize>(), generate_random_name());
+        assert!(pure_sentinel.add_keys(other_request.clone(), generate_random_name(),
+                                       keys.clone(), 1).is_none());
+        let key_pair = random_keypair();
+        let signature = sign_claim(&other_request, &serialised_claim, &key_pair.1);
+        match pure_sentinel.add_claim(other_request, generate_random_name(), signature,
+                                      serialised_claim.clone(), QUORUM, 1) {
+            Some(AddResult::RequestKeys(_)) => (),
+            _ => panic!("expected keys to be requested"),
+        }
+
+        assert!(pure_sentinel.add_keys(request.clone(), close_group, keys, 1).is_none());
+
+        for (index, &(ref climant_name, ref key_pair)) in claimants.iter().enumerate() {
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+            match pure_sentinel.add_claim(request.clone(), climant_name.clone(), signature,
+                                          serialised_claim.clone(), QUORUM, 1) {
+                Some(AddResult::Resolved(resolved, claim)) => {
+                    assert_eq!(index + 1, QUORUM);
+                    assert_eq!(resolved, request);
+                    assert_eq!(claim, serialised_claim);
+                }
+                None => assert!(index + 1 < QUORUM),
+                _ => panic!("unexpected result"),
+            }
+        }
+    }
+
+    #[test]
+    fn early_keys_below_key_quorum_still_requested() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let close_group = generate_random_name();
+        let expected = close_group.clone();
+        pure_sentinel.set_close_group_check(Box::new(move |_, sender| *sender == expected));
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let claimant = generate_random_name();
+        let key_pair = seeded_keypair(35);
+
+        // A single responder can't make up a key quorum of two, so keys are still requested.
+        assert!(pure_sentinel.add_keys(request.clone(), close_group,
+                                       vec![(claimant.clone(), key_pair.0)], 2).is_none());
+        let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+        match pure_sentinel.add_claim(request, claimant, signature, serialised_claim, QUORUM,
+                                      2) {
+            Some(AddResult::RequestKeys(_)) => (),
+            _ => panic!("expected keys to be requested"),
+        }
+    }
+
+    #[test]
+    fn missing_keys_requested_again() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        pure_sentinel.set_key_request_backoff(Duration::zero(), Duration::zero());
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let mut name_key_pairs = Vec::new();
+
+        for _ in 0..2 {
+            let key_pair = random_keypair();
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+            let climant_name = generate_random_name();
+            name_key_pairs.push((climant_name.clone(), key_pair.0.clone()));
+            let _ = pure_sentinel.add_claim(request.clone(), climant_name, signature,
+                                            serialised_claim.clone(), QUORUM, 1);
+        }
+
+        assert_eq!(pure_sentinel.missing_keys(&request).len(), 2);
+
+        let _ = pure_sentinel.add_keys(request.clone(), generate_random_name(),
+                                       vec![name_key_pairs[0].clone()], 1);
+        assert!(pure_sentinel.missing_keys(&request) == vec![name_key_pairs[1].0.clone()]);
+
+        let key_requests = pure_sentinel.poll_key_requests();
+        assert_eq!(key_requests.len(), 1);
+        assert_eq!(key_requests[0].0, request);
+        assert!(key_requests[0].1 == vec![name_key_pairs[1].0.clone()]);
+
+        // With a long delay nothing is due yet.
+        pure_sentinel.set_key_request_backoff(Duration::hours(1), Duration::hours(1));
+        let other_request = TestRequest::new(random::<usize>(), generate_random_name());
+        let key_pair = random_keypair();
+        let signature = sign_claim(&other_request, &serialised_claim, &key_pair.1);
+        let _ = pure_sentinel.add_claim(other_request.clone(), generate_random_name(), signature,
+                                        serialised_claim, QUORUM, 1);
+        assert_eq!(pure_sentinel.missing_keys(&other_request).len(), 1);
+        assert!(pure_sentinel.poll_key_requests().iter()
+                             .all(|&(ref polled, _)| *polled != other_request));
+    }
+
+    #[test]
+    fn timing_follows_the_clock() {
+        reset_random();
+        let clock = ManualClock::new();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        pure_sentinel.set_clock(Arc::new(clock.clone()));
+        pure_sentinel.set_replay_window(Duration::seconds(30), 10);
+        pure_sentinel.set_key_request_backoff(Duration::seconds(1), Duration::seconds(1));
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let claimants = (0..2).map(|_| (generate_random_name(), random_keypair()))
+                              .collect::<Vec<_>>();
+        let add_claim = |pure_sentinel: &mut PureSentinel<TestRequest, TestName>, index: usize| {
+            let (ref claimant, ref key_pair) = claimants[index];
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+            pure_sentinel.add_claim(request.clone(), claimant.clone(), signature,
+                                    serialised_claim.clone(), 2, 1)
+        };
+
+        // A claim arriving 29s after the first with a 30s expiry finds the request pending.
+        let _ = add_claim(&mut pure_sentinel, 0);
+        assert!(pure_sentinel.poll_key_requests().is_empty());
+        clock.advance(Duration::seconds(29));
+        assert_eq!(pure_sentinel.poll_key_requests().len(), 1);
+        assert!(pure_sentinel.expire_pending(Duration::seconds(30)).is_empty());
+        assert!(add_claim(&mut pure_sentinel, 1).is_none());
+        clock.advance(Duration::seconds(2));
+        assert_eq!(pure_sentinel.expire_pending(Duration::seconds(30)), vec![request.clone()]);
+
+        // Resolved requests are remembered for the replay window, and then forgotten.
+        let _ = add_claim(&mut pure_sentinel, 0);
+        let _ = add_claim(&mut pure_sentinel, 1);
+        let keys = claimants.iter()
+                            .map(|&(ref name, ref key_pair)| (name.clone(), key_pair.0.clone()))
+                            .collect::<Vec<_>>();
+        assert!(pure_sentinel.add_keys(request.clone(), generate_random_name(), keys,
+                                       1).is_some());
+        clock.advance(Duration::seconds(29));
+        match add_claim(&mut pure_sentinel, 0) {
+            Some(AddResult::AlreadyResolved(..)) => (),
+            _ => panic!("expected the request to be remembered"),
+        }
+        clock.advance(Duration::seconds(2));
+        match add_claim(&mut pure_sentinel, 0) {
+            Some(AddResult::RequestKeys(_)) => (),
+            _ => panic!("expected the request to be forgotten"),
+        }
+    }
+
+    #[test]
+    fn keys_requested_through_sink() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        pure_sentinel.set_key_sink(Box::new(TraceGetKeys { calls: calls.clone() }));
+        pure_sentinel.set_key_request_backoff(Duration::zero(), Duration::zero());
+        let source = generate_random_name();
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let key_pair = random_keypair();
+        let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+        let climant_name = generate_random_name();
+
+        // Two requests from the same source only ask for the group keys once.
+        for core in 0..2 {
+            assert!(pure_sentinel.add_claim(TestRequest::new(core, source.clone()),
+                                            climant_name.clone(), signature.clone(),
+                                            serialised_claim.clone(), QUORUM, 1).is_none());
+        }
+        assert!(pure_sentinel.poll_key_requests().is_empty());
+
+        assert_eq!(*calls.lock().unwrap(), vec![KeyRequest::Group(source),
+                                                KeyRequest::Client(climant_name)]);
+    }
+
+    #[test]
+    fn keys_answered_once_confirmed() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName> = PureSentinel::new();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        pure_sentinel.set_key_sink(Box::new(TraceGetKeys { calls: calls.clone() }));
+        let source = generate_random_name();
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let key_pair = seeded_keypair(37);
+        let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+        let climant_name = generate_random_name();
+        let keys = vec![(climant_name.clone(), key_pair.0)];
+        let group_requests = |calls: &Arc<Mutex<Vec<KeyRequest<TestName>>>>| {
+            calls.lock().unwrap().iter().filter(|call| {
+                **call == KeyRequest::Group(source.clone())
+            }).count()
+        };
+
+        let first = TestRequest::new(0, source.clone());
+        assert!(pure_sentinel.add_claim(first.clone(), climant_name.clone(), signature.clone(),
+                                        serialised_claim.clone(), QUORUM, 2).is_none());
+        assert_eq!(group_requests(&calls), 1);
+
+        // A single responder doesn't confirm the keys, so the group isn't asked again.
+        assert!(pure_sentinel.add_keys(first.clone(), generate_random_name(), keys.clone(), 2)
+                             .is_none());
+        assert!(pure_sentinel.add_claim(TestRequest::new(1, source.clone()),
+                                        climant_name.clone(), signature.clone(),
+                                        serialised_claim.clone(), QUORUM, 2).is_none());
+        assert_eq!(group_requests(&calls), 1);
+
+        // Once a quorum confirms them the group is answered, and asked afresh next time.
+        assert!(pure_sentinel.add_keys(first, generate_random_name(), keys, 2).is_none());
+        assert!(pure_sentinel.add_claim(TestRequest::new(2, source.clone()), climant_name,
+                                        signature, serialised_claim, QUORUM, 2).is_none());
+        assert_eq!(group_requests(&calls), 2);
+    }
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct AuthorityRequest {
+        authority: Authority,
+        name: TestName,
+    }
+
+    impl Source<TestName> for AuthorityRequest {
+        fn get_source(&self) -> TestName {
+            self.name.clone()
+        }
+    }
+
+    impl GetAuthority for AuthorityRequest {
+        fn get_authority(&self) -> Authority {
+            self.authority
+        }
+    }
+
+    #[test]
+    fn quorums_taken_from_authority() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<AuthorityRequest, TestName> = PureSentinel::new();
+        let mut policies = PolicyTable::default();
+        policies.set(Authority::NaeManager,
+                     QuorumPolicy { claim_quorum: 2, key_quorum: 1, close_group_only: true });
+        pure_sentinel.set_policies(policies);
+        let close_group = (0..3).map(|_| generate_random_name()).collect::<Vec<_>>();
+        let members = close_group.clone();
+        pure_sentinel.set_close_group_check(Box::new(move |_, sender| members.contains(sender)));
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let request = AuthorityRequest { authority: Authority::NaeManager,
+                                         name: generate_random_name() };
+        let mut keys = Vec::new();
+
+        // Outsiders are not counted towards the quorum.
+        let key_pair = random_keypair();
+        let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+        assert!(pure_sentinel.add_claim_by_authority(request.clone(), generate_random_name(),
+                                                     signature, serialised_claim.clone())
+                             .is_none());
+        assert_eq!(pure_sentinel.pending_count(), 0);
+
+        for climant_name in close_group.into_iter().take(2) {
+            let key_pair = random_keypair();
+            let signature = sign_claim(&request, &serialised_claim, &key_pair.1);
+            keys.push((climant_name.clone(), key_pair.0));
+            let _ = pure_sentinel.add_claim_by_authority(request.clone(), climant_name,
+                                                         signature, serialised_claim.clone());
+        }
+
+        assert!(pure_sentinel.add_keys_by_authority(request.clone(), generate_random_name(),
+                                                    keys)
+            .and_then(|result| { assert_eq!(result.1, serialised_claim);
+                                 assert_eq!(result.0, request);
+                                 Some(result)
+            }).is_some());
+    }
+
+    #[test]
+    fn close_group_only_fails_closed() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<AuthorityRequest, TestName> = PureSentinel::new();
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let key_pair = seeded_keypair(40);
+        let signature = crypto::sign::sign_detached(&serialised_claim, &key_pair.1);
+
+        // Without a close group check, group authorities accept no claims.
+        let request = AuthorityRequest { authority: Authority::NaeManager,
+                                         name: generate_random_name() };
+        assert!(pure_sentinel.add_claim_by_authority(request, generate_random_name(),
+                                                     signature.clone(),
+                                                     serialised_claim.clone())
+                             .is_none());
+        assert_eq!(pure_sentinel.pending_count(), 0);
+
+        // Clients sign on their own, so need no close group, but nobody else signs for them.
+        let client = generate_random_name();
+        let request = AuthorityRequest { authority: Authority::Client, name: client.clone() };
+        assert!(pure_sentinel.add_claim_by_authority(request.clone(), generate_random_name(),
+                                                     signature.clone(),
+                                                     serialised_claim.clone())
+                             .is_none());
+        assert_eq!(pure_sentinel.pending_count(), 0);
+        assert!(pure_sentinel.add_claim_by_authority(request, client, signature,
+                                                     serialised_claim)
+                             .is_some());
+        assert_eq!(pure_sentinel.pending_count(), 1);
+    }
+
+    #[test]
+    fn mock_signature_scheme() {
+        reset_random();
+        let mut pure_sentinel: PureSentinel<TestRequest, TestName, MockScheme> =
+            PureSentinel::new();
+        let request = TestRequest::new(random::<usize>(), generate_random_name());
+        let serialised_claim = TestClaim { value: random::<usize>() }.serialise();
+        let bound = bound_claim(&serialise(&request), &serialised_claim);
+        let mut keys = Vec::new();
+
+        for index in 0..QUORUM {
+            let climant_name = generate_random_name();
+            let key = index as u64;
+            // The last claimant signs with a key other than the one vouched for.
+            let signing_key = if index + 1 == QUORUM { key + 1 } else { key };
+            keys.push((climant_name.clone(), key));
+            let _ = pure_sentinel.add_claim(request.clone(), climant_name,
+                                            (signing_key, bound.clone()),
+                                            serialised_claim.clone(), QUORUM - 1, 1);
+        }
+
+        assert!(pure_sentinel.add_keys(request.clone(), generate_random_name(), keys, 1)
+            .and_then(|result| { assert_eq!(result.1, serialised_claim);
+                                 assert_eq!(result.0, request);
+                                 Some(result)
+            }).is_some());
+    }
 }
@@ -12,8 +12,18 @@
 // under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
 // KIND, either express or implied.
 
+//! RefreshSentinel accumulates values under a key until a quorum of them has been received.
+//!
+//! Calls can be recorded with `set_recorder`, and a recorded trace fed back into a fresh
+//! sentinel with `replay`.
+
 extern crate lru_time_cache;
 use lru_time_cache::LruCache;
+use metrics::RefreshSentinelMetrics;
+use trace::{self, Divergence, RefreshCall, RefreshOutcome, Recorder, TraceEntry};
+
+/// Recorder of the calls made into a `RefreshSentinel`.
+pub type RefreshRecorder<K, V> = Box<Recorder<RefreshCall<K, V>, RefreshOutcome<K, V>> + Send>;
 
 /// Entry for accumulation.
 #[derive(Clone)]
@@ -23,7 +33,6 @@ pub struct Entry<V> {
 }
 
 /// Generic type for accumulating multiple values under a given key.
-#[allow(dead_code)]
 pub struct RefreshSentinel<K, V>
     where K: PartialOrd + Ord + Clone,
           V: Clone
@@ -31,23 +40,27 @@ pub struct RefreshSentinel<K, V>
     /// Threshold for resolution.
     quorum: usize,
     storage: LruCache<K, Entry<V>>,
+    metrics: RefreshSentinelMetrics,
+    recorder: Option<RefreshRecorder<K, V>>,
 }
 
 impl<K: PartialOrd + Ord + Clone, V: Clone> RefreshSentinel<K, V> {
     /// Construct with quorum.
-    #[allow(dead_code)]
     pub fn new(quorum: usize) -> RefreshSentinel<K, V> {
-        RefreshSentinel { quorum: quorum, storage: LruCache::<K, Entry<V>>::with_capacity(1000) }
+        RefreshSentinel {
+            quorum: quorum,
+            storage: LruCache::<K, Entry<V>>::with_capacity(1000),
+            metrics: RefreshSentinelMetrics::default(),
+            recorder: None,
+        }
     }
 
     /// Check for the existence of a key.
-    #[allow(dead_code)]
     pub fn contains_key(&self, key: &K) -> bool {
         self.storage.check(key)
     }
 
     /// Check whether a quorum of values has been accumulated for the given key.
-    #[allow(dead_code)]
     pub fn is_quorum_reached(&mut self, key: &K) -> bool {
         let entry = self.storage.get(key);
 
@@ -60,13 +73,29 @@ impl<K: PartialOrd + Ord + Clone, V: Clone> RefreshSentinel<K, V> {
 
     /// Adds a key/value pair, if the key already exists add the value under that key.
     /// Optionally returns the key and the vector of values if the quroum has been reached.
-    #[allow(dead_code)]
     pub fn add(&mut self, key: K, value: V) -> Option<(K, Vec<V>)> {
+        let call = self.recorder.as_ref().map(|_| RefreshCall::Add(key.clone(), value.clone()));
+        let result = self.accumulate(key, value);
+        if let Some(call) = call {
+            self.record(&call, &RefreshOutcome::of(&result));
+        }
+        result
+    }
+
+    fn record(&mut self, call: &RefreshCall<K, V>, outcome: &RefreshOutcome<K, V>) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(call, outcome);
+        }
+    }
+
+    fn accumulate(&mut self, key: K, value: V) -> Option<(K, Vec<V>)> {
+        self.metrics.values_received += 1;
         let entry = self.storage.remove(&key);
         if entry.is_none() {
             let entry_in = Entry { received_response: vec![value] };
             self.storage.add(key.clone(), entry_in.clone());
             if self.quorum == 1 {
+                self.metrics.resolutions += 1;
                 let result = (key, entry_in.received_response);
                 return Some(result);
             }
@@ -75,6 +104,7 @@ impl<K: PartialOrd + Ord + Clone, V: Clone> RefreshSentinel<K, V> {
             tmp.received_response.push(value);
             self.storage.add(key.clone(), tmp.clone());
             if tmp.received_response.len() >= self.quorum {
+                self.metrics.resolutions += 1;
                 return Some((key, tmp.received_response));
             }
         }
@@ -82,7 +112,6 @@ impl<K: PartialOrd + Ord + Clone, V: Clone> RefreshSentinel<K, V> {
     }
 
     /// Retrieve a key/vec<value> pair from the cache.
-    #[allow(dead_code)]
     pub fn get(&mut self, key: &K) -> Option<(K, Vec<V>)> {
         let entry = self.storage.get(key);
         if entry.is_none() {
@@ -93,28 +122,65 @@ impl<K: PartialOrd + Ord + Clone, V: Clone> RefreshSentinel<K, V> {
     }
 
     /// Remove all values for the given key.
-    #[allow(dead_code)]
     pub fn delete(&mut self, key: &K) {
+        if self.recorder.is_some() {
+            self.record(&RefreshCall::Delete(key.clone()), &RefreshOutcome::Nothing);
+        }
         self.storage.remove(key);
     }
 
     /// Return the size of the cache.
-    #[allow(dead_code)]
     pub fn cache_size(&mut self) -> usize {
         self.storage.len()
     }
 
+    /// Return the counters of the sentinel, with the number of keys held.
+    pub fn metrics(&self) -> RefreshSentinelMetrics {
+        RefreshSentinelMetrics { entries: self.storage.len(), ..self.metrics.clone() }
+    }
+
+    /// Set the recorder every call to `add`, `delete` and `set_quorum` is passed to, with its
+    /// outcome.
+    pub fn set_recorder(&mut self, recorder: RefreshRecorder<K, V>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Feed the calls of `trace` into the sentinel, which should be fresh and start with the
+    /// quorum the recorded one started with. Returns the calls whose outcome differs from the
+    /// recorded one.
+    pub fn replay(&mut self,
+                  trace: Vec<TraceEntry<RefreshCall<K, V>, RefreshOutcome<K, V>>>)
+                  -> Vec<Divergence<RefreshOutcome<K, V>>>
+        where V: PartialEq
+    {
+        trace::diverging(trace, |_, call| {
+            match call {
+                RefreshCall::Add(key, value) => RefreshOutcome::of(&self.add(key, value)),
+                RefreshCall::Delete(key) => {
+                    self.delete(&key);
+                    RefreshOutcome::Nothing
+                }
+                RefreshCall::SetQuorum(quorum) => {
+                    self.set_quorum(quorum);
+                    RefreshOutcome::Nothing
+                }
+            }
+        })
+    }
+
     /// Set the quorum to a new value.
-    #[allow(dead_code)]
     pub fn set_quorum(&mut self, quorum: usize) {
+        if self.recorder.is_some() {
+            self.record(&RefreshCall::SetQuorum(quorum), &RefreshOutcome::Nothing);
+        }
         self.quorum = quorum;
     }
 }
 
 #[cfg(test)]
 mod test {
-    extern crate rand;
     use super::*;
+    use testing::{random, reset_random};
 
     #[test]
     fn add() {
@@ -148,10 +214,11 @@ mod test {
 
     #[test]
     fn add_single_value_quorum() {
+        reset_random();
         let quorum_size: usize = 19;
         let mut sentinel: RefreshSentinel<i32, u32> = RefreshSentinel::new(quorum_size);
-        let key = rand::random::<i32>();
-        let value = rand::random::<u32>();
+        let key = random::<i32>();
+        let value = random::<u32>();
         for i in 0..quorum_size - 1 {
             assert!(sentinel.add(key, value).is_none());
             let key_value = sentinel.get(&key).unwrap();
@@ -174,37 +241,39 @@ mod test {
 
     #[test]
     fn add_multiple_values_quorum() {
+        reset_random();
         let quorum_size: usize = 19;
         let mut sentinel: RefreshSentinel<i32, u32> = RefreshSentinel::new(quorum_size);
-        let key = rand::random::<i32>();
+        let key = random::<i32>();
         for _ in 0..quorum_size - 1 {
-            assert!(sentinel.add(key, rand::random::<u32>()).is_none());
+            assert!(sentinel.add(key, random::<u32>()).is_none());
             assert_eq!(sentinel.is_quorum_reached(&key), false);
         }
-        assert!(sentinel.add(key, rand::random::<u32>()).is_some());
+        assert!(sentinel.add(key, random::<u32>()).is_some());
         assert_eq!(sentinel.is_quorum_reached(&key), true);
     }
 
     #[test]
     fn add_multiple_keys_quorum() {
+        reset_random();
         let quorum_size: usize = 19;
         let mut sentinel: RefreshSentinel<i32, u32> = RefreshSentinel::new(quorum_size);
-        let key = rand::random::<i32>();
+        let key = random::<i32>();
         let mut noise_keys: Vec<i32> = Vec::with_capacity(5);
         while noise_keys.len() < 5 {
-            let noise_key = rand::random::<i32>();
+            let noise_key = random::<i32>();
             if noise_key != key {
                 noise_keys.push(noise_key);
             };
         };
         for _ in 0..quorum_size - 1 {
             for noise_key in noise_keys.iter() {
-                sentinel.add(noise_key.clone(), rand::random::<u32>());
+                sentinel.add(noise_key.clone(), random::<u32>());
             }
-            assert!(sentinel.add(key, rand::random::<u32>()).is_none());
+            assert!(sentinel.add(key, random::<u32>()).is_none());
             assert_eq!(sentinel.is_quorum_reached(&key), false);
         }
-        assert!(sentinel.add(key, rand::random::<u32>()).is_some());
+        assert!(sentinel.add(key, random::<u32>()).is_some());
         assert_eq!(sentinel.is_quorum_reached(&key), true);
     }
 
@@ -304,9 +373,23 @@ mod test {
 
     #[test]
     fn set_quorum_size() {
+        reset_random();
         let mut sentinel: RefreshSentinel<i32, u32> = RefreshSentinel::new(2);
-        let random = rand::random::<usize>();
+        let random = random::<usize>();
         sentinel.set_quorum(random);
         assert_eq!(random, sentinel.quorum);
     }
+
+    #[test]
+    fn metrics_counted() {
+        let mut sentinel: RefreshSentinel<i32, u32> = RefreshSentinel::new(2);
+        assert!(sentinel.add(1, 1).is_none());
+        assert!(sentinel.add(2, 1).is_none());
+        assert!(sentinel.add(1, 2).is_some());
+
+        let metrics = sentinel.metrics();
+        assert_eq!(metrics.values_received, 3);
+        assert_eq!(metrics.resolutions, 1);
+        assert_eq!(metrics.entries, 2);
+    }
 }
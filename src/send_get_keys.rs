@@ -0,0 +1,120 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Callback interface the sentinels ask the network for keys through.
+//!
+//! By default the sentinels return the keys they need to the caller. A sentinel given a
+//! `SendGetKeys` implementation with `set_key_sink` calls it instead. Key requests still
+//! outstanding are not repeated, so the same keys are not asked for twice within
+//! `OUTSTANDING_EXPIRY_MILLIS`, measured with the clock of the sentinel, unless they arrived
+//! in between.
+
+use expiring_cache::ExpiringCache;
+use time::{Duration, SteadyTime};
+
+/// Milliseconds a key request is considered outstanding for, if not answered.
+pub const OUTSTANDING_EXPIRY_MILLIS: i64 = 1000;
+/// Number of outstanding key requests remembered.
+pub const MAX_OUTSTANDING_COUNT: usize = 1000;
+
+/// Sends key requests to the network.
+pub trait SendGetKeys<Name> {
+    /// Asks for the public key of the single node or client `address`.
+    fn get_client_key(&mut self, address: Name);
+    /// Asks the close group of `group_address` for the public keys of its members.
+    fn get_group_key(&mut self, group_address: Name);
+}
+
+/// A request for keys sent through `SendGetKeys`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable, RustcDecodable)]
+pub enum KeyRequest<Name> {
+    /// Sent with `get_client_key`.
+    Client(Name),
+    /// Sent with `get_group_key`.
+    Group(Name),
+}
+
+/// A `SendGetKeys` implementation with the key requests still outstanding on it.
+pub struct KeySink<Name> where Name: Eq + PartialOrd + Ord + Clone {
+    sink: Box<SendGetKeys<Name> + Send>,
+    outstanding: ExpiringCache<KeyRequest<Name>, ()>,
+}
+
+impl<Name> KeySink<Name> where Name: Eq + PartialOrd + Ord + Clone {
+    /// Wraps `sink`, with no key requests outstanding.
+    pub fn new(sink: Box<SendGetKeys<Name> + Send>) -> KeySink<Name> {
+        KeySink {
+            sink: sink,
+            outstanding: ExpiringCache::new(Duration::milliseconds(OUTSTANDING_EXPIRY_MILLIS),
+                                            MAX_OUTSTANDING_COUNT),
+        }
+    }
+
+    /// Sends `request` unless it is already outstanding at `now`. Returns true if it was sent.
+    pub fn send(&mut self, request: KeyRequest<Name>, now: SteadyTime) -> bool {
+        if self.outstanding.check(&request, now) {
+            return false;
+        }
+
+        match request.clone() {
+            KeyRequest::Client(address) => self.sink.get_client_key(address),
+            KeyRequest::Group(group_address) => self.sink.get_group_key(group_address),
+        }
+
+        self.outstanding.add(request, (), now);
+        true
+    }
+
+    /// Marks `request` as answered, so that it is sent again when next needed.
+    pub fn answered(&mut self, request: &KeyRequest<Name>) {
+        self.outstanding.forget(request);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use testing::TraceGetKeys;
+    use time::{Duration, SteadyTime};
+
+    #[test]
+    fn outstanding_requests_not_repeated() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut key_sink = KeySink::new(Box::new(TraceGetKeys { calls: calls.clone() }));
+        let now = SteadyTime::now();
+
+        assert!(key_sink.send(KeyRequest::Group(1), now));
+        assert!(!key_sink.send(KeyRequest::Group(1), now));
+        assert!(key_sink.send(KeyRequest::Client(1), now));
+        assert!(key_sink.send(KeyRequest::Group(2), now));
+
+        key_sink.answered(&KeyRequest::Group(1));
+        assert!(key_sink.send(KeyRequest::Group(1), now));
+
+        // Unanswered requests are sent again once they expire.
+        let expiry = Duration::milliseconds(OUTSTANDING_EXPIRY_MILLIS);
+        assert!(!key_sink.send(KeyRequest::Group(2), now + expiry));
+        assert!(key_sink.send(KeyRequest::Group(2), now + expiry + Duration::milliseconds(1)));
+
+        assert_eq!(*calls.lock().unwrap(), vec![KeyRequest::Group(1),
+                                                KeyRequest::Client(1),
+                                                KeyRequest::Group(2),
+                                                KeyRequest::Group(1),
+                                                KeyRequest::Group(2)]);
+    }
+}
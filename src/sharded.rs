@@ -0,0 +1,328 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Sentinels that can be shared between threads.
+//!
+//! Requests are spread by hash over a number of shards, each an ordinary sentinel behind its
+//! own lock, so claims of different requests are verified in parallel. Each sharded sentinel
+//! hashes with keys of its own, so that requests can't be crafted to crowd one shard. The
+//! shards of a `ShardedSentinel` share a single `SharedKeyStore`, so keys added for a request
+//! in one shard verify claims held in all of them. The key store is only locked while keys
+//! are added or looked up, not while signatures are verified.
+//!
+//! Limits on what the sentinels hold are set through the sharded sentinel, which divides the
+//! limits spanning requests between the shards so that together they hold no more than a
+//! single sentinel would. Limits set on each shard through `configure` apply per shard.
+
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use eviction::EvictionPolicy;
+use key_sentinel::{GroupClaimTrait, IdTrait, KeySentinel};
+use limits::{ClaimLimits, Rejection};
+use pure_sentinel::{AddResult, PureSentinel, SharedKeyStore, Source};
+use signature_scheme::{Ed25519, SignatureScheme};
+use super::SerialisedClaim;
+
+/// A `PureSentinel` split into independently locked shards.
+pub struct ShardedSentinel<Request, Name, Scheme = Ed25519>
+    where Request: Eq + PartialOrd + Ord + Clone + Hash + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    shards: Vec<Mutex<PureSentinel<Request, Name, Scheme>>>,
+    hash_state: RandomState,
+}
+
+impl<Request, Name, Scheme> ShardedSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Hash + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: SignatureScheme
+{
+    /// Creates a sentinel of `shard_count` shards, at least one, sharing one key store. The
+    /// default limits are divided between the shards.
+    pub fn new(shard_count: usize) -> ShardedSentinel<Request, Name, Scheme> {
+        let key_store = SharedKeyStore::new();
+        let sentinel = ShardedSentinel {
+            shards: (0..cmp::max(shard_count, 1))
+                        .map(|_| Mutex::new(PureSentinel::with_key_store(key_store.clone())))
+                        .collect(),
+            hash_state: RandomState::new(),
+        };
+        sentinel.set_claim_limits(ClaimLimits::default());
+        sentinel.set_eviction_policy(EvictionPolicy::default());
+        sentinel
+    }
+
+    /// Calls `configure` on every shard in turn, to set weights or policies. Limits set
+    /// through it apply to each shard, so add up over the shards.
+    pub fn configure<F>(&self, mut configure: F)
+        where F: FnMut(&mut PureSentinel<Request, Name, Scheme>)
+    {
+        for shard in self.shards.iter() {
+            configure(&mut lock(shard));
+        }
+    }
+
+    /// Replaces the byte limits on held claims, dividing the total between the shards. The
+    /// limits on a single claim and request are kept, as each request is held by one shard.
+    pub fn set_claim_limits(&self, claim_limits: ClaimLimits) {
+        let shard_limits = divide_claim_limits(claim_limits, self.shards.len());
+        self.configure(|shard| shard.set_claim_limits(shard_limits));
+    }
+
+    /// Replaces the eviction policy, dividing the number of pending requests, in total and
+    /// per source, between the shards.
+    pub fn set_eviction_policy(&self, eviction_policy: EvictionPolicy) {
+        let shard_count = self.shards.len();
+        let shard_policy = EvictionPolicy {
+            max_pending: divide(eviction_policy.max_pending, shard_count),
+            max_pending_per_source: divide(eviction_policy.max_pending_per_source, shard_count),
+        };
+        self.configure(|shard| shard.set_eviction_policy(shard_policy));
+    }
+
+    /// Adds a claim as `PureSentinel::add_claim` does, locking only the shard of `request`.
+    pub fn add_claim(&self,
+                     request: Request,
+                     claimant: Name,
+                     signature: Scheme::Signature,
+                     claim: SerialisedClaim,
+                     claim_quorum: usize,
+                     key_quorum: usize)
+                     -> Option<AddResult<Request, Name>> {
+        lock(shard(&self.shards, &self.hash_state, &request))
+            .add_claim(request, claimant, signature, claim, claim_quorum, key_quorum)
+    }
+
+    /// Adds keys as `PureSentinel::add_keys` does, locking only the shard of `request`.
+    pub fn add_keys(&self,
+                    request: Request,
+                    sender: Name,
+                    keys: Vec<(Name, Scheme::PublicKey)>,
+                    key_quorum: usize)
+                    -> Option<(Request, SerialisedClaim)> {
+        lock(shard(&self.shards, &self.hash_state, &request))
+            .add_keys(request, sender, keys, key_quorum)
+    }
+
+    /// Returns the number of requests currently awaiting resolution over all shards.
+    pub fn pending_count(&self) -> usize {
+        self.shards.iter().fold(0, |count, shard| count + lock(shard).pending_count())
+    }
+}
+
+/// A `KeySentinel` split into independently locked shards.
+pub struct ShardedKeySentinel<Request, Name, IdType, GroupClaim, Scheme = Ed25519>
+    where Request: Eq + PartialOrd + Ord + Clone + Hash,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    shards: Vec<Mutex<KeySentinel<Request, Name, IdType, GroupClaim, Scheme>>>,
+    hash_state: RandomState,
+}
+
+impl<Request, Name, IdType, GroupClaim, Scheme>
+    ShardedKeySentinel<Request, Name, IdType, GroupClaim, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Hash,
+          Name: Eq + PartialOrd + Ord + Clone + Debug,
+          IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+          GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+          Scheme: SignatureScheme
+{
+    /// Creates a sentinel of `shard_count` shards, at least one. The default limits are
+    /// divided between the shards.
+    pub fn new(shard_count: usize)
+               -> ShardedKeySentinel<Request, Name, IdType, GroupClaim, Scheme> {
+        let sentinel = ShardedKeySentinel {
+            shards: (0..cmp::max(shard_count, 1))
+                        .map(|_| Mutex::new(KeySentinel::new()))
+                        .collect(),
+            hash_state: RandomState::new(),
+        };
+        sentinel.set_claim_limits(ClaimLimits::default());
+        sentinel
+    }
+
+    /// Calls `configure` on every shard in turn, to set policies. Limits set through it apply
+    /// to each shard, so add up over the shards.
+    pub fn configure<F>(&self, mut configure: F)
+        where F: FnMut(&mut KeySentinel<Request, Name, IdType, GroupClaim, Scheme>)
+    {
+        for shard in self.shards.iter() {
+            configure(&mut lock(shard));
+        }
+    }
+
+    /// Replaces the byte limits on held group claims, dividing the total between the shards.
+    pub fn set_claim_limits(&self, claim_limits: ClaimLimits) {
+        let shard_limits = divide_claim_limits(claim_limits, self.shards.len());
+        self.configure(|shard| shard.set_claim_limits(shard_limits));
+    }
+
+    /// Adds identities as `KeySentinel::add_identities` does, locking only the shard of
+    /// `request`.
+    pub fn add_identities(&self,
+                          request: Request,
+                          sender: Name,
+                          serialised: SerialisedClaim,
+                          signature: Scheme::Signature,
+                          claim: GroupClaim,
+                          quorum_size: usize)
+                          -> Result<Option<(Request, Vec<IdType>)>, Rejection> {
+        lock(shard(&self.shards, &self.hash_state, &request))
+            .add_identities(request, sender, serialised, signature, claim, quorum_size)
+    }
+}
+
+fn shard<'a, Key: Hash, Shard>(shards: &'a [Mutex<Shard>],
+                               hash_state: &RandomState,
+                               key: &Key)
+                               -> &'a Mutex<Shard> {
+    let mut hasher = hash_state.build_hasher();
+    key.hash(&mut hasher);
+    &shards[(hasher.finish() % shards.len() as u64) as usize]
+}
+
+// The share of `limit` each of `shard_count` shards gets, rounded down but at least one so
+// that every shard can hold something.
+fn divide(limit: usize, shard_count: usize) -> usize {
+    cmp::max(limit / shard_count, 1)
+}
+
+fn divide_claim_limits(claim_limits: ClaimLimits, shard_count: usize) -> ClaimLimits {
+    ClaimLimits {
+        max_total_bytes: divide(claim_limits.max_total_bytes, shard_count),
+        ..claim_limits
+    }
+}
+
+fn lock<Shard>(shard: &Mutex<Shard>) -> MutexGuard<Shard> {
+    // A shard is left poisoned by a panic halfway through an update, after which its state
+    // can't be trusted.
+    shard.lock().expect("Sentinel shard poisoned")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::{random, random_keypair, reset_random, seeded_keypair, sign_claim};
+    use sodiumoxide::crypto::sign;
+    use eviction::EvictionPolicy;
+    use std::sync::Arc;
+    use std::thread;
+    use pure_sentinel::{AddResult, Source};
+
+    const QUORUM: usize = 5;
+    const REQUEST_COUNT: usize = 16;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        name: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.name
+        }
+    }
+
+    #[test]
+    fn shards_share_keys() {
+        reset_random();
+        let sentinel = Arc::new(ShardedSentinel::<TestRequest, u64>::new(4));
+        let claim = vec![random::<u8>(); 16];
+        let claimants = Arc::new((0..QUORUM).map(|name| (name as u64, random_keypair()))
+                                            .collect::<Vec<_>>());
+        let requests = (0..REQUEST_COUNT).map(|core| TestRequest { core: core, name: 1000 })
+                                         .collect::<Vec<_>>();
+
+        let add_claims = |claimants: &[(u64, (sign::PublicKey, sign::SecretKey))]| {
+            let handles = requests.iter().map(|request| {
+                let sentinel = sentinel.clone();
+                let request = request.clone();
+                let claim = claim.clone();
+                let claimants = claimants.to_vec();
+                thread::spawn(move || {
+                    claimants.iter().filter_map(|&(name, ref key_pair)| {
+                        let signature = sign_claim(&request, &claim, &key_pair.1);
+                        match sentinel.add_claim(request.clone(), name, signature,
+                                                 claim.clone(), QUORUM, 1) {
+                            Some(AddResult::Resolved(_, _)) => Some(()),
+                            _ => None,
+                        }
+                    }).count()
+                })
+            }).collect::<Vec<_>>();
+            // All threads are spawned before any is joined, so the shards run concurrently.
+            handles.into_iter().map(|handle| handle.join().unwrap())
+                   .fold(0, |total, count| total + count)
+        };
+
+        assert_eq!(add_claims(&claimants[..QUORUM - 1]), 0);
+        assert_eq!(sentinel.pending_count(), REQUEST_COUNT);
+
+        // Keys added through one request verify the claims of all of them.
+        let keys = claimants.iter()
+                            .map(|&(name, ref key_pair)| (name, key_pair.0))
+                            .collect::<Vec<_>>();
+        assert!(sentinel.add_keys(requests[0].clone(), 1001, keys, 1).is_none());
+
+        assert_eq!(add_claims(&claimants[QUORUM - 1..]), REQUEST_COUNT);
+        assert_eq!(sentinel.pending_count(), 0);
+    }
+
+    #[test]
+    fn limits_divided_between_shards() {
+        reset_random();
+        let sentinel = ShardedSentinel::<TestRequest, u64>::new(4);
+        sentinel.set_eviction_policy(EvictionPolicy {
+            max_pending: REQUEST_COUNT / 2,
+            max_pending_per_source: REQUEST_COUNT / 2,
+        });
+        let claim = vec![random::<u8>(); 16];
+        let key_pair = seeded_keypair(41);
+        let signature = sign::sign_detached(&claim, &key_pair.1);
+
+        for core in 0..4 * REQUEST_COUNT {
+            let request = TestRequest { core: core, name: core as u64 };
+            let _ = sentinel.add_claim(request, 0, signature.clone(), claim.clone(), QUORUM, 1);
+        }
+        assert!(sentinel.pending_count() <= REQUEST_COUNT / 2);
+    }
+
+    #[test]
+    fn shards_keyed_per_sentinel() {
+        let shards = (0..4).map(Mutex::new).collect::<Vec<_>>();
+        let placement = |hash_state: &RandomState| {
+            (0..4 * REQUEST_COUNT).map(|core| {
+                let request = TestRequest { core: core, name: 1000 };
+                *lock(shard(&shards, hash_state, &request))
+            }).collect::<Vec<_>>()
+        };
+
+        // The same keys spread requests the same way, and other keys differently.
+        let hash_state = RandomState::new();
+        assert_eq!(placement(&hash_state), placement(&hash_state));
+        assert!(placement(&hash_state) != placement(&RandomState::new()));
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Signature schemes the sentinels can verify claims with.
+//!
+//! The sentinels, `KeyStore` and `IdTrait` are generic over a `SignatureScheme`, defaulting to
+//! `Ed25519` from sodiumoxide. Any other scheme, including mock schemes in tests, can be
+//! plugged in by implementing the trait.
+//!
+//! `Hybrid` pairs two schemes, typically Ed25519 with a post-quantum scheme, so that
+//! identities stay safe should either scheme be broken. Every key and signature is then a
+//! pair, and a claim only verifies if both signatures do. The crate ships no post-quantum
+//! scheme: the caller fills in `PostQuantum` with one implemented over an audited library.
+
+use sodiumoxide::crypto::sign;
+use std::marker::PhantomData;
+
+/// A detached signature scheme.
+pub trait SignatureScheme {
+    /// Public key claims are verified against.
+    type PublicKey: Clone;
+    /// Detached signature over a serialised claim.
+    type Signature: Clone;
+    /// Ordered representation of a public key, used to tally the senders vouching for it.
+    type KeyData: Clone + Ord;
+    /// Ordered representation of a signature, used to recognise a claim sent again.
+    type SignatureData: Clone + Ord;
+
+    /// Returns true if `signature` over `message` verifies against `public_key`.
+    fn verify(signature: &Self::Signature, public_key: &Self::PublicKey, message: &[u8]) -> bool;
+
+    /// Returns the ordered representation of `public_key`.
+    fn key_data(public_key: &Self::PublicKey) -> Self::KeyData;
+
+    /// Returns the ordered representation of `signature`.
+    fn signature_data(signature: &Self::Signature) -> Self::SignatureData;
+}
+
+/// Ed25519 signatures as implemented by sodiumoxide.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ed25519;
+
+// FIXME: We only tally KeyData and not PublicKey directly
+// because PublicKey doesn't derive from Ord in the current version of
+// sodiumdioxide library. Once that library is bumped to version 0.0.6
+// or above, we should be able to use PublicKey as KeyData.
+impl SignatureScheme for Ed25519 {
+    type PublicKey = sign::PublicKey;
+    type Signature = sign::Signature;
+    type KeyData = [u8; sign::PUBLICKEYBYTES];
+    // Arrays this long aren't ordered.
+    type SignatureData = Vec<u8>;
+
+    fn verify(signature: &sign::Signature, public_key: &sign::PublicKey, message: &[u8]) -> bool {
+        sign::verify_detached(signature, message, public_key)
+    }
+
+    fn key_data(public_key: &sign::PublicKey) -> [u8; sign::PUBLICKEYBYTES] {
+        public_key.0
+    }
+
+    fn signature_data(signature: &sign::Signature) -> Vec<u8> {
+        signature.0.to_vec()
+    }
+}
+
+/// Two signature schemes used together. Keys and signatures are pairs, and a signature only
+/// verifies if both halves verify.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Hybrid<Classical, PostQuantum>(PhantomData<(Classical, PostQuantum)>);
+
+/// Ed25519 paired with a post-quantum scheme.
+pub type Ed25519Hybrid<PostQuantum> = Hybrid<Ed25519, PostQuantum>;
+
+impl<Classical, PostQuantum> SignatureScheme for Hybrid<Classical, PostQuantum>
+    where Classical: SignatureScheme,
+          PostQuantum: SignatureScheme
+{
+    type PublicKey = (Classical::PublicKey, PostQuantum::PublicKey);
+    type Signature = (Classical::Signature, PostQuantum::Signature);
+    type KeyData = (Classical::KeyData, PostQuantum::KeyData);
+    type SignatureData = (Classical::SignatureData, PostQuantum::SignatureData);
+
+    fn verify(signature: &Self::Signature, public_key: &Self::PublicKey, message: &[u8]) -> bool {
+        Classical::verify(&signature.0, &public_key.0, message) &&
+        PostQuantum::verify(&signature.1, &public_key.1, message)
+    }
+
+    fn key_data(public_key: &Self::PublicKey) -> Self::KeyData {
+        (Classical::key_data(&public_key.0), PostQuantum::key_data(&public_key.1))
+    }
+
+    fn signature_data(signature: &Self::Signature) -> Self::SignatureData {
+        (Classical::signature_data(&signature.0), PostQuantum::signature_data(&signature.1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto::sign;
+    use testing::{seeded_keypair, MockScheme};
+
+    #[test]
+    fn ed25519_verifies() {
+        let message = vec![1u8, 2, 3];
+        let key_pair = seeded_keypair(1);
+        let other_key_pair = seeded_keypair(2);
+        let signature = sign::sign_detached(&message, &key_pair.1);
+
+        assert!(Ed25519::verify(&signature, &key_pair.0, &message));
+        assert!(!Ed25519::verify(&signature, &other_key_pair.0, &message));
+        assert!(!Ed25519::verify(&signature, &key_pair.0, &[1u8, 2]));
+        let public_key = key_pair.0;
+        assert_eq!(Ed25519::key_data(&public_key), public_key.0);
+    }
+
+    #[test]
+    fn hybrid_needs_both_signatures() {
+        let message = vec![1u8, 2, 3];
+        let key_pair = seeded_keypair(1);
+        let other_key_pair = seeded_keypair(2);
+        let classical_key = key_pair.0;
+        let public_key = (classical_key, 7u64);
+        let classical = sign::sign_detached(&message, &key_pair.1);
+        let forged_classical = sign::sign_detached(&message, &other_key_pair.1);
+
+        assert!(Ed25519Hybrid::<MockScheme>::verify(&(classical, (7, message.clone())),
+                                                    &public_key,
+                                                    &message));
+        assert!(!Ed25519Hybrid::<MockScheme>::verify(&(classical, (8, message.clone())),
+                                                     &public_key,
+                                                     &message));
+        assert!(!Ed25519Hybrid::<MockScheme>::verify(&(forged_classical, (7, message.clone())),
+                                                     &public_key,
+                                                     &message));
+        assert_eq!(Ed25519Hybrid::<MockScheme>::key_data(&public_key),
+                   (classical_key.0, 7));
+    }
+}
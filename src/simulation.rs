@@ -0,0 +1,498 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Deterministic simulation of a network whose nodes send group messages to a receiver.
+//!
+//! Nodes have random XOR names, and the close group of an address is the `GROUP_SIZE` nodes
+//! closest to it. In each run the close group of a random source sends a signed claim to a
+//! receiver, which asks the source group for the keys of its members in one of the two ways
+//! of `docs/sentinel.md`. A `Receiver::Group` runs a `GroupSentinel` and goes through the
+//! GetGroupKey flow: the members of the source group answer with their signed view of the
+//! group, and the keys it confirms verify the claims. A `Receiver::Node` runs a
+//! `PureSentinel` and goes through the GetKey flow: each member answers with the keys of the
+//! group as it sees them, and the keys a quorum of members vouch for verify the claims.
+//!
+//! Messages are delayed by a random number of steps, which reorders them, and can be
+//! dropped. Nodes leave and join while a run is in progress, so members answer with the
+//! group as they see it at that point. All randomness, names and keys included, is drawn from
+//! one seeded generator, so a run is reproduced from its seed.
+//!
+//! Members of the source group can be set to collude with `set_colluders`. Colluders sign a
+//! forged claim, and forge claims of the honest members under keys of their own making,
+//! which they list as the keys of the honest members when asked for keys.
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use sodiumoxide::crypto::sign;
+use std::collections::{BTreeMap, BTreeSet};
+use group_sentinel::{GroupResult, GroupSentinel};
+use key_sentinel::{GroupClaimTrait, IdTrait};
+use pure_sentinel::{AddResult, PureSentinel, Source};
+use testing::sign_claim;
+use SerialisedClaim;
+
+/// Number of nodes in a close group.
+pub const GROUP_SIZE: usize = 8;
+/// Number of claims, and of key responses, that have to agree.
+pub const QUORUM: usize = 6;
+/// Number of nodes a network starts with.
+pub const NODE_COUNT: usize = 64;
+
+const CLAIM_SIZE: usize = 16;
+
+/// A request, sent by the close group of `source`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+pub struct SimRequest {
+    /// Distinguishes requests of the same source.
+    pub id: u64,
+    /// Address the request was sent from.
+    pub source: u64,
+}
+
+impl Source<u64> for SimRequest {
+    fn get_source(&self) -> u64 {
+        self.source
+    }
+}
+
+/// A node's name and public key, as listed in a key response.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SimId {
+    name: u64,
+    public_key: [u8; sign::PUBLICKEYBYTES],
+}
+
+impl IdTrait<u64> for SimId {
+    fn name(&self) -> u64 {
+        self.name
+    }
+
+    fn public_key(&self) -> sign::PublicKey {
+        sign::PublicKey(self.public_key)
+    }
+}
+
+/// The close group of the source, as one of its members sees it.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SimGroupClaim {
+    identities: Vec<SimId>,
+}
+
+impl SimGroupClaim {
+    fn serialise(&self) -> SerialisedClaim {
+        let mut serialised = Vec::new();
+        for id in self.identities.iter() {
+            for shift in 0..8 {
+                serialised.push((id.name >> (8 * shift)) as u8);
+            }
+            serialised.extend(id.public_key.iter().cloned());
+        }
+        serialised
+    }
+}
+
+impl GroupClaimTrait<SimId> for SimGroupClaim {
+    fn group_identities(&self) -> Vec<SimId> {
+        self.identities.clone()
+    }
+}
+
+/// The sentinel of a receiver asking for keys with GetGroupKey.
+pub type SimSentinel = GroupSentinel<SimRequest, u64, SimId, SimGroupClaim>;
+
+/// The sentinel of a receiver asking for keys with GetKey.
+pub type SimPureSentinel = PureSentinel<SimRequest, u64>;
+
+/// A receiver, by the way it asks the source group for keys.
+pub enum Receiver {
+    /// Asks each member for its signed view of the group.
+    Group(SimSentinel),
+    /// Asks each member for the keys of the group, which a quorum of members vouch for.
+    Node(SimPureSentinel),
+}
+
+impl Receiver {
+    /// A receiver asking for keys with GetGroupKey.
+    pub fn group() -> Receiver {
+        Receiver::Group(SimSentinel::new())
+    }
+
+    /// A receiver asking for keys with GetKey.
+    pub fn node() -> Receiver {
+        Receiver::Node(SimPureSentinel::new())
+    }
+}
+
+/// Faults injected into a run.
+#[derive(Clone, Copy, Debug)]
+pub struct Faults {
+    /// Percentage of messages dropped.
+    pub drop_percent: u32,
+    /// Upper bound on the steps a message is delayed by, at least one.
+    pub max_delay: u64,
+    /// Percentage of steps in which a node leaves and another joins.
+    pub churn_percent: u32,
+}
+
+enum Message {
+    Claim(SimRequest, u64, sign::Signature, SerialisedClaim),
+    // Asks the named member of the source group for its view of the group.
+    GetGroupKey(SimRequest, u64),
+    KeyResponse(SimRequest, u64, SerialisedClaim, sign::Signature, SimGroupClaim),
+    // Asks the named member of the source group for the keys of the group.
+    GetKey(SimRequest, u64),
+    Keys(SimRequest, u64, Vec<(u64, sign::PublicKey)>),
+}
+
+// What the receiver made of a message.
+enum Delivered {
+    // The source group surrounding the name is to be asked for keys.
+    AskForKeys(SimRequest, u64),
+    Resolved(SerialisedClaim),
+    AlreadyResolved(SerialisedClaim),
+}
+
+/// What came of a run.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Outcome {
+    /// The claim the honest members of the source group sent.
+    pub claim: SerialisedClaim,
+    /// The claim the colluders sent.
+    pub forged_claim: SerialisedClaim,
+    /// The claim the receiver resolved the request to, if it did.
+    pub resolved: Option<SerialisedClaim>,
+    /// Messages delivered.
+    pub delivered: usize,
+}
+
+/// A simulated network, with the messages in flight.
+pub struct Network {
+    rng: XorShiftRng,
+    faults: Faults,
+    nodes: BTreeMap<u64, (sign::PublicKey, sign::SecretKey)>,
+    colluder_count: usize,
+    // Colluders of the current run, and the keys they forged for the honest members.
+    colluders: BTreeSet<u64>,
+    forged_keys: BTreeMap<u64, (sign::PublicKey, sign::SecretKey)>,
+    // Messages by step of delivery, then order of sending.
+    in_flight: BTreeMap<(u64, u64), Message>,
+    step: u64,
+    sent: u64,
+}
+
+impl Network {
+    /// Creates a network of `NODE_COUNT` nodes drawn from `seed`.
+    pub fn new(seed: u32, faults: Faults) -> Network {
+        let mut network = Network {
+            rng: XorShiftRng::from_seed([seed, 0x193a_6754, 0xa8a7_d469, 0x9783_0e05]),
+            faults: faults,
+            nodes: BTreeMap::new(),
+            colluder_count: 0,
+            colluders: BTreeSet::new(),
+            forged_keys: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+            step: 0,
+            sent: 0,
+        };
+        while network.nodes.len() < NODE_COUNT {
+            network.add_node();
+        }
+        network
+    }
+
+    /// Returns the names of the `GROUP_SIZE` nodes closest to `address`, closest first.
+    pub fn close_group(&self, address: u64) -> Vec<u64> {
+        let mut names = self.nodes.keys().cloned().collect::<Vec<_>>();
+        names.sort_by_key(|name| name ^ address);
+        names.truncate(GROUP_SIZE);
+        names
+    }
+
+    /// Makes the `count` members of each source group closest to its address collude.
+    pub fn set_colluders(&mut self, count: usize) {
+        self.colluder_count = count;
+    }
+
+    /// Has the close group of a random source send a claim to the receiver, and delivers
+    /// messages until none are left in flight.
+    pub fn run(&mut self, receiver: &mut Receiver) -> Outcome {
+        let request = SimRequest { id: self.rng.gen(), source: self.rng.gen() };
+        let claim = (0..CLAIM_SIZE).map(|_| self.rng.gen()).collect::<SerialisedClaim>();
+        let forged_claim = claim.iter().map(|byte| !byte).collect::<SerialisedClaim>();
+        let mut resolved = None;
+        let mut delivered = 0;
+
+        let group = self.close_group(request.source);
+        self.colluders = group.iter().take(self.colluder_count).cloned().collect();
+        self.forged_keys.clear();
+        if !self.colluders.is_empty() {
+            for member in group.iter().skip(self.colluder_count) {
+                let forged_key = sign::keypair_from_seed(&sign::Seed(self.rng.gen()));
+                let _ = self.forged_keys.insert(*member, forged_key);
+            }
+        }
+
+        for member in group {
+            let sent = if self.colluders.contains(&member) { &forged_claim } else { &claim };
+            let signature = sign_claim(&request, sent, &self.nodes[&member].1);
+            self.send(Message::Claim(request.clone(), member, signature, sent.clone()));
+
+            let impersonation = self.forged_keys.get(&member).map(|forged_key| {
+                sign_claim(&request, &forged_claim, &forged_key.1)
+            });
+            if let Some(signature) = impersonation {
+                self.send(Message::Claim(request.clone(), member, signature,
+                                         forged_claim.clone()));
+            }
+        }
+
+        while let Some(key) = self.in_flight.keys().next().cloned() {
+            let message = self.in_flight.remove(&key).expect("Message in flight");
+            self.step = key.0;
+            self.churn();
+            delivered += 1;
+
+            match self.deliver(receiver, message) {
+                Some(Delivered::AskForKeys(request, group)) => {
+                    for member in self.close_group(group) {
+                        let ask = match *receiver {
+                            Receiver::Group(_) => Message::GetGroupKey(request.clone(), member),
+                            Receiver::Node(_) => Message::GetKey(request.clone(), member),
+                        };
+                        self.send(ask);
+                    }
+                }
+                Some(Delivered::Resolved(resolved_claim)) => {
+                    assert!(resolved.is_none(), "Request resolved twice");
+                    resolved = Some(resolved_claim);
+                }
+                Some(Delivered::AlreadyResolved(resolved_claim)) =>
+                    assert_eq!(Some(resolved_claim), resolved),
+                None => (),
+            }
+        }
+
+        Outcome {
+            claim: claim,
+            forged_claim: forged_claim,
+            resolved: resolved,
+            delivered: delivered,
+        }
+    }
+
+    fn deliver(&mut self, receiver: &mut Receiver, message: Message) -> Option<Delivered> {
+        match (message, receiver) {
+            (Message::GetGroupKey(request, member), _) => {
+                self.answer_get_group_key(request, member);
+                None
+            }
+            (Message::GetKey(request, member), _) => {
+                self.answer_get_key(request, member);
+                None
+            }
+            (Message::Claim(request, sender, signature, claim),
+             &mut Receiver::Group(ref mut sentinel)) =>
+                Self::delivered(sentinel.add_message(request, sender, signature, claim, QUORUM)),
+            (Message::KeyResponse(request, sender, serialised, signature, group_claim),
+             &mut Receiver::Group(ref mut sentinel)) =>
+                Self::delivered(sentinel.add_key_response(request, sender, serialised,
+                                                          signature, group_claim, QUORUM)),
+            (Message::Claim(request, sender, signature, claim),
+             &mut Receiver::Node(ref mut sentinel)) => {
+                match sentinel.add_claim(request.clone(), sender, signature, claim, QUORUM,
+                                         QUORUM) {
+                    Some(AddResult::RequestKeys(group)) =>
+                        Some(Delivered::AskForKeys(request, group)),
+                    Some(AddResult::Resolved(_, claim)) => Some(Delivered::Resolved(claim)),
+                    Some(AddResult::AlreadyResolved(_, claim)) =>
+                        Some(Delivered::AlreadyResolved(claim)),
+                    Some(AddResult::Rejected(_)) => panic!("Claim within limits rejected"),
+                    None => None,
+                }
+            }
+            (Message::Keys(request, sender, keys), &mut Receiver::Node(ref mut sentinel)) =>
+                sentinel.add_keys(request, sender, keys, QUORUM)
+                        .map(|(_, claim)| Delivered::Resolved(claim)),
+            _ => panic!("Message of another flow delivered"),
+        }
+    }
+
+    fn delivered(result: Option<GroupResult<SimRequest, u64>>) -> Option<Delivered> {
+        match result {
+            Some(GroupResult::GetGroupKey(request, group)) =>
+                Some(Delivered::AskForKeys(request, group)),
+            Some(GroupResult::Resolved(_, claim)) => Some(Delivered::Resolved(claim)),
+            Some(GroupResult::AlreadyResolved(_, claim)) =>
+                Some(Delivered::AlreadyResolved(claim)),
+            Some(GroupResult::Rejected(_)) => panic!("Claim within limits rejected"),
+            None => None,
+        }
+    }
+
+    fn add_node(&mut self) {
+        let name = self.rng.gen();
+        let key_pair = sign::keypair_from_seed(&sign::Seed(self.rng.gen()));
+        let _ = self.nodes.insert(name, key_pair);
+    }
+
+    // With the churn percentage as odds, replaces a random node by a new one.
+    fn churn(&mut self) {
+        if self.rng.gen_range(0, 100) >= self.faults.churn_percent {
+            return;
+        }
+        let index = self.rng.gen_range(0, self.nodes.len());
+        let leaving = *self.nodes.keys().nth(index).expect("Node in range");
+        let _ = self.nodes.remove(&leaving);
+        self.add_node();
+    }
+
+    // Members that have left since they were asked don't answer.
+    fn answer_get_group_key(&mut self, request: SimRequest, member: u64) {
+        let secret_key = match self.nodes.get(&member) {
+            Some(&(_, ref secret_key)) => secret_key.clone(),
+            None => return,
+        };
+        let group_claim = SimGroupClaim {
+            identities: self.group_keys(&request, member).into_iter().map(|(name, public_key)| {
+                SimId { name: name, public_key: public_key.0 }
+            }).collect(),
+        };
+        let serialised = group_claim.serialise();
+        let signature = sign::sign_detached(&serialised, &secret_key);
+        self.send(Message::KeyResponse(request, member, serialised, signature, group_claim));
+    }
+
+    fn answer_get_key(&mut self, request: SimRequest, member: u64) {
+        if !self.nodes.contains_key(&member) {
+            return;
+        }
+        let keys = self.group_keys(&request, member);
+        self.send(Message::Keys(request, member, keys));
+    }
+
+    // The keys of the source group of `request`, as `member` lists them.
+    fn group_keys(&self, request: &SimRequest, member: u64) -> Vec<(u64, sign::PublicKey)> {
+        let colluding = self.colluders.contains(&member);
+        self.close_group(request.source).into_iter().map(|name| {
+            let public_key = match self.forged_keys.get(&name) {
+                Some(forged_key) if colluding => forged_key.0,
+                _ => self.nodes[&name].0,
+            };
+            (name, public_key)
+        }).collect()
+    }
+
+    fn send(&mut self, message: Message) {
+        if self.rng.gen_range(0, 100) < self.faults.drop_percent {
+            return;
+        }
+        let delay = self.rng.gen_range(1, self.faults.max_delay + 1);
+        self.sent += 1;
+        let _ = self.in_flight.insert((self.step + delay, self.sent), message);
+    }
+}
+
+mod test {
+    use super::*;
+
+    const RUN_COUNT: u32 = 1000;
+
+    // Runs each seed in a fresh network with a fresh receiver. Every resolution has to be to
+    // the claim sent. Returns the number of runs that resolved.
+    fn run_seeds<F>(faults: Faults, receiver: F) -> u32 where F: Fn() -> Receiver {
+        (1..RUN_COUNT + 1).filter(|seed| {
+            let outcome = Network::new(*seed, faults).run(&mut receiver());
+            match outcome.resolved {
+                Some(ref resolved) => {
+                    assert!(*resolved == outcome.claim, "Seed {} resolved to another claim", seed);
+                    true
+                }
+                None => false,
+            }
+        }).count() as u32
+    }
+
+    #[test]
+    fn reordered_messages_resolve() {
+        let faults = Faults { drop_percent: 0, max_delay: 10, churn_percent: 0 };
+        assert_eq!(run_seeds(faults, Receiver::group), RUN_COUNT);
+        assert_eq!(run_seeds(faults, Receiver::node), RUN_COUNT);
+    }
+
+    // Odds of at least `count` of `trials` independent events of the given odds happening.
+    fn at_least(count: usize, trials: usize, odds: f64) -> f64 {
+        (count..trials + 1).fold(0.0, |total, happening| {
+            let ways = (0..happening).fold(1.0, |ways, k| {
+                ways * (trials - k) as f64 / (k + 1) as f64
+            });
+            total +
+            ways * odds.powi(happening as i32) * (1.0 - odds).powi((trials - happening) as i32)
+        })
+    }
+
+    // Bounds on the runs resolving, if each does with the given odds: four standard
+    // deviations either side of the expected number.
+    fn expected_resolved(odds: f64) -> (u32, u32) {
+        let expected = RUN_COUNT as f64 * odds;
+        let deviation = (expected * (1.0 - odds)).sqrt();
+        ((expected - 4.0 * deviation).floor() as u32, (expected + 4.0 * deviation).ceil() as u32)
+    }
+
+    #[test]
+    fn lost_messages_stay_safe() {
+        // With no retries, a run resolves when a quorum of claims arrives, and enough key
+        // responses for the keys of their senders, each of which needs both its request and
+        // itself to arrive. No one vouches for its own key, so it takes a response from one
+        // more than a quorum for the key of every responder to be vouched for by a quorum.
+        let faults = Faults { drop_percent: 5, max_delay: 10, churn_percent: 0 };
+        let arrival = 1.0 - faults.drop_percent as f64 / 100.0;
+        let (least, most) = expected_resolved(at_least(QUORUM, GROUP_SIZE, arrival) *
+                                              at_least(QUORUM + 1, GROUP_SIZE,
+                                                       arrival * arrival));
+        for resolved in vec![run_seeds(faults, Receiver::group),
+                             run_seeds(faults, Receiver::node)] {
+            assert!(least <= resolved && resolved <= most,
+                    "{} runs resolved, expected {} to {}", resolved, least, most);
+        }
+    }
+
+    #[test]
+    fn churn_stays_safe() {
+        // A run delivers a claim, a request for keys and a key response per member. A node
+        // leaving the source group, or joining it, at a delivery changes the group. A run
+        // outlasts one change, after which its members still list one more than a quorum in
+        // common, but not necessarily two.
+        let faults = Faults { drop_percent: 0, max_delay: 10, churn_percent: 5 };
+        let change = faults.churn_percent as f64 / 100.0 * (2 * GROUP_SIZE) as f64 /
+                     NODE_COUNT as f64;
+        let (least, _) = expected_resolved(1.0 - at_least(2, 3 * GROUP_SIZE, change));
+        for resolved in vec![run_seeds(faults, Receiver::group),
+                             run_seeds(faults, Receiver::node)] {
+            assert!(resolved >= least, "{} runs resolved, expected at least {}", resolved, least);
+        }
+    }
+
+    #[test]
+    fn runs_reproduced_from_seed() {
+        let faults = Faults { drop_percent: 10, max_delay: 10, churn_percent: 10 };
+        for seed in 1..11 {
+            assert_eq!(Network::new(seed, faults).run(&mut Receiver::group()),
+                       Network::new(seed, faults).run(&mut Receiver::group()));
+            assert_eq!(Network::new(seed, faults).run(&mut Receiver::node()),
+                       Network::new(seed, faults).run(&mut Receiver::node()));
+        }
+    }
+}
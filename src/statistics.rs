@@ -15,43 +15,189 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-pub struct Frequency<Key: PartialEq + Eq + Clone> {
-    map: Vec<(Key, usize)>,
+//! Counting of equal values, used to find the claims a quorum agrees on.
+//!
+//! Votes can be weighted, so that for instance established nodes carry more say than fresh
+//! joiners. A quorum is then a threshold on the summed weight rather than on the number of
+//! votes; with the default weight of one the two are the same.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// Weight each name carries when voting. Names without an explicit weight carry the default.
+#[derive(Clone, Debug)]
+pub struct Weights<Name: Ord> {
+    map: BTreeMap<Name, usize>,
+    default: usize,
+}
+
+impl<Name: Ord> Weights<Name> {
+    /// Creates a table in which every name carries a weight of one.
+    pub fn new() -> Weights<Name> {
+        Weights::with_default(1)
+    }
+
+    /// Creates a table in which names without an explicit weight carry `default`.
+    pub fn with_default(default: usize) -> Weights<Name> {
+        Weights { map: BTreeMap::new(), default: default }
+    }
+
+    /// Sets the weight of `name`.
+    pub fn set(&mut self, name: Name, weight: usize) {
+        let _ = self.map.insert(name, weight);
+    }
+
+    /// Reverts `name` to the default weight.
+    pub fn remove(&mut self, name: &Name) {
+        let _ = self.map.remove(name);
+    }
+
+    /// Returns the weight of `name`.
+    pub fn get(&self, name: &Name) -> usize {
+        self.map.get(name).cloned().unwrap_or(self.default)
+    }
+
+    /// Returns the summed weight of `names`.
+    pub fn total<'a, Names>(&self, names: Names) -> usize
+        where Names: Iterator<Item = &'a Name>,
+              Name: 'a
+    {
+        names.fold(0, |total, name| total.saturating_add(self.get(name)))
+    }
+}
+
+impl<Name: Ord> Default for Weights<Name> {
+    fn default() -> Weights<Name> {
+        Weights::new()
+    }
+}
+
+/// Counts the occurrences of each distinct key, optionally weighted.
+///
+/// Updates are O(1). `at_least` is O(n) plus sorting the keys it returns, and `top_k` is
+/// O(n log k); neither sorts or clones the whole map. With weighted updates every count
+/// below is a summed weight.
+pub struct Frequency<Key: Hash + Eq + Clone> {
+    map: HashMap<Key, usize>,
 }
 
-impl<Key: PartialEq + Eq + Clone> Frequency<Key> {
+impl<Key: Hash + Eq + Clone> Frequency<Key> {
+    /// Creates an empty frequency count.
     pub fn new() -> Frequency<Key> {
-        Frequency { map: Vec::<(Key, usize)>::new() }
+        Frequency { map: HashMap::new() }
     }
 
+    /// Counts one more occurrence of `key`.
     pub fn update(&mut self, key: &Key) {
-        let mut fresh_key: bool = true;
-        for stored_key in self.map.iter_mut() {
-            if &stored_key.0 == key {
-                stored_key.1 += 1;
-                fresh_key = false;
-                break;
-            }
+        self.update_weighted(key, 1)
+    }
+
+    /// Counts one more occurrence of `key`, carrying `weight`.
+    pub fn update_weighted(&mut self, key: &Key, weight: usize) {
+        if let Some(count) = self.map.get_mut(key) {
+            *count = count.saturating_add(weight);
+            return;
+        }
+        let _ = self.map.insert(key.clone(), weight);
+    }
+
+    /// Returns the number of occurrences counted for `key`.
+    pub fn count(&self, key: &Key) -> usize {
+        self.map.get(key).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of distinct keys counted.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if nothing has been counted.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns all keys with at least `threshold` occurrences, most frequent first and
+    /// ordered by key among equals.
+    pub fn at_least(&self, threshold: usize) -> Vec<(&Key, usize)> where Key: Ord {
+        let mut agreed = self.map.iter()
+                                 .filter(|&(_, count)| *count >= threshold)
+                                 .map(|(key, count)| (key, *count))
+                                 .collect::<Vec<_>>();
+        agreed.sort_by(|a, b| (b.1, a.0).cmp(&(a.1, b.0)));
+        agreed
+    }
+
+    /// Returns the `k` most frequent keys, most frequent first. Ties are broken arbitrarily.
+    pub fn top_k(&self, k: usize) -> Vec<(&Key, usize)> {
+        if k == 0 {
+            return Vec::new();
         }
-        if fresh_key {
-            self.map.push((key.clone(), 1));
+
+        // Min-heap on count holding the k most frequent keys seen so far.
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (key, count) in self.map.iter() {
+            heap.push(LeastFirst(*count, key));
+            if heap.len() > k {
+                let _ = heap.pop();
+            }
         }
+
+        let mut top = heap.into_iter()
+                          .map(|LeastFirst(count, key)| (key, count))
+                          .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.1.cmp(&a.1));
+        top
     }
 
-    pub fn sort_by_highest(&mut self) -> Vec<(Key, usize)> {
-        self.map.sort_by(|a,b| b.1.cmp(&a.1));
-        self.map.clone()
+    /// Returns all keys with their counts, most frequent first.
+    pub fn sort_by_highest(&self) -> Vec<(Key, usize)> {
+        let mut all = self.map.iter()
+                              .map(|(key, count)| (key.clone(), *count))
+                              .collect::<Vec<_>>();
+        all.sort_by(|a, b| b.1.cmp(&a.1));
+        all
     }
 }
 
+impl<Key: Hash + Eq + Clone> Default for Frequency<Key> {
+    fn default() -> Frequency<Key> {
+        Frequency::new()
+    }
+}
+
+// Orders entries by count only, reversed, so `BinaryHeap` pops the least frequent first.
+struct LeastFirst<'a, Key: 'a>(usize, &'a Key);
+
+impl<'a, Key> Ord for LeastFirst<'a, Key> {
+    fn cmp(&self, other: &LeastFirst<'a, Key>) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl<'a, Key> PartialOrd for LeastFirst<'a, Key> {
+    fn partial_cmp(&self, other: &LeastFirst<'a, Key>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, Key> PartialEq for LeastFirst<'a, Key> {
+    fn eq(&self, other: &LeastFirst<'a, Key>) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<'a, Key> Eq for LeastFirst<'a, Key> { }
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use rand::{Rng, thread_rng};
+    use rand::Rng;
+    use testing::seeded_rng;
 
     #[test]
     fn fill_monotonic_distribution() {
-        let mut rng = thread_rng();
+        let mut rng = seeded_rng(1);
 
         // ensure a monotonic decreasing function
         let domain_low = 0u32;
@@ -94,4 +240,56 @@ mod test {
             max_count = value.1.clone();
         };
     }
+
+    #[test]
+    fn threshold_and_top_k() {
+        let mut freq = Frequency::new();
+        for key in 0u32..10 {
+            for _ in 0..key + 1 {
+                freq.update(&key);
+            }
+        }
+
+        assert_eq!(freq.len(), 10);
+        assert_eq!(freq.count(&7), 8);
+        assert_eq!(freq.count(&10), 0);
+
+        let at_least = freq.at_least(8).into_iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        assert_eq!(at_least, vec![9, 8, 7]);
+        assert!(freq.at_least(11).is_empty());
+
+        // Keys counted equally often come in key order, whatever the order of the map.
+        let mut ties = Frequency::new();
+        for key in [5u32, 1, 3].iter() {
+            ties.update(key);
+        }
+        assert_eq!(ties.at_least(1), vec![(&1, 1), (&3, 1), (&5, 1)]);
+
+        let top = freq.top_k(3).into_iter().map(|(key, count)| (*key, count)).collect::<Vec<_>>();
+        assert_eq!(top, vec![(9, 10), (8, 9), (7, 8)]);
+        assert_eq!(freq.top_k(20).len(), 10);
+        assert!(freq.top_k(0).is_empty());
+    }
+
+    #[test]
+    fn weighted_updates() {
+        let mut weights = Weights::new();
+        weights.set('a', 3);
+        weights.set('b', 0);
+        assert_eq!(weights.get(&'a'), 3);
+        assert_eq!(weights.get(&'c'), 1);
+        assert_eq!(weights.total(['a', 'b', 'c'].iter()), 4);
+        weights.remove(&'a');
+        assert_eq!(weights.get(&'a'), 1);
+
+        let mut freq = Frequency::new();
+        freq.update_weighted(&"heavy", 5);
+        freq.update(&"light");
+        freq.update(&"light");
+        freq.update_weighted(&"heavy", 2);
+
+        assert_eq!(freq.count(&"heavy"), 7);
+        assert_eq!(freq.count(&"light"), 2);
+        assert_eq!(freq.at_least(3), vec![(&"heavy", 7)]);
+    }
 }
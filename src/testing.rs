@@ -0,0 +1,363 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Fixtures for testing code built on the sentinels, built with the `testing` feature.
+//!
+//! A `SignedGroup` holds the Ed25519 key pairs of a group of named members. It signs claims
+//! as the group would send them, and answers GetGroupKey with the responses `KeySentinel` and
+//! `GroupSentinel` expect, using `TestId` and `TestGroupClaim` for the identities. An
+//! attacking group can sign claims under the names of another group, and answer GetGroupKey
+//! listing its own keys under their names. `MockScheme` stands in for a signature scheme
+//! and `TraceGetKeys` for the sender of key requests.
+//!
+//! Randomness is drawn from seeded generators, so that a failing test fails the same way on
+//! every run. Time is left to a `clock::ManualClock`.
+
+use std::cell::RefCell;
+use rand::{Rand, Rng, SeedableRng, XorShiftRng};
+use rustc_serialize::Encodable;
+use sodiumoxide::crypto::sign;
+use key_sentinel::{GroupClaimTrait, IdTrait};
+use pure_sentinel::Source;
+use send_get_keys::{KeyRequest, SendGetKeys};
+use signature_scheme::SignatureScheme;
+use std::sync::{Arc, Mutex};
+use {SerialisedClaim, bound_claim, serialise};
+
+/// A request sent by the group of `source`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Debug, RustcEncodable, RustcDecodable)]
+pub struct TestRequest<Name> {
+    /// Distinguishes requests of the same source.
+    pub id: u64,
+    /// Address the request was sent from.
+    pub source: Name,
+}
+
+impl<Name> Source<Name> for TestRequest<Name>
+    where Name: Eq + PartialOrd + Ord + Clone + Encodable
+{
+    fn get_source(&self) -> Name {
+        self.source.clone()
+    }
+}
+
+/// A member's name and public key, as listed in a GetGroupKey response.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TestId<Name> {
+    /// Name of the member.
+    pub name: Name,
+    /// Bytes of the public key of the member.
+    pub public_key: [u8; sign::PUBLICKEYBYTES],
+}
+
+impl<Name: Clone> IdTrait<Name> for TestId<Name> {
+    fn name(&self) -> Name {
+        self.name.clone()
+    }
+
+    fn public_key(&self) -> sign::PublicKey {
+        sign::PublicKey(self.public_key)
+    }
+}
+
+/// The identities of a group, as a GetGroupKey response lists them.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TestGroupClaim<Name> {
+    /// Identities of the members.
+    pub identities: Vec<TestId<Name>>,
+}
+
+impl<Name> TestGroupClaim<Name> {
+    /// Returns the bytes a response signs: the public keys listed, in order.
+    pub fn serialise(&self) -> SerialisedClaim {
+        self.identities.iter().flat_map(|id| id.public_key.iter().cloned()).collect()
+    }
+}
+
+impl<Name: Clone> GroupClaimTrait<TestId<Name>> for TestGroupClaim<Name> {
+    fn group_identities(&self) -> Vec<TestId<Name>> {
+        self.identities.clone()
+    }
+}
+
+/// A claim signed by `claimant`, as passed to `PureSentinel::add_claim`.
+#[derive(Clone)]
+pub struct SignedClaim<Name> {
+    /// Name the claim is sent under.
+    pub claimant: Name,
+    /// Signature over `claim` bound to its request.
+    pub signature: sign::Signature,
+    /// The serialised claim.
+    pub claim: SerialisedClaim,
+}
+
+/// A GetGroupKey response, as passed to `KeySentinel::add_identities`.
+#[derive(Clone)]
+pub struct KeyResponse<Name> {
+    /// Name the response is sent under.
+    pub sender: Name,
+    /// Serialised form of `group_claim`.
+    pub serialised: SerialisedClaim,
+    /// Signature of the sender over `serialised`.
+    pub signature: sign::Signature,
+    /// The identities listed.
+    pub group_claim: TestGroupClaim<Name>,
+}
+
+/// Named members with their key pairs.
+#[derive(Clone)]
+pub struct SignedGroup<Name> {
+    members: Vec<(Name, (sign::PublicKey, sign::SecretKey))>,
+}
+
+impl<Name> SignedGroup<Name> where Name: Clone {
+    /// Creates a group of `names` with key pairs seeded from `random`.
+    pub fn new(names: Vec<Name>) -> SignedGroup<Name> {
+        SignedGroup { members: names.into_iter().map(|name| (name, random_keypair())).collect() }
+    }
+
+    /// Creates a group of `names` with key pairs drawn from `rng`, so that the same seed gives
+    /// the same keys.
+    pub fn from_rng<R: Rng>(names: Vec<Name>, rng: &mut R) -> SignedGroup<Name> {
+        SignedGroup {
+            members: names.into_iter().map(|name| {
+                (name, sign::keypair_from_seed(&sign::Seed(rng.gen())))
+            }).collect(),
+        }
+    }
+
+    /// Creates a group of `size` members with random names and key pairs drawn from `rng`.
+    pub fn random<R: Rng>(size: usize, rng: &mut R) -> SignedGroup<Name> where Name: Rand {
+        let names = (0..size).map(|_| rng.gen()).collect();
+        SignedGroup::from_rng(names, rng)
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns the names of the members.
+    pub fn names(&self) -> Vec<Name> {
+        self.members.iter().map(|&(ref name, _)| name.clone()).collect()
+    }
+
+    /// Returns the names and public keys of the members, as passed to `add_keys`.
+    pub fn keys(&self) -> Vec<(Name, sign::PublicKey)> {
+        self.members.iter().map(|&(ref name, ref key_pair)| (name.clone(), key_pair.0)).collect()
+    }
+
+    /// Splits the group into its first `at` members and the rest.
+    pub fn split_at(&self, at: usize) -> (SignedGroup<Name>, SignedGroup<Name>) {
+        let (first, rest) = self.members.split_at(at);
+        (SignedGroup { members: first.to_vec() }, SignedGroup { members: rest.to_vec() })
+    }
+
+    /// Returns the identities of the members.
+    pub fn group_claim(&self) -> TestGroupClaim<Name> {
+        TestGroupClaim {
+            identities: self.members.iter().map(|&(ref name, ref key_pair)| {
+                TestId { name: name.clone(), public_key: (key_pair.0).0 }
+            }).collect(),
+        }
+    }
+
+    /// Returns `claim` for `request` signed by each member.
+    pub fn sign_claims<Request>(&self,
+                                request: &Request,
+                                claim: &SerialisedClaim)
+                                -> Vec<SignedClaim<Name>>
+        where Request: Encodable
+    {
+        self.impersonate(self, request, claim)
+    }
+
+    /// Returns the GetGroupKey response of each member, listing the whole group.
+    pub fn key_responses(&self) -> Vec<KeyResponse<Name>> {
+        self.respond(self.group_claim())
+    }
+
+    /// Returns `claim` for `request` sent under the names of `victims` and signed with the
+    /// keys of the members, one member per victim.
+    pub fn impersonate<Request>(&self,
+                                victims: &SignedGroup<Name>,
+                                request: &Request,
+                                claim: &SerialisedClaim)
+                                -> Vec<SignedClaim<Name>>
+        where Request: Encodable
+    {
+        self.members.iter().zip(victims.members.iter()).map(|(&(_, ref key_pair), victim)| {
+            SignedClaim {
+                claimant: victim.0.clone(),
+                signature: sign_claim(request, claim, &key_pair.1),
+                claim: claim.clone(),
+            }
+        }).collect()
+    }
+
+    /// Returns the public keys of the members under the names of `victims`, as forged keys
+    /// passed to `add_keys`.
+    pub fn keys_as(&self, victims: &SignedGroup<Name>) -> Vec<(Name, sign::PublicKey)> {
+        victims.names().into_iter().zip(self.members.iter().map(|member| (member.1).0)).collect()
+    }
+
+    /// Returns the GetGroupKey response of each member, listing the members themselves and
+    /// the names of `victims` with keys of the members, so that the members can impersonate
+    /// them once the keys are accepted.
+    pub fn forged_key_responses(&self, victims: &SignedGroup<Name>) -> Vec<KeyResponse<Name>> {
+        let mut group_claim = self.group_claim();
+        group_claim.identities.extend(self.keys_as(victims).into_iter().map(|(name, key)| {
+            TestId { name: name, public_key: key.0 }
+        }));
+        self.respond(group_claim)
+    }
+
+    fn respond(&self, group_claim: TestGroupClaim<Name>) -> Vec<KeyResponse<Name>> {
+        let serialised = group_claim.serialise();
+        self.members.iter().map(|&(ref name, ref key_pair)| {
+            KeyResponse {
+                sender: name.clone(),
+                serialised: serialised.clone(),
+                signature: sign::sign_detached(&serialised, &key_pair.1),
+                group_claim: group_claim.clone(),
+            }
+        }).collect()
+    }
+}
+
+/// Seed of the generator `random` draws from.
+pub const SEED: [u32; 4] = [0x9e37_79b9, 0x7f4a_7c15, 0xf39c_c060, 0x5ced_c834];
+
+thread_local!(static RNG: RefCell<XorShiftRng> = RefCell::new(XorShiftRng::from_seed(SEED)));
+
+/// Returns a value drawn from a generator seeded with `SEED` on each thread, in place of
+/// `rand::random`.
+pub fn random<T: Rand>() -> T {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// Seeds the generator `random` draws from on this thread with `SEED` again. Tests drawing
+/// from `random` call it first, as tests can share a thread, so that each draws the same
+/// values whichever tests ran before it.
+pub fn reset_random() {
+    RNG.with(|rng| *rng.borrow_mut() = XorShiftRng::from_seed(SEED))
+}
+
+/// Returns an Ed25519 key pair seeded from `random`, in place of `gen_keypair`.
+pub fn random_keypair() -> (sign::PublicKey, sign::SecretKey) {
+    sign::keypair_from_seed(&sign::Seed(random()))
+}
+
+/// Returns a generator seeded with `seed`, for tests that run over several seeds.
+pub fn seeded_rng(seed: u32) -> XorShiftRng {
+    XorShiftRng::from_seed([seed, SEED[1], SEED[2], SEED[3]])
+}
+
+/// Returns the Ed25519 key pair drawn from `seeded_rng(seed)`, in place of `gen_keypair`.
+pub fn seeded_keypair(seed: u32) -> (sign::PublicKey, sign::SecretKey) {
+    sign::keypair_from_seed(&sign::Seed(seeded_rng(seed).gen()))
+}
+
+/// Returns the signature with `secret_key` over `claim` bound to `request`, as
+/// `PureSentinel::add_claim` verifies it.
+pub fn sign_claim<Request>(request: &Request,
+                           claim: &SerialisedClaim,
+                           secret_key: &sign::SecretKey)
+                           -> sign::Signature
+    where Request: Encodable
+{
+    sign::sign_detached(&bound_claim(&serialise(request), claim), secret_key)
+}
+
+/// Returns `size` random bytes drawn from `rng`, for use as a claim.
+pub fn random_claim<R: Rng>(size: usize, rng: &mut R) -> SerialisedClaim {
+    (0..size).map(|_| rng.gen()).collect()
+}
+
+/// A signature scheme for tests, in which a signature is the signing key followed by the
+/// signed bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MockScheme;
+
+impl SignatureScheme for MockScheme {
+    type PublicKey = u64;
+    type Signature = (u64, SerialisedClaim);
+    type KeyData = u64;
+    type SignatureData = (u64, SerialisedClaim);
+
+    fn verify(signature: &(u64, SerialisedClaim), public_key: &u64, message: &[u8]) -> bool {
+        signature.0 == *public_key && &signature.1[..] == message
+    }
+
+    fn key_data(public_key: &u64) -> u64 {
+        *public_key
+    }
+
+    fn signature_data(signature: &(u64, SerialisedClaim)) -> (u64, SerialisedClaim) {
+        signature.clone()
+    }
+}
+
+/// A `SendGetKeys` recording the requests sent through it, shared with the test through
+/// `calls`.
+pub struct TraceGetKeys<Name> {
+    /// Requests sent, oldest first.
+    pub calls: Arc<Mutex<Vec<KeyRequest<Name>>>>,
+}
+
+impl<Name> SendGetKeys<Name> for TraceGetKeys<Name> {
+    fn get_client_key(&mut self, address: Name) {
+        self.calls.lock().unwrap().push(KeyRequest::Client(address));
+    }
+
+    fn get_group_key(&mut self, group_address: Name) {
+        self.calls.lock().unwrap().push(KeyRequest::Group(group_address));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use key_sentinel::KeySentinel;
+
+    const QUORUM: usize = 5;
+
+    #[test]
+    fn forged_key_responses_outvoted() {
+        let mut rng = seeded_rng(1);
+        let group = SignedGroup::<u64>::random(QUORUM + 1, &mut rng);
+        let (attackers, _) = SignedGroup::<u64>::random(QUORUM + 1, &mut rng).split_at(2);
+        let mut sentinel: KeySentinel<TestRequest<u64>, u64, TestId<u64>, TestGroupClaim<u64>> =
+            KeySentinel::new();
+        let request = TestRequest { id: rng.gen(), source: rng.gen() };
+
+        let responses = attackers.forged_key_responses(&group).into_iter()
+                                 .chain(group.key_responses());
+        let mut confirmed = None;
+        for response in responses {
+            if let Some((_, ids)) = sentinel.add_identities(request.clone(), response.sender,
+                                                            response.serialised,
+                                                            response.signature,
+                                                            response.group_claim,
+                                                            QUORUM).unwrap() {
+                confirmed = Some(ids);
+            }
+        }
+
+        assert_eq!(confirmed.map(|ids| ids.len()), Some(group.len()));
+    }
+}
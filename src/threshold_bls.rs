@@ -0,0 +1,324 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Threshold BLS signatures on the BLS12-381 curve, as a `ThresholdScheme`.
+//!
+//! Public keys are points of G1 and signatures points of G2, with messages hashed to G2 as in
+//! the `BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_` ciphersuite. The secret key of a group is
+//! the constant term of a polynomial of degree `threshold - 1`, and the member at `index`
+//! holds its value at `index + 1`. The public key set of the group holds the commitments to
+//! the coefficients, from which the public key share of each member is derived, and the
+//! number of members, so that shares of indices outside the group don't verify. Any
+//! `threshold` verified shares over a message are combined into the signature of the group
+//! by Lagrange interpolation at zero, the same whichever members signed.
+//!
+//! `SecretKeySet` deals the key shares of a group from a single secret, as a trusted dealer
+//! would. Generating them without a dealer is left to the application.
+//!
+//! Only built with the `bls` feature.
+
+use bls12_381::{pairing, G1Affine, G1Projective, G2Affine, G2Projective, Scalar};
+use bls12_381::hash_to_curve::{ExpandMsgXmd, HashToCurve};
+use rand::Rng;
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use threshold_sentinel::ThresholdScheme;
+
+/// Domain separation tag messages are hashed to G2 with.
+pub const DST: &'static [u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_";
+
+/// Threshold BLS over BLS12-381.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlsThreshold;
+
+/// Commitments to the coefficients of the polynomial of a group, lowest degree first, with
+/// the number of members of the group.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PublicKeySet {
+    commitments: Vec<G1Affine>,
+    members: usize,
+}
+
+impl PublicKeySet {
+    /// Number of shares needed to produce a group signature.
+    pub fn threshold(&self) -> usize {
+        self.commitments.len()
+    }
+
+    /// Number of members of the group, indexed from zero.
+    pub fn members(&self) -> usize {
+        self.members
+    }
+
+    /// Public key of the group.
+    pub fn public_key(&self) -> G1Affine {
+        self.public_key_share_at(Scalar::zero())
+    }
+
+    /// Public key share of the member at `index`, or None if the group has no such member.
+    pub fn public_key_share(&self, index: usize) -> Option<G1Affine> {
+        member_point(index, self.members).map(|point| self.public_key_share_at(point))
+    }
+
+    /// The number of members as eight big-endian bytes, followed by the commitments
+    /// compressed and concatenated.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let members = self.members as u64;
+        (0..8).rev().map(|byte| (members >> (8 * byte)) as u8)
+            .chain(self.commitments.iter()
+                                   .flat_map(|commitment| commitment.to_compressed().to_vec()))
+            .collect()
+    }
+
+    fn public_key_share_at(&self, point: Scalar) -> G1Affine {
+        let share = self.commitments.iter().rev().fold(G1Projective::identity(), |share, next| {
+            &share * &point + G1Projective::from(next)
+        });
+        G1Affine::from(share)
+    }
+}
+
+/// Coefficients of the polynomial of a group, lowest degree first, with the number of
+/// members of the group.
+pub struct SecretKeySet {
+    coefficients: Vec<Scalar>,
+    members: usize,
+}
+
+impl SecretKeySet {
+    /// Draws the polynomial of a group of `members` needing `threshold` shares for a
+    /// signature, which has to be at least one and at most `members`.
+    pub fn random<R: Rng>(threshold: usize, members: usize, rng: &mut R) -> SecretKeySet {
+        assert!(threshold > 0, "A threshold of zero shares can't sign");
+        assert!(threshold <= members, "A threshold above the members can't be reached");
+        SecretKeySet {
+            members: members,
+            coefficients: (0..threshold).map(|_| {
+                let mut bytes = [0u8; 64];
+                for byte in bytes.iter_mut() {
+                    *byte = rng.gen();
+                }
+                Scalar::from_bytes_wide(&bytes)
+            }).collect(),
+        }
+    }
+
+    /// The public key set of the group.
+    pub fn public_keys(&self) -> PublicKeySet {
+        PublicKeySet {
+            commitments: self.coefficients.iter()
+                                          .map(|coefficient| {
+                                              G1Affine::from(G1Projective::generator() *
+                                                             coefficient)
+                                          })
+                                          .collect(),
+            members: self.members,
+        }
+    }
+
+    /// Secret key share of the member at `index`, or None if the group has no such member.
+    pub fn secret_key_share(&self, index: usize) -> Option<SecretKeyShare> {
+        member_point(index, self.members).map(|point| {
+            SecretKeyShare(self.coefficients.iter().rev().fold(Scalar::zero(), |share, next| {
+                share * point + next
+            }))
+        })
+    }
+}
+
+/// Secret key share of a single member.
+pub struct SecretKeyShare(Scalar);
+
+impl SecretKeyShare {
+    /// Signs `message`, producing the share of the member.
+    pub fn sign(&self, message: &[u8]) -> G2Affine {
+        G2Affine::from(hash_to_g2(message) * self.0)
+    }
+}
+
+impl ThresholdScheme for BlsThreshold {
+    type PublicKeySet = PublicKeySet;
+    type SignatureShare = G2Affine;
+    type Signature = G2Affine;
+    type KeySetData = Vec<u8>;
+
+    fn threshold(keys: &PublicKeySet) -> usize {
+        keys.threshold()
+    }
+
+    fn key_set_data(keys: &PublicKeySet) -> Vec<u8> {
+        keys.to_bytes()
+    }
+
+    fn verify_share(keys: &PublicKeySet, index: usize, share: &G2Affine, message: &[u8]) -> bool {
+        keys.public_key_share(index).map_or(false, |public_key| {
+            verify_with(&public_key, share, message)
+        })
+    }
+
+    fn combine(keys: &PublicKeySet, shares: &[(usize, G2Affine)]) -> Option<G2Affine> {
+        let mut members = BTreeSet::new();
+        let shares = shares.iter()
+                           .filter(|&&(index, _)| members.insert(index))
+                           .take(keys.threshold())
+                           .collect::<Vec<_>>();
+        if shares.len() < keys.threshold() {
+            return None;
+        }
+
+        // Interpolates the signatures of the members at zero.
+        let points = match shares.iter().map(|&&(index, _)| member_point(index, keys.members))
+                                 .collect::<Option<Vec<_>>>() {
+            Some(points) => points,
+            None => return None,
+        };
+        let mut signature = G2Projective::identity();
+        for (position, &&(_, ref share)) in shares.iter().enumerate() {
+            let mut coefficient = Scalar::one();
+            for (other, point) in points.iter().enumerate() {
+                if other == position {
+                    continue;
+                }
+                let inverse: Option<Scalar> = (point - points[position]).invert().into();
+                coefficient = match inverse {
+                    Some(inverse) => coefficient * point * &inverse,
+                    None => return None,
+                };
+            }
+            signature = signature + G2Projective::from(share) * coefficient;
+        }
+        Some(G2Affine::from(signature))
+    }
+
+    fn verify(keys: &PublicKeySet, signature: &G2Affine, message: &[u8]) -> bool {
+        verify_with(&keys.public_key(), signature, message)
+    }
+}
+
+// Members are numbered from one, as the secret of the group is the value at zero. None if
+// `index` is not one of `members`.
+fn member_point(index: usize, members: usize) -> Option<Scalar> {
+    if index >= members {
+        return None;
+    }
+    index.checked_add(1).map(|point| Scalar::from(point as u64))
+}
+
+fn hash_to_g2(message: &[u8]) -> G2Projective {
+    <G2Projective as HashToCurve<ExpandMsgXmd<Sha256>>>::hash_to_curve(message, DST)
+}
+
+fn verify_with(public_key: &G1Affine, signature: &G2Affine, message: &[u8]) -> bool {
+    if bool::from(public_key.is_identity()) || bool::from(signature.is_identity()) {
+        return false;
+    }
+    pairing(&G1Affine::generator(), signature) ==
+        pairing(public_key, &G2Affine::from(hash_to_g2(message)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pure_sentinel::Source;
+    use testing::seeded_rng;
+    use threshold_sentinel::{ThresholdResult, ThresholdScheme, ThresholdSentinel};
+
+    const GROUP_SIZE: usize = 8;
+    const THRESHOLD: usize = 5;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        group: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.group
+        }
+    }
+
+    #[test]
+    fn any_threshold_of_shares_signs_for_the_group() {
+        let secret_keys = SecretKeySet::random(THRESHOLD, GROUP_SIZE, &mut seeded_rng(31));
+        let keys = secret_keys.public_keys();
+        let message = b"claim";
+        let shares = (0..GROUP_SIZE).map(|index| {
+            (index, secret_keys.secret_key_share(index).unwrap().sign(message))
+        }).collect::<Vec<_>>();
+
+        for &(index, ref share) in shares.iter() {
+            assert!(BlsThreshold::verify_share(&keys, index, share, message));
+            assert!(!BlsThreshold::verify_share(&keys, index + 1, share, message));
+            assert!(!BlsThreshold::verify_share(&keys, index, share, b"other claim"));
+        }
+
+        // Indices outside the group have no key share, up to the largest.
+        assert!(secret_keys.secret_key_share(GROUP_SIZE).is_none());
+        for &index in [GROUP_SIZE, usize::max_value()].iter() {
+            assert!(!BlsThreshold::verify_share(&keys, index, &shares[0].1, message));
+            let mut outside = shares[..THRESHOLD].to_vec();
+            outside[0].0 = index;
+            assert!(BlsThreshold::combine(&keys, &outside).is_none());
+        }
+
+        let first = BlsThreshold::combine(&keys, &shares[..THRESHOLD]).unwrap();
+        let last = BlsThreshold::combine(&keys, &shares[GROUP_SIZE - THRESHOLD..]).unwrap();
+        assert_eq!(first, last);
+        assert!(BlsThreshold::verify(&keys, &first, message));
+        assert!(!BlsThreshold::verify(&keys, &first, b"other claim"));
+        assert!(!BlsThreshold::verify(&SecretKeySet::random(THRESHOLD, GROUP_SIZE,
+                                                            &mut seeded_rng(32))
+                                          .public_keys(),
+                                      &first,
+                                      message));
+
+        // A share repeated doesn't stand in for another member.
+        let mut repeated = shares[..THRESHOLD - 1].to_vec();
+        repeated.push(shares[0].clone());
+        assert!(BlsThreshold::combine(&keys, &repeated).is_none());
+    }
+
+    #[test]
+    fn sentinel_resolves_to_group_signature() {
+        let secret_keys = SecretKeySet::random(THRESHOLD, GROUP_SIZE, &mut seeded_rng(33));
+        let keys = secret_keys.public_keys();
+        let request = TestRequest { core: 0, group: 1 };
+        let claim = b"claim".to_vec();
+        let mut sentinel = ThresholdSentinel::<TestRequest, u64, BlsThreshold>::new();
+
+        for sender in 0..2 {
+            assert!(sentinel.add_group_key(request.clone(), sender, keys.clone(), 2).is_none());
+        }
+
+        for index in 0..THRESHOLD {
+            let share = secret_keys.secret_key_share(index).unwrap().sign(&claim);
+            match sentinel.add_share(request.clone(), index, share, claim.clone()) {
+                Some(ThresholdResult::Resolved(resolved, resolved_claim, signature)) => {
+                    assert_eq!(index + 1, THRESHOLD);
+                    assert_eq!(resolved, request);
+                    assert!(ThresholdSentinel::<TestRequest, u64, BlsThreshold>::verify(
+                        &keys, &signature, &resolved_claim));
+                    return;
+                }
+                None => assert!(index + 1 < THRESHOLD),
+                _ => panic!("unexpected result"),
+            }
+        }
+        panic!("expected resolution");
+    }
+}
@@ -0,0 +1,680 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! ThresholdSentinel confirms group consensus from threshold signature shares.
+//!
+//! Instead of one full signature per group member, each member sends a share of the group
+//! signature, such as a BLS signature share. Shares are verified against the public key set of
+//! the source group and accumulated per claim. Once a threshold of verified shares agree on a
+//! claim they are combined into a single group signature, which anyone holding the group public
+//! key can verify without fetching the keys of the individual members.
+//!
+//! Threshold BLS over BLS12-381 is provided by `threshold_bls::BlsThreshold`, with the `bls`
+//! feature. Other schemes are plugged in by implementing `ThresholdScheme`.
+//!
+//! The public key set of a group is only used once a quorum of distinct senders has sent
+//! the same key set, as keys are in `KeyStore`, and is then remembered for later requests
+//! from the group. Shares are held against the same `ClaimLimits` as the claims of
+//! `PureSentinel`, and at most `MAX_SHARES_PER_MEMBER` per member index and
+//! `MAX_SHARES_PER_REQUEST` in all are held per request. When the total is reached, the
+//! requests with shares of the fewest members are evicted. Once the key set of the group is
+//! known, the shares held for its requests that don't verify are dropped, and later ones
+//! that don't are dropped on arrival, so that junk only holds a request until then.
+//!
+//! As with `PureSentinel`, group keys can be asked for through a `SendGetKeys` sink set with
+//! `set_key_sink`, in place of returning `ThresholdResult::RequestKeys`. Key requests still
+//! outstanding are timed with the clock given to `set_clock`.
+
+use lru_time_cache::LruCache;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use clock::{self, Clock};
+use eviction::{self, Progress};
+use limits::{ByteBudget, ClaimLimits, Rejection};
+use pure_sentinel::Source;
+use send_get_keys::{KeyRequest, KeySink, SendGetKeys};
+use statistics::Frequency;
+use super::SerialisedClaim;
+
+const MAX_REQUEST_COUNT: usize = 1000;
+const MAX_GROUP_COUNT: usize = 1000;
+/// Number of shares held per request.
+pub const MAX_SHARES_PER_REQUEST: usize = 64;
+/// Number of shares held per member index of a request.
+pub const MAX_SHARES_PER_MEMBER: usize = 2;
+/// Number of distinct key sets tallied per group until one reaches the key quorum.
+pub const MAX_KEY_SETS_PER_GROUP: usize = 8;
+
+// Key sets sent for a group, by their ordered representation, with their senders.
+type KeySetVotes<Name, Scheme> = BTreeMap<<Scheme as ThresholdScheme>::KeySetData,
+                                          (<Scheme as ThresholdScheme>::PublicKeySet,
+                                           BTreeSet<Name>)>;
+
+// Bytes of claims held for a request, with the members whose shares they came with.
+#[derive(Default)]
+struct Held {
+    bytes: usize,
+    members: BTreeSet<usize>,
+    last_seen: u64,
+}
+
+/// A threshold signature scheme, such as threshold BLS.
+pub trait ThresholdScheme {
+    /// Public key of a group, together with what is needed to verify the shares of its
+    /// members.
+    type PublicKeySet: Clone;
+    /// Signature share produced by a single group member.
+    type SignatureShare: Clone;
+    /// Group signature combined from a threshold of shares.
+    type Signature: Clone;
+    /// Ordered representation of a public key set, used to tally the senders sending it.
+    type KeySetData: Clone + Ord;
+
+    /// Number of shares needed to produce a group signature.
+    fn threshold(keys: &Self::PublicKeySet) -> usize;
+
+    /// Returns the ordered representation of `keys`.
+    fn key_set_data(keys: &Self::PublicKeySet) -> Self::KeySetData;
+
+    /// Returns true if `share` over `message` was produced by the member at `index`.
+    fn verify_share(keys: &Self::PublicKeySet,
+                    index: usize,
+                    share: &Self::SignatureShare,
+                    message: &[u8])
+                    -> bool;
+
+    /// Combines verified shares from distinct members into a group signature. Returns None
+    /// if they can't be combined, such as when too few are given.
+    fn combine(keys: &Self::PublicKeySet,
+               shares: &[(usize, Self::SignatureShare)])
+               -> Option<Self::Signature>;
+
+    /// Returns true if `signature` over `message` verifies against the group public key.
+    fn verify(keys: &Self::PublicKeySet, signature: &Self::Signature, message: &[u8]) -> bool;
+}
+
+/// Result of adding a share or a group key.
+pub enum ThresholdResult<Request, Name, Signature> {
+    /// The caller should request the public key set of the group surrounding the target.
+    RequestKeys(Name),
+    /// The request resolved to the claim, signed by the group.
+    Resolved(Request, SerialisedClaim, Signature),
+    /// The share was not held because its claim would exceed the claim limits.
+    Rejected(Rejection),
+}
+
+/// ThresholdSentinel is templated on an immutable Request type identifying the message and a
+/// Name type identifying groups, as well as the threshold scheme used by the groups.
+pub struct ThresholdSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: ThresholdScheme
+{
+    //                  +--- Member index
+    //                  V
+    shares: LruCache<Request, Vec<(usize, Scheme::SignatureShare, SerialisedClaim)>>,
+    held: BTreeMap<Request, Held>,
+    budget: ByteBudget,
+    // Stamps held requests with their last share, to break ties on eviction.
+    sequence: u64,
+    key_votes: LruCache<Name, KeySetVotes<Name, Scheme>>,
+    group_keys: LruCache<Name, Scheme::PublicKeySet>,
+    key_sink: Option<KeySink<Name>>,
+    clock: Arc<Clock>,
+}
+
+impl<Request, Name, Scheme> ThresholdSentinel<Request, Name, Scheme>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone,
+          Scheme: ThresholdScheme
+{
+    /// Creates a sentinel that knows no group keys yet.
+    pub fn new() -> ThresholdSentinel<Request, Name, Scheme> {
+        ThresholdSentinel {
+            shares: LruCache::with_capacity(MAX_REQUEST_COUNT),
+            held: BTreeMap::new(),
+            budget: ByteBudget::new(ClaimLimits::default()),
+            sequence: 0,
+            key_votes: LruCache::with_capacity(MAX_GROUP_COUNT),
+            group_keys: LruCache::with_capacity(MAX_GROUP_COUNT),
+            key_sink: None,
+            clock: clock::system_clock(),
+        }
+    }
+
+    /// Replaces the byte limits on the claims of held shares. Shares already held are kept
+    /// even if they exceed the new limits.
+    pub fn set_claim_limits(&mut self, claim_limits: ClaimLimits) {
+        self.budget.set_limits(claim_limits);
+    }
+
+    /// Sets the sink group keys are asked for through. `add_share` then no longer returns
+    /// `ThresholdResult::RequestKeys`.
+    pub fn set_key_sink(&mut self, sink: Box<SendGetKeys<Name> + Send>) {
+        self.key_sink = Some(KeySink::new(sink));
+    }
+
+    /// Replaces the clock outstanding key requests are timed with.
+    pub fn set_clock(&mut self, clock: Arc<Clock>) {
+        self.clock = clock;
+    }
+
+    /// This adds the signature share of the group member at `index` over `claim`.
+    ///
+    /// Possible results are:
+    /// * Some(ThresholdResult::Resolved(request, claim, signature)): indicating that a
+    ///   threshold of members signed the claim and their shares were combined.
+    /// * Some(ThresholdResult::RequestKeys(target)): indicating that the caller should
+    ///   request the public key set of the group surrounding the target.
+    /// * Some(ThresholdResult::Rejected(reason)): indicating that the share was not held
+    ///   because its claim would exceed the claim limits.
+    /// * None: indicating that no resolve was possible yet, or that the share was dropped
+    ///   for not verifying, or for the request holding `MAX_SHARES_PER_MEMBER` shares of the
+    ///   member or `MAX_SHARES_PER_REQUEST` already.
+    pub fn add_share(&mut self,
+                     request: Request,
+                     index: usize,
+                     share: Scheme::SignatureShare,
+                     claim: SerialisedClaim)
+                     -> Option<ThresholdResult<Request, Name, Scheme::Signature>> {
+        let source = request.get_source();
+        let known_keys = self.group_keys.get(&source).cloned();
+
+        if let Some(ref keys) = known_keys {
+            if !Scheme::verify_share(keys, index, &share, &claim) {
+                return None;
+            }
+        }
+
+        let (held, of_member) = self.shares.get(&request).map_or((0, 0), |shares| {
+            (shares.len(), shares.iter().filter(|&&(member, _, _)| member == index).count())
+        });
+        if held >= MAX_SHARES_PER_REQUEST || of_member >= MAX_SHARES_PER_MEMBER {
+            return None;
+        }
+
+        if let Err(rejection) = self.charge(&request, index, claim.len()) {
+            return Some(ThresholdResult::Rejected(rejection));
+        }
+
+        let saw_first_time = held == 0;
+        self.shares.entry(request.clone()).or_insert_with(Vec::new).push((index, share, claim));
+
+        let keys = match known_keys {
+            Some(keys) => keys,
+            None => {
+                if !saw_first_time {
+                    return None;
+                }
+                return match self.key_sink {
+                    Some(ref mut key_sink) => {
+                        let _ = key_sink.send(KeyRequest::Group(source), self.clock.now());
+                        None
+                    }
+                    None => Some(ThresholdResult::RequestKeys(source)),
+                };
+            }
+        };
+
+        self.resolve(request, &keys)
+    }
+
+    /// This adds the public key set of the group the request came from, as sent by
+    /// `sender`. The key set is used once `key_quorum` distinct senders have sent it, and
+    /// is then remembered for later requests from the same group, and the shares held for
+    /// requests of the group that don't verify against it are dropped. When the key set
+    /// leads to the resolution of the request, the request, the claim and the group signature
+    /// are returned.
+    pub fn add_group_key(&mut self,
+                         request: Request,
+                         sender: Name,
+                         keys: Scheme::PublicKeySet,
+                         key_quorum: usize)
+                         -> Option<ThresholdResult<Request, Name, Scheme::Signature>> {
+        let source = request.get_source();
+        let keys = match self.tally_key_set(source.clone(), sender, keys, key_quorum) {
+            Some(keys) => keys,
+            None => return None,
+        };
+
+        if let Some(ref mut key_sink) = self.key_sink {
+            key_sink.answered(&KeyRequest::Group(source.clone()));
+        }
+        self.group_keys.add(source.clone(), keys.clone());
+        self.drop_unverified(&source, &keys);
+
+        if !self.shares.check(&request) {
+            return None;
+        }
+
+        self.resolve(request, &keys)
+    }
+
+    /// Verifies a group signature over `claim` against the group's public key set.
+    pub fn verify(keys: &Scheme::PublicKeySet,
+                  signature: &Scheme::Signature,
+                  claim: &SerialisedClaim)
+                  -> bool {
+        Scheme::verify(keys, signature, claim)
+    }
+
+    // Counts `sender` for `keys` of the group `source`. Returns the key set once it reaches
+    // the quorum, forgetting the others sent for the group.
+    fn tally_key_set(&mut self,
+                     source: Name,
+                     sender: Name,
+                     keys: Scheme::PublicKeySet,
+                     key_quorum: usize)
+                     -> Option<Scheme::PublicKeySet> {
+        let confirmed = {
+            let votes = self.key_votes.entry(source.clone()).or_insert_with(BTreeMap::new);
+            let key_set_data = Scheme::key_set_data(&keys);
+            if !votes.contains_key(&key_set_data) && votes.len() >= MAX_KEY_SETS_PER_GROUP {
+                return None;
+            }
+
+            let entry = votes.entry(key_set_data).or_insert_with(|| (keys, BTreeSet::new()));
+            let _ = entry.1.insert(sender);
+            if entry.1.len() >= key_quorum { Some(entry.0.clone()) } else { None }
+        };
+
+        if confirmed.is_some() {
+            let _ = self.key_votes.remove(&source);
+        }
+        confirmed
+    }
+
+    // Keeps only the first verified share of each member for the requests of `source`,
+    // forgetting requests left with none.
+    fn drop_unverified(&mut self, source: &Name, keys: &Scheme::PublicKeySet) {
+        let requests = self.held.keys()
+                                .filter(|request| request.get_source() == *source)
+                                .cloned()
+                                .collect::<Vec<_>>();
+        for request in requests {
+            let verified = match self.shares.remove(&request) {
+                Some(shares) => Self::verify_shares(keys, &shares),
+                None => Vec::new(),
+            };
+            self.release(&request);
+            if verified.is_empty() {
+                continue;
+            }
+
+            let bytes = verified.iter().map(|&(_, _, ref claim)| claim.len()).sum();
+            self.sequence += 1;
+            self.budget.charge(bytes);
+            let _ = self.held.insert(request.clone(), Held {
+                bytes: bytes,
+                members: verified.iter().map(|&(member, _, _)| member).collect(),
+                last_seen: self.sequence,
+            });
+            self.shares.add(request, verified);
+        }
+    }
+
+    // Accounts for the share of `member` with a claim of `size` bytes held for `request`, or
+    // rejects it. While only the total is exceeded, the weakest other requests are evicted
+    // to make room.
+    fn charge(&mut self, request: &Request, member: usize, size: usize) -> Result<(), Rejection> {
+        let request_bytes = self.held_bytes(request);
+
+        loop {
+            match self.budget.check(size, request_bytes) {
+                Err(Rejection::TotalBytesExceeded) => {
+                    // Requests dropped by the cache still count against the budget until
+                    // reclaimed here.
+                    self.reclaim();
+                    if self.budget.check(size, request_bytes).is_ok() {
+                        break;
+                    }
+                    match self.weakest(request) {
+                        Some(victim) => {
+                            let _ = self.shares.remove(&victim);
+                            self.release(&victim);
+                        }
+                        None => return Err(Rejection::TotalBytesExceeded),
+                    }
+                }
+                result => {
+                    try!(result);
+                    break;
+                }
+            }
+        }
+
+        self.sequence += 1;
+        self.budget.charge(size);
+        let held = self.held.entry(request.clone()).or_insert_with(Held::default);
+        held.bytes += size;
+        let _ = held.members.insert(member);
+        held.last_seen = self.sequence;
+        Ok(())
+    }
+
+    // The request other than `spared` whose shares come from the fewest members.
+    fn weakest(&self, spared: &Request) -> Option<Request> {
+        eviction::weakest(self.held.iter()
+                                   .filter(|&(request, _)| request != spared)
+                                   .map(|(request, held)| {
+                                       let progress = Progress {
+                                           verified: 0,
+                                           claimants: held.members.len(),
+                                           quorum: 1,
+                                       };
+                                       (request, progress, held.last_seen)
+                                   })).cloned()
+    }
+
+    // Bytes held for `request`, forgetting them if the cache has since dropped the request.
+    fn held_bytes(&mut self, request: &Request) -> usize {
+        if self.shares.check(request) {
+            self.held.get(request).map_or(0, |held| held.bytes)
+        } else {
+            self.release(request);
+            0
+        }
+    }
+
+    fn release(&mut self, request: &Request) {
+        if let Some(held) = self.held.remove(request) {
+            self.budget.release(held.bytes);
+        }
+    }
+
+    fn reclaim(&mut self) {
+        let dropped = self.held.keys()
+                               .filter(|request| !self.shares.check(request))
+                               .cloned()
+                               .collect::<Vec<_>>();
+        for request in dropped {
+            self.release(&request);
+        }
+    }
+
+    fn resolve(&mut self,
+               request: Request,
+               keys: &Scheme::PublicKeySet)
+               -> Option<ThresholdResult<Request, Name, Scheme::Signature>> {
+        let threshold = Scheme::threshold(keys);
+
+        let verified = match self.shares.get(&request) {
+            Some(shares) => Self::verify_shares(keys, shares),
+            None => return None,
+        };
+
+        if verified.len() < threshold {
+            return None;
+        }
+
+        let mut frequency = Frequency::new();
+        for &(_, _, ref claim) in verified.iter() {
+            frequency.update(claim);
+        }
+
+        let claim = match frequency.at_least(threshold).into_iter().next() {
+            Some((claim, _)) => claim.clone(),
+            None => return None,
+        };
+
+        let shares = verified.into_iter()
+                             .filter(|&(_, _, ref signed)| *signed == claim)
+                             .map(|(index, share, _)| (index, share))
+                             .collect::<Vec<_>>();
+
+        Scheme::combine(keys, &shares).map(|signature| {
+            let _ = self.shares.remove(&request);
+            self.release(&request);
+            ThresholdResult::Resolved(request, claim, signature)
+        })
+    }
+
+    // Keeps the first verified share of each member.
+    fn verify_shares(keys: &Scheme::PublicKeySet,
+                     shares: &Vec<(usize, Scheme::SignatureShare, SerialisedClaim)>)
+                     -> Vec<(usize, Scheme::SignatureShare, SerialisedClaim)> {
+        let mut members = BTreeSet::new();
+        shares.iter().filter(|&&(index, ref share, ref claim)| {
+            !members.contains(&index) && Scheme::verify_share(keys, index, share, claim) &&
+            members.insert(index)
+        }).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::{random, reset_random};
+    use limits::{ClaimLimits, Rejection};
+    use pure_sentinel::Source;
+    use SerialisedClaim;
+
+    const GROUP_SIZE: usize = 8;
+    const THRESHOLD: usize = 5;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, RustcEncodable)]
+    struct TestRequest {
+        core: usize,
+        group: u64,
+    }
+
+    impl Source<u64> for TestRequest {
+        fn get_source(&self) -> u64 {
+            self.group
+        }
+    }
+
+    // A share is the member index with the signed bytes, and the group signature is the
+    // group key with the signed bytes. Good enough to exercise the accumulation.
+    struct MockScheme;
+
+    impl ThresholdScheme for MockScheme {
+        type PublicKeySet = u64;
+        type SignatureShare = (usize, SerialisedClaim);
+        type Signature = (u64, SerialisedClaim);
+        type KeySetData = u64;
+
+        fn threshold(_: &u64) -> usize {
+            THRESHOLD
+        }
+
+        fn key_set_data(keys: &u64) -> u64 {
+            *keys
+        }
+
+        fn verify_share(_: &u64,
+                        index: usize,
+                        share: &(usize, SerialisedClaim),
+                        message: &[u8])
+                        -> bool {
+            share.0 == index && &share.1[..] == message
+        }
+
+        fn combine(keys: &u64,
+                   shares: &[(usize, (usize, SerialisedClaim))])
+                   -> Option<(u64, SerialisedClaim)> {
+            if shares.len() < THRESHOLD {
+                return None;
+            }
+            Some((*keys, (shares[0].1).1.clone()))
+        }
+
+        fn verify(keys: &u64, signature: &(u64, SerialisedClaim), message: &[u8]) -> bool {
+            signature.0 == *keys && &signature.1[..] == message
+        }
+    }
+
+    fn share(index: usize, claim: &SerialisedClaim) -> (usize, SerialisedClaim) {
+        (index, claim.clone())
+    }
+
+    #[test]
+    fn shares_combine_once_group_key_arrives() {
+        reset_random();
+        let mut sentinel = ThresholdSentinel::<TestRequest, u64, MockScheme>::new();
+        let group_key = random::<u64>();
+        let request = TestRequest { core: random::<usize>(), group: random::<u64>() };
+        let claim = vec![random::<u8>(); 16];
+        let other_claim = vec![0u8; 4];
+
+        for index in 0..GROUP_SIZE {
+            let result = if index < 2 {
+                // Minority signing something else.
+                sentinel.add_share(request.clone(), index, share(index, &other_claim),
+                                   other_claim.clone())
+            } else {
+                sentinel.add_share(request.clone(), index, share(index, &claim), claim.clone())
+            };
+
+            match result {
+                Some(ThresholdResult::RequestKeys(group)) => {
+                    assert_eq!(index, 0);
+                    assert_eq!(group, request.group);
+                }
+                Some(ThresholdResult::Resolved(_, _, _)) => panic!("resolved without keys"),
+                Some(ThresholdResult::Rejected(_)) => panic!("share rejected"),
+                None => assert!(index > 0),
+            }
+        }
+
+        // A key set is only used once a quorum of senders sent it.
+        assert!(sentinel.add_group_key(request.clone(), 0, group_key, 2).is_none());
+        assert!(sentinel.add_group_key(request.clone(), 1, group_key + 1, 2).is_none());
+        assert!(sentinel.add_group_key(request.clone(), 0, group_key, 2).is_none());
+        match sentinel.add_group_key(request.clone(), 2, group_key, 2) {
+            Some(ThresholdResult::Resolved(resolved, resolved_claim, signature)) => {
+                assert_eq!(resolved, request);
+                assert_eq!(resolved_claim, claim);
+                assert!(ThresholdSentinel::<TestRequest, u64, MockScheme>::verify(&group_key,
+                                                                                  &signature,
+                                                                                  &claim));
+            }
+            _ => panic!("expected resolution"),
+        }
+    }
+
+    #[test]
+    fn known_group_key_resolves_at_threshold() {
+        reset_random();
+        let mut sentinel = ThresholdSentinel::<TestRequest, u64, MockScheme>::new();
+        let group = random::<u64>();
+        let first = TestRequest { core: 0, group: group };
+        let claim = vec![random::<u8>(); 16];
+
+        let _ = sentinel.add_share(first.clone(), 0, share(0, &claim), claim.clone());
+        assert!(sentinel.add_group_key(first, 0, random::<u64>(), 1).is_none());
+
+        let request = TestRequest { core: 1, group: group };
+        for index in 0..THRESHOLD {
+            // Forged and repeated shares are not counted.
+            assert!(sentinel.add_share(request.clone(), index, share(index + 1, &claim),
+                                       claim.clone()).is_none());
+            let result = sentinel.add_share(request.clone(), index, share(index, &claim),
+                                            claim.clone());
+            let _ = sentinel.add_share(request.clone(), index, share(index, &claim),
+                                       claim.clone());
+            match result {
+                Some(ThresholdResult::Resolved(resolved, _, _)) => {
+                    assert_eq!(index + 1, THRESHOLD);
+                    assert_eq!(resolved, request);
+                    return;
+                }
+                Some(ThresholdResult::RequestKeys(_)) => panic!("group key already known"),
+                Some(ThresholdResult::Rejected(_)) => panic!("share rejected"),
+                None => assert!(index + 1 < THRESHOLD),
+            }
+        }
+        panic!("expected resolution");
+    }
+
+    #[test]
+    fn held_shares_bounded() {
+        reset_random();
+        let mut sentinel = ThresholdSentinel::<TestRequest, u64, MockScheme>::new();
+        sentinel.set_claim_limits(ClaimLimits { max_claim_size: 16,
+                                                max_request_bytes: 16 * MAX_SHARES_PER_REQUEST,
+                                                max_total_bytes: 16 * MAX_SHARES_PER_REQUEST });
+        let request = TestRequest { core: 0, group: random::<u64>() };
+        let claim = vec![random::<u8>(); 16];
+
+        match sentinel.add_share(request.clone(), 0, share(0, &claim), vec![0u8; 17]) {
+            Some(ThresholdResult::Rejected(rejection)) =>
+                assert_eq!(rejection, Rejection::ClaimTooLarge),
+            _ => panic!("expected rejection"),
+        }
+
+        // Each member holds a bounded number of shares while the group key is unknown.
+        for _ in 0..2 * MAX_SHARES_PER_MEMBER {
+            let _ = sentinel.add_share(request.clone(), 0, share(1, &claim), claim.clone());
+        }
+        assert_eq!(sentinel.budget.total(), 16 * MAX_SHARES_PER_MEMBER);
+
+        // Junk of many members fills the request up to the cap.
+        for index in 1..MAX_SHARES_PER_REQUEST + 1 {
+            let _ = sentinel.add_share(request.clone(), index, share(index + 1, &claim),
+                                       claim.clone());
+        }
+        assert_eq!(sentinel.budget.total(), 16 * MAX_SHARES_PER_REQUEST);
+
+        // Another request makes room by evicting the junk, the only other request held.
+        let other = TestRequest { core: 1, group: request.group };
+        assert!(sentinel.add_share(other.clone(), 0, share(0, &claim), claim.clone()).is_some());
+        assert!(!sentinel.shares.check(&request));
+        assert_eq!(sentinel.budget.total(), 16);
+
+        // Once the group key is known, shares that don't verify are not held.
+        assert!(sentinel.add_group_key(other.clone(), 0, random::<u64>(), 1).is_none());
+        let third = TestRequest { core: 2, group: request.group };
+        assert!(sentinel.add_share(third.clone(), 0, share(1, &claim), claim.clone()).is_none());
+        assert!(!sentinel.shares.check(&third));
+    }
+
+    #[test]
+    fn unverified_shares_dropped_once_group_key_arrives() {
+        reset_random();
+        let mut sentinel = ThresholdSentinel::<TestRequest, u64, MockScheme>::new();
+        let request = TestRequest { core: 0, group: random::<u64>() };
+        let claim = vec![random::<u8>(); 16];
+
+        // Junk in the name of every member, with one genuine share among it.
+        let _ = sentinel.add_share(request.clone(), 0, share(0, &claim), claim.clone());
+        for index in 0..MAX_SHARES_PER_REQUEST {
+            let _ = sentinel.add_share(request.clone(), index, share(index + 1, &claim),
+                                       claim.clone());
+        }
+        assert_eq!(sentinel.shares.get(&request).map(|shares| shares.len()),
+                   Some(MAX_SHARES_PER_REQUEST));
+
+        // The key set drops the junk, and the members sending their shares again resolve.
+        assert!(sentinel.add_group_key(request.clone(), 0, random::<u64>(), 1).is_none());
+        assert_eq!(sentinel.shares.get(&request).map(|shares| shares.len()), Some(1));
+        assert_eq!(sentinel.budget.total(), 16);
+        for index in 1..THRESHOLD {
+            match sentinel.add_share(request.clone(), index, share(index, &claim),
+                                     claim.clone()) {
+                Some(ThresholdResult::Resolved(resolved, _, _)) => {
+                    assert_eq!(index + 1, THRESHOLD);
+                    assert_eq!(resolved, request);
+                    return;
+                }
+                None => assert!(index + 1 < THRESHOLD),
+                _ => panic!("unexpected result"),
+            }
+        }
+        panic!("expected resolution");
+    }
+}
@@ -0,0 +1,559 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Traces of the calls made into a sentinel, for post-mortem debugging.
+//!
+//! A sentinel given a `Recorder` with `set_recorder` passes it every call that changes its
+//! state, with the arguments and the outcome of the call. A `TraceWriter` records them as
+//! CBOR `TraceEntry`s, each stamped with the milliseconds since the first. `read_trace` reads
+//! them back, and the `replay` method of a fresh sentinel, configured as the recorded one
+//! was, feeds them into it and returns each call whose outcome differs from the recorded one.
+//!
+//! Keys and signatures are recorded with the claims, so traces hold nothing that isn't already
+//! sent over the network, but they do hold every claim in full.
+//!
+//! Without the types of the sentinel recorded, `read_untyped_trace` still reads the stamp of
+//! each entry with the names of its call and outcome, and checks that the stamps don't go
+//! back in time, as the `sentinel_verify` binary does.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use cbor::{Cbor, CborError, Decoder, Encoder};
+use rustc_serialize::{Decodable, Encodable};
+use time::SteadyTime;
+use clock::{self, Clock};
+use limits::Rejection;
+use pure_sentinel::{AddResult, Source};
+use SerialisedClaim;
+
+/// A call with its outcome, made `at_millis` milliseconds after the first call recorded.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TraceEntry<Call, Outcome> {
+    /// Milliseconds since the first call recorded.
+    pub at_millis: i64,
+    /// The call made.
+    pub call: Call,
+    /// What the call returned.
+    pub outcome: Outcome,
+}
+
+/// A replayed call whose outcome differs from the recorded one.
+#[derive(Clone, Debug)]
+pub struct Divergence<Outcome> {
+    /// Position of the call in the trace.
+    pub index: usize,
+    /// Milliseconds since the first call recorded.
+    pub at_millis: i64,
+    /// The outcome recorded.
+    pub recorded: Outcome,
+    /// The outcome on replay.
+    pub replayed: Outcome,
+}
+
+/// A call into a `PureSentinel`.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub enum PureCall<Request, Name, Signature, PublicKey> {
+    /// `add_claim` with the request, claimant, signature, claim and the claim and key quorums.
+    AddClaim(Request, Name, Signature, SerialisedClaim, usize, usize),
+    /// `add_keys` with the request, sender, keys and key quorum.
+    AddKeys(Request, Name, Vec<(Name, PublicKey)>, usize),
+    /// `add_request_keys` with the request and keys.
+    AddRequestKeys(Request, Vec<(Name, PublicKey)>),
+    /// `expire_pending` with the maximum age in milliseconds.
+    ExpirePending(i64),
+}
+
+/// Outcome of a call into a `PureSentinel`.
+#[derive(PartialEq, Eq, Clone, Debug, RustcEncodable, RustcDecodable)]
+pub enum PureOutcome<Request, Name> {
+    /// Nothing was returned.
+    Nothing,
+    /// `AddResult::RequestKeys`.
+    RequestKeys(Name),
+    /// `AddResult::Resolved`, or a resolution returned by `add_keys`.
+    Resolved(Request, SerialisedClaim),
+    /// `AddResult::AlreadyResolved`.
+    AlreadyResolved(Request, SerialisedClaim),
+    /// `AddResult::Rejected`.
+    Rejected(Rejection),
+    /// The requests dropped by `expire_pending`.
+    Expired(Vec<Request>),
+}
+
+impl<Request, Name> PureOutcome<Request, Name>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone
+{
+    /// Returns the outcome of `add_claim` returning `result`.
+    pub fn of_claim(result: &Option<AddResult<Request, Name>>) -> PureOutcome<Request, Name> {
+        match *result {
+            None => PureOutcome::Nothing,
+            Some(AddResult::RequestKeys(ref target)) => PureOutcome::RequestKeys(target.clone()),
+            Some(AddResult::Resolved(ref request, ref claim)) =>
+                PureOutcome::Resolved(request.clone(), claim.clone()),
+            Some(AddResult::AlreadyResolved(ref request, ref claim)) =>
+                PureOutcome::AlreadyResolved(request.clone(), claim.clone()),
+            Some(AddResult::Rejected(rejection)) => PureOutcome::Rejected(rejection),
+        }
+    }
+
+    /// Returns the outcome of `add_keys` returning `result`.
+    pub fn of_keys(result: &Option<(Request, SerialisedClaim)>) -> PureOutcome<Request, Name> {
+        match *result {
+            Some((ref request, ref claim)) => PureOutcome::Resolved(request.clone(), claim.clone()),
+            None => PureOutcome::Nothing,
+        }
+    }
+}
+
+/// A call into a `KeySentinel`.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub enum KeyCall<Request, Name, Signature, GroupClaim> {
+    /// `add_identities` with the request, sender, serialised claim, signature, group claim
+    /// and quorum.
+    AddIdentities(Request, Name, SerialisedClaim, Signature, GroupClaim, usize),
+}
+
+/// Outcome of a call into a `KeySentinel`.
+#[derive(PartialEq, Eq, Clone, Debug, RustcEncodable, RustcDecodable)]
+pub enum KeyOutcome<Request, IdType> {
+    /// Nothing was confirmed yet.
+    Nothing,
+    /// The identities of the group of the request were confirmed.
+    Confirmed(Request, Vec<IdType>),
+    /// The group claim was rejected.
+    Rejected(Rejection),
+}
+
+impl<Request: Clone, IdType: Clone> KeyOutcome<Request, IdType> {
+    /// Returns the outcome of `add_identities` returning `result`.
+    pub fn of(result: &Result<Option<(Request, Vec<IdType>)>, Rejection>)
+              -> KeyOutcome<Request, IdType> {
+        match *result {
+            Ok(Some((ref request, ref identities))) =>
+                KeyOutcome::Confirmed(request.clone(), identities.clone()),
+            Ok(None) => KeyOutcome::Nothing,
+            Err(rejection) => KeyOutcome::Rejected(rejection),
+        }
+    }
+}
+
+/// A call into a refresh sentinel, which accumulates values under a key.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub enum RefreshCall<Key, Value> {
+    /// `add` with the key and value.
+    Add(Key, Value),
+    /// `delete` with the key.
+    Delete(Key),
+    /// `set_quorum` with the quorum.
+    SetQuorum(usize),
+}
+
+/// Outcome of a call into a refresh sentinel.
+#[derive(PartialEq, Eq, Clone, Debug, RustcEncodable, RustcDecodable)]
+pub enum RefreshOutcome<Key, Value> {
+    /// The quorum of the key has not been reached, or the call returns nothing.
+    Nothing,
+    /// The quorum of the key was reached with the values given.
+    Resolved(Key, Vec<Value>),
+}
+
+impl<Key: Clone, Value: Clone> RefreshOutcome<Key, Value> {
+    /// Returns the outcome of `add` returning `result`.
+    pub fn of(result: &Option<(Key, Vec<Value>)>) -> RefreshOutcome<Key, Value> {
+        match *result {
+            Some((ref key, ref values)) => RefreshOutcome::Resolved(key.clone(), values.clone()),
+            None => RefreshOutcome::Nothing,
+        }
+    }
+}
+
+/// Receives every call made into a sentinel, with its outcome.
+pub trait Recorder<Call, Outcome> {
+    /// Records `call`, which returned `outcome`.
+    fn record(&mut self, call: &Call, outcome: &Outcome);
+}
+
+// Borrowed form of `TraceEntry`, so that calls are written without being cloned.
+#[derive(RustcEncodable)]
+struct EntryRef<'a, Call: 'a, Outcome: 'a> {
+    at_millis: i64,
+    call: &'a Call,
+    outcome: &'a Outcome,
+}
+
+/// Writes the calls it is given to a writer, as CBOR `TraceEntry`s.
+pub struct TraceWriter<W: Write> {
+    encoder: Encoder<W>,
+    clock: Arc<Clock>,
+    start: Option<SteadyTime>,
+    error: Option<CborError>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Creates a writer to `writer`, timing calls with the system clock.
+    pub fn new(writer: W) -> TraceWriter<W> {
+        TraceWriter::with_clock(writer, clock::system_clock())
+    }
+
+    /// Creates a writer to `writer`, timing calls with `clock`. It should be the clock of the
+    /// sentinel recorded, so that replay sees the same passage of time.
+    pub fn with_clock(writer: W, clock: Arc<Clock>) -> TraceWriter<W> {
+        TraceWriter {
+            encoder: Encoder::from_writer(writer),
+            clock: clock,
+            start: None,
+            error: None,
+        }
+    }
+
+    /// Returns the error writing failed with, if it did. Calls after it are not recorded.
+    pub fn error(&self) -> Option<&CborError> {
+        self.error.as_ref()
+    }
+}
+
+impl<W, Call, Outcome> Recorder<Call, Outcome> for TraceWriter<W>
+    where W: Write,
+          Call: Encodable,
+          Outcome: Encodable
+{
+    fn record(&mut self, call: &Call, outcome: &Outcome) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let now = self.clock.now();
+        let start = *self.start.get_or_insert(now);
+        let entry = EntryRef {
+            at_millis: (now - start).num_milliseconds(),
+            call: call,
+            outcome: outcome,
+        };
+
+        // Each entry is flushed, so that the trace is complete up to a crash.
+        if let Err(error) = self.encoder.encode(&[entry]).and_then(|()| self.encoder.flush()) {
+            self.error = Some(error);
+        }
+    }
+}
+
+/// Reads the entries of a trace written by a `TraceWriter`.
+pub fn read_trace<R, Call, Outcome>(reader: R)
+                                    -> Result<Vec<TraceEntry<Call, Outcome>>, CborError>
+    where R: Read,
+          Call: Decodable,
+          Outcome: Decodable
+{
+    Decoder::from_reader(reader).decode().collect()
+}
+
+/// An entry of a trace read without the types of the sentinel recorded.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct UntypedEntry {
+    /// Milliseconds since the first call recorded.
+    pub at_millis: i64,
+    /// Name of the call, such as `AddClaim`.
+    pub call: String,
+    /// Name of the outcome, such as `Resolved`.
+    pub outcome: String,
+}
+
+/// Reason a trace read without its types is unsound.
+#[derive(Debug)]
+pub enum TraceError {
+    /// The trace is not a CBOR sequence.
+    Decode(CborError),
+    /// The entry at the index is not a `TraceEntry`.
+    Malformed(usize),
+    /// The entry at the index is stamped before the entry preceding it.
+    OutOfOrder(usize),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TraceError::Decode(ref error) => write!(formatter, "cannot decode: {}", error),
+            TraceError::Malformed(index) => write!(formatter, "#{} is not a trace entry", index),
+            TraceError::OutOfOrder(index) =>
+                write!(formatter, "#{} is stamped before the entry preceding it", index),
+        }
+    }
+}
+
+/// Reads the entries of a trace written by a `TraceWriter` without the types of the sentinel
+/// recorded, checking that their stamps don't decrease.
+pub fn read_untyped_trace<R: Read>(reader: R) -> Result<Vec<UntypedEntry>, TraceError> {
+    let mut entries: Vec<UntypedEntry> = Vec::new();
+    for (index, item) in Decoder::from_reader(reader).items().enumerate() {
+        let item = try!(item.map_err(TraceError::Decode));
+        let entry = try!(untyped_entry(item).ok_or(TraceError::Malformed(index)));
+        if entries.last().map_or(false, |last| entry.at_millis < last.at_millis) {
+            return Err(TraceError::OutOfOrder(index));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn untyped_entry(item: Cbor) -> Option<UntypedEntry> {
+    let mut fields = match item {
+        Cbor::Map(fields) => fields,
+        _ => return None,
+    };
+    let at_millis = match fields.remove("at_millis") {
+        Some(Cbor::Unsigned(millis)) => millis.into_u64() as i64,
+        Some(Cbor::Signed(millis)) => millis.into_i64(),
+        _ => return None,
+    };
+    let call = fields.remove("call").and_then(variant_name);
+    let outcome = fields.remove("outcome").and_then(variant_name);
+    match (call, outcome) {
+        (Some(call), Some(outcome)) =>
+            Some(UntypedEntry { at_millis: at_millis, call: call, outcome: outcome }),
+        _ => None,
+    }
+}
+
+// Enum variants are encoded as their name, or as a map of the name and the fields.
+fn variant_name(item: Cbor) -> Option<String> {
+    match item {
+        Cbor::Unicode(name) => Some(name),
+        Cbor::Map(mut fields) => match fields.remove("variant") {
+            Some(Cbor::Unicode(name)) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Passes each call of `trace` to `replay`, with the milliseconds since the first call, and
+/// returns the calls whose outcome differs from the recorded one. The `replay` methods of the
+/// sentinels are built on it.
+pub fn diverging<Call, Outcome, F>(trace: Vec<TraceEntry<Call, Outcome>>,
+                                   mut replay: F)
+                                   -> Vec<Divergence<Outcome>>
+    where Outcome: PartialEq,
+          F: FnMut(i64, Call) -> Outcome
+{
+    trace.into_iter().enumerate().filter_map(|(index, entry)| {
+        let replayed = replay(entry.at_millis, entry.call);
+        if replayed == entry.outcome {
+            None
+        } else {
+            Some(Divergence {
+                index: index,
+                at_millis: entry.at_millis,
+                recorded: entry.outcome,
+                replayed: replayed,
+            })
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use time::Duration;
+    use clock::ManualClock;
+    use key_sentinel::{KeySentinel, KeyTraceCall};
+    use limits::{ClaimLimits, Rejection};
+    use pure_sentinel::{PureSentinel, PureTraceCall};
+    use refresh_sentinel::RefreshSentinel;
+    use signature_scheme::Ed25519;
+    use testing::{random_claim, seeded_rng, SignedGroup, TestGroupClaim, TestId, TestRequest};
+
+    const QUORUM: usize = 4;
+
+    type Request = TestRequest<u64>;
+    type PureTrace = Vec<TraceEntry<PureTraceCall<Request, u64, Ed25519>,
+                                    PureOutcome<Request, u64>>>;
+    type KeyTrace = Vec<TraceEntry<KeyTraceCall<Request, u64, TestGroupClaim<u64>, Ed25519>,
+                                   KeyOutcome<Request, TestId<u64>>>>;
+    type RefreshTrace = Vec<TraceEntry<RefreshCall<u64, u32>, RefreshOutcome<u64, u32>>>;
+
+    // A writer whose bytes stay readable once it is handed to a sentinel.
+    #[derive(Clone)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn pure_trace_replayed() {
+        let mut rng = seeded_rng(1);
+        let group = SignedGroup::<u64>::random(QUORUM + 1, &mut rng);
+        let claim = random_claim(16, &mut rng);
+        let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+        let clock = ManualClock::new();
+        let mut sentinel: PureSentinel<Request, u64> = PureSentinel::new();
+        sentinel.set_clock(Arc::new(clock.clone()));
+        sentinel.set_recorder(Box::new(TraceWriter::with_clock(buffer.clone(),
+                                                               Arc::new(clock.clone()))));
+
+        let requests = (0..2).map(|id| TestRequest { id: id, source: 1000 }).collect::<Vec<_>>();
+        for request in requests.iter() {
+            for signed in group.sign_claims(request, &claim) {
+                let _ = sentinel.add_claim(request.clone(), signed.claimant, signed.signature,
+                                           signed.claim, QUORUM, 1);
+            }
+            clock.advance(Duration::seconds(20));
+        }
+        let _ = sentinel.add_keys(requests[0].clone(), 2000, group.keys(), 1);
+        clock.advance(Duration::seconds(15));
+        let _ = sentinel.expire_pending(Duration::seconds(30));
+
+        let trace: PureTrace = read_trace(&buffer.bytes()[..]).unwrap();
+        assert_eq!(trace.len(), 2 * (QUORUM + 1) + 2);
+        assert_eq!(trace.last().map(|entry| entry.at_millis), Some(55000));
+        match trace.last().map(|entry| &entry.outcome) {
+            Some(&PureOutcome::Expired(ref expired)) =>
+                assert_eq!(*expired, vec![requests[1].clone()]),
+            _ => panic!("expected the second request to expire"),
+        }
+
+        // A fresh sentinel configured alike reaches the same outcomes, timing included.
+        assert!(PureSentinel::<Request, u64>::new().replay(trace.clone()).is_empty());
+
+        // One configured differently diverges where the configuration matters.
+        let mut restricted: PureSentinel<Request, u64> = PureSentinel::new();
+        restricted.set_claim_limits(ClaimLimits { max_claim_size: 8, ..ClaimLimits::default() });
+        let divergences = restricted.replay(trace);
+        assert_eq!(divergences[0].index, 0);
+        assert_eq!(divergences[0].replayed, PureOutcome::Rejected(Rejection::ClaimTooLarge));
+    }
+
+    #[test]
+    fn key_trace_replayed() {
+        let mut rng = seeded_rng(2);
+        let group = SignedGroup::<u64>::random(QUORUM + 1, &mut rng);
+        let request = TestRequest { id: 1, source: 1000 };
+        let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+        let mut sentinel: KeySentinel<Request, u64, TestId<u64>, TestGroupClaim<u64>> =
+            KeySentinel::new();
+        sentinel.set_recorder(Box::new(TraceWriter::new(buffer.clone())));
+
+        for response in group.key_responses() {
+            let _ = sentinel.add_identities(request.clone(), response.sender, response.serialised,
+                                            response.signature, response.group_claim, QUORUM);
+        }
+
+        let trace: KeyTrace = read_trace(&buffer.bytes()[..]).unwrap();
+        assert_eq!(trace.len(), group.len());
+        match trace.last().map(|entry| &entry.outcome) {
+            Some(&KeyOutcome::Confirmed(ref confirmed, _)) => assert_eq!(*confirmed, request),
+            _ => panic!("expected the group to be confirmed"),
+        }
+        assert!(KeySentinel::<Request, u64, TestId<u64>, TestGroupClaim<u64>>::new()
+                    .replay(trace).is_empty());
+    }
+
+    #[test]
+    fn refresh_trace_replayed() {
+        let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+        let mut sentinel: RefreshSentinel<u64, u32> = RefreshSentinel::new(QUORUM);
+        sentinel.set_recorder(Box::new(TraceWriter::new(buffer.clone())));
+
+        for value in 0..QUORUM as u32 - 1 {
+            assert!(sentinel.add(1, value).is_none());
+        }
+        sentinel.delete(&1);
+        assert!(sentinel.add(1, 0).is_none());
+        sentinel.set_quorum(1);
+        assert!(sentinel.add(2, 0).is_some());
+
+        let trace: RefreshTrace = read_trace(&buffer.bytes()[..]).unwrap();
+        assert_eq!(trace.len(), QUORUM + 3);
+        match trace.last().map(|entry| &entry.call) {
+            Some(&RefreshCall::Add(2, 0)) => (),
+            _ => panic!("expected the last add"),
+        }
+        assert_eq!(trace.last().map(|entry| &entry.outcome),
+                   Some(&RefreshOutcome::Resolved(2, vec![0])));
+
+        // Deletions and quorum changes are replayed with the values.
+        assert!(RefreshSentinel::<u64, u32>::new(QUORUM).replay(trace.clone()).is_empty());
+
+        // A sentinel started with another quorum diverges.
+        let divergences = RefreshSentinel::<u64, u32>::new(QUORUM - 1).replay(trace);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].index, QUORUM - 2);
+    }
+
+    #[test]
+    fn untyped_trace_read() {
+        let mut rng = seeded_rng(3);
+        let group = SignedGroup::<u64>::random(QUORUM, &mut rng);
+        let claim = random_claim(16, &mut rng);
+        let request = TestRequest { id: 1, source: 1000 };
+        let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+        let clock = ManualClock::new();
+        let mut sentinel: PureSentinel<Request, u64> = PureSentinel::new();
+        sentinel.set_clock(Arc::new(clock.clone()));
+        sentinel.set_recorder(Box::new(TraceWriter::with_clock(buffer.clone(),
+                                                               Arc::new(clock.clone()))));
+
+        for signed in group.sign_claims(&request, &claim) {
+            let _ = sentinel.add_claim(request.clone(), signed.claimant, signed.signature,
+                                       signed.claim, QUORUM, 1);
+            clock.advance(Duration::seconds(1));
+        }
+        let _ = sentinel.add_keys(request, 2000, group.keys(), 1);
+
+        let entries = read_untyped_trace(&buffer.bytes()[..]).unwrap();
+        assert_eq!(entries.len(), QUORUM + 1);
+        assert_eq!(entries[0], UntypedEntry { at_millis: 0,
+                                              call: "AddClaim".to_string(),
+                                              outcome: "RequestKeys".to_string() });
+        assert_eq!(entries[QUORUM], UntypedEntry { at_millis: QUORUM as i64 * 1000,
+                                                   call: "AddKeys".to_string(),
+                                                   outcome: "Resolved".to_string() });
+
+        // Entries stamped out of order are reported.
+        let mut reordered = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+        {
+            let mut encoder = Encoder::from_writer(&mut reordered);
+            let calls = vec![RefreshCall::Add(1u64, 1u32), RefreshCall::Add(1, 2)];
+            for (at_millis, call) in vec![5i64, 4].into_iter().zip(calls) {
+                let entry = TraceEntry { at_millis: at_millis,
+                                         call: call,
+                                         outcome: RefreshOutcome::Nothing::<u64, u32> };
+                encoder.encode(&[entry]).unwrap();
+            }
+        }
+        match read_untyped_trace(&reordered.bytes()[..]) {
+            Err(TraceError::OutOfOrder(1)) => (),
+            _ => panic!("expected the second entry to be out of order"),
+        }
+    }
+}
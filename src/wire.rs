@@ -0,0 +1,519 @@
+// Copyright 2015 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! CBOR wire format of the messages of the sentinel protocol.
+//!
+//! The messages are those of `docs/sentinel.md`: signed claims, `GetKey` and `GetGroupKey`
+//! requests, the responses to them, which preserve the message id and the signature of the
+//! request, and group identity claims. The request a message belongs to serves as its
+//! message id. Each message is passed to the sentinel it is meant for with its `add_to`.
+//!
+//! A message is encoded into an envelope holding `WIRE_VERSION` and the message encoded on
+//! its own, so that the version of a message can be checked before its body is decoded.
+//! Messages of other versions are refused with `WireError::UnsupportedVersion`. Claims and
+//! envelope bodies are CBOR byte strings.
+//!
+//! `Message::decode` is given the `ClaimLimits` of the sentinel the message is for. Bytes
+//! longer than `MAX_MESSAGE_OVERHEAD` past `max_claim_size` are refused before anything is
+//! decoded, so a peer can't make a node allocate more than a claim's worth, and decoded claims
+//! longer than `max_claim_size` are refused too. So are bytes left after the envelope.
+//!
+//! The signature of a key request is over `KeyRequestMessage::signed_bytes`, the request and
+//! whose keys are asked for encoded together, so that it can't be moved to another request.
+
+use std::fmt;
+use std::fmt::Debug;
+use cbor::{CborBytes, CborError, Decoder, Encoder};
+use rustc_serialize::{Decodable, Encodable};
+use sodiumoxide::crypto::sign;
+use key_sentinel::{GroupClaimTrait, IdTrait, KeySentinel};
+use limits::{ClaimLimits, Rejection};
+use pure_sentinel::{AddResult, PureSentinel, Source};
+use send_get_keys::KeyRequest;
+use signature_scheme::SignatureScheme;
+use SerialisedClaim;
+
+/// Version of the wire format messages are encoded with.
+pub const WIRE_VERSION: u32 = 1;
+
+/// Bytes a message may take beyond its claim, for the envelope, request, names, keys and
+/// signatures.
+pub const MAX_MESSAGE_OVERHEAD: usize = 64 * 1024;
+
+/// Reason bytes could not be decoded into a message.
+#[derive(Debug)]
+pub enum WireError {
+    /// The bytes are not a CBOR encoded envelope or message.
+    Cbor(CborError),
+    /// The envelope holds no message.
+    Empty,
+    /// The message is of a version of the wire format this one can't decode.
+    UnsupportedVersion(u32),
+    /// Bytes are left after the envelope.
+    TrailingBytes,
+    /// The bytes, of the given length, are too many to hold a claim within the limits.
+    MessageTooLarge(usize),
+    /// The claim, of the given length, is larger than `max_claim_size`.
+    ClaimTooLarge(usize),
+}
+
+impl From<CborError> for WireError {
+    fn from(error: CborError) -> WireError {
+        WireError::Cbor(error)
+    }
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WireError::Cbor(ref error) => write!(formatter, "invalid CBOR: {:?}", error),
+            WireError::Empty => write!(formatter, "no message"),
+            WireError::UnsupportedVersion(version) =>
+                write!(formatter, "unsupported wire version {}", version),
+            WireError::TrailingBytes => write!(formatter, "bytes after the message"),
+            WireError::MessageTooLarge(length) =>
+                write!(formatter, "message of {} bytes too large", length),
+            WireError::ClaimTooLarge(length) =>
+                write!(formatter, "claim of {} bytes too large", length),
+        }
+    }
+}
+
+/// A claim signed by one claimant of the group the request came from.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct ClaimMessage<Request, Name, Signature> {
+    /// The request claimed, also the message id.
+    pub request: Request,
+    /// Name of the claimant.
+    pub claimant: Name,
+    /// Signature of the claimant over `claim` bound to `request`, as `bound_claim` encodes them.
+    pub signature: Signature,
+    /// The serialised claim.
+    pub claim: CborBytes,
+}
+
+/// A `GetKey` or `GetGroupKey` request for the keys needed to verify the claims of a request.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct KeyRequestMessage<Request, Name, Signature> {
+    /// The request whose claims the keys verify, preserved from its claims.
+    pub request: Request,
+    /// The client or group whose keys are asked for.
+    pub keys_of: KeyRequest<Name>,
+    /// Signature of the node asking, over `signed_bytes`.
+    pub signature: Signature,
+}
+
+/// A `GetKeyResponse` or `GetGroupKeyResponse`, listing the keys one sender vouches for.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct KeyResponseMessage<Request, Name, Signature, PublicKey> {
+    /// The request, preserved from the key request.
+    pub request: Request,
+    /// Name of the sender.
+    pub sender: Name,
+    /// Names with their public keys.
+    pub keys: Vec<(Name, PublicKey)>,
+    /// Signature of the key request, preserved from it.
+    pub signature: Signature,
+}
+
+/// A group claim listing the identities of a group, signed by one of its members.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub struct GroupIdentitiesMessage<Request, Name, Signature, GroupClaim> {
+    /// The request, also the message id.
+    pub request: Request,
+    /// Name of the sender.
+    pub sender: Name,
+    /// The group claim, as the sender signed it.
+    pub serialised: CborBytes,
+    /// Signature of the sender over `serialised`.
+    pub signature: Signature,
+    /// The group claim.
+    pub claim: GroupClaim,
+}
+
+/// A message of the sentinel protocol.
+#[derive(Clone, RustcEncodable, RustcDecodable)]
+pub enum Message<Request, Name, Signature, PublicKey, GroupClaim> {
+    /// A signed claim.
+    Claim(ClaimMessage<Request, Name, Signature>),
+    /// A `GetKey` or `GetGroupKey` request.
+    GetKey(KeyRequestMessage<Request, Name, Signature>),
+    /// A response to a key request.
+    GetKeyResponse(KeyResponseMessage<Request, Name, Signature, PublicKey>),
+    /// A signed group claim.
+    GroupIdentities(GroupIdentitiesMessage<Request, Name, Signature, GroupClaim>),
+}
+
+// What a message is sent as: the version with the message encoded on its own.
+#[derive(RustcEncodable, RustcDecodable)]
+struct Envelope {
+    version: u32,
+    body: CborBytes,
+}
+
+impl<Request, Name, Signature, PublicKey, GroupClaim>
+    Message<Request, Name, Signature, PublicKey, GroupClaim>
+    where Request: Encodable + Decodable,
+          Name: Encodable + Decodable,
+          Signature: Encodable + Decodable,
+          PublicKey: Encodable + Decodable,
+          GroupClaim: Encodable + Decodable
+{
+    /// Encodes the message, in an envelope of the current `WIRE_VERSION`.
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        let body = try!(encode_one(self));
+        encode_one(&Envelope { version: WIRE_VERSION, body: CborBytes(body) })
+    }
+
+    /// Decodes a message encoded with `encode`, refusing claims beyond `limits`.
+    pub fn decode(bytes: &[u8], limits: &ClaimLimits)
+                  -> Result<Message<Request, Name, Signature, PublicKey, GroupClaim>, WireError> {
+        if bytes.len() > limits.max_claim_size.saturating_add(MAX_MESSAGE_OVERHEAD) {
+            return Err(WireError::MessageTooLarge(bytes.len()));
+        }
+        let envelope: Envelope = try!(decode_one(bytes));
+        if envelope.version != WIRE_VERSION {
+            return Err(WireError::UnsupportedVersion(envelope.version));
+        }
+        let message = try!(decode_one(&envelope.body));
+        let claim_length = match message {
+            Message::Claim(ref message) => message.claim.len(),
+            Message::GroupIdentities(ref message) => message.serialised.len(),
+            _ => 0,
+        };
+        if claim_length > limits.max_claim_size {
+            return Err(WireError::ClaimTooLarge(claim_length));
+        }
+        Ok(message)
+    }
+}
+
+fn encode_one<T: Encodable>(item: &T) -> Result<Vec<u8>, WireError> {
+    let mut encoder = Encoder::from_memory();
+    try!(encoder.encode(&[item]));
+    Ok(encoder.as_bytes().to_vec())
+}
+
+// Decodes the only item in `bytes`.
+fn decode_one<T: Decodable>(bytes: &[u8]) -> Result<T, WireError> {
+    let mut decoder = Decoder::from_bytes(bytes);
+    let mut items = decoder.decode();
+    let item = match items.next() {
+        Some(Ok(item)) => item,
+        Some(Err(error)) => return Err(WireError::Cbor(error)),
+        None => return Err(WireError::Empty),
+    };
+    match items.next() {
+        Some(_) => Err(WireError::TrailingBytes),
+        None => Ok(item),
+    }
+}
+
+impl<Request, Name, Signature> ClaimMessage<Request, Name, Signature>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone
+{
+    /// Adds the claim to `sentinel`, as `PureSentinel::add_claim` does.
+    pub fn add_to<Scheme>(self,
+                          sentinel: &mut PureSentinel<Request, Name, Scheme>,
+                          claim_quorum: usize,
+                          key_quorum: usize)
+                          -> Option<AddResult<Request, Name>>
+        where Scheme: SignatureScheme<Signature = Signature>
+    {
+        sentinel.add_claim(self.request, self.claimant, self.signature, self.claim.0,
+                           claim_quorum, key_quorum)
+    }
+}
+
+impl<Request, Name> KeyRequestMessage<Request, Name, sign::Signature>
+    where Request: Encodable,
+          Name: Encodable
+{
+    /// Returns the request for the keys of `keys_of` needed by `request`, signed with
+    /// `secret_key`.
+    pub fn sign(request: Request,
+                keys_of: KeyRequest<Name>,
+                secret_key: &sign::SecretKey)
+                -> Result<KeyRequestMessage<Request, Name, sign::Signature>, WireError> {
+        let signed_bytes = try!(Self::signed_bytes(&request, &keys_of));
+        Ok(KeyRequestMessage {
+            request: request,
+            keys_of: keys_of,
+            signature: sign::sign_detached(&signed_bytes, secret_key),
+        })
+    }
+}
+
+impl<Request, Name, Signature> KeyRequestMessage<Request, Name, Signature>
+    where Request: Encodable,
+          Name: Encodable
+{
+    /// Returns the bytes the signature of a key request is over: `request` and `keys_of`
+    /// encoded together.
+    pub fn signed_bytes(request: &Request, keys_of: &KeyRequest<Name>)
+                        -> Result<Vec<u8>, WireError> {
+        encode_one(&(request, keys_of))
+    }
+
+    /// Returns true if the signature verifies against `public_key`, the key of the node asking.
+    pub fn verify<Scheme>(&self, public_key: &Scheme::PublicKey) -> bool
+        where Scheme: SignatureScheme<Signature = Signature>
+    {
+        match Self::signed_bytes(&self.request, &self.keys_of) {
+            Ok(signed_bytes) => Scheme::verify(&self.signature, public_key, &signed_bytes),
+            Err(_) => false,
+        }
+    }
+}
+
+impl<Request, Name, Signature> KeyRequestMessage<Request, Name, Signature>
+    where Signature: Clone
+{
+    /// Returns the response of `sender` listing `keys`, preserving the request and the
+    /// signature of this message.
+    pub fn respond<PublicKey>(&self,
+                              sender: Name,
+                              keys: Vec<(Name, PublicKey)>)
+                              -> KeyResponseMessage<Request, Name, Signature, PublicKey>
+        where Request: Clone
+    {
+        KeyResponseMessage {
+            request: self.request.clone(),
+            sender: sender,
+            keys: keys,
+            signature: self.signature.clone(),
+        }
+    }
+}
+
+impl<Request, Name, Signature, PublicKey> KeyResponseMessage<Request, Name, Signature, PublicKey>
+    where Request: Eq + PartialOrd + Ord + Clone + Source<Name>,
+          Name: Eq + PartialOrd + Ord + Clone
+{
+    /// Adds the keys to `sentinel`, as `PureSentinel::add_keys` does.
+    pub fn add_to<Scheme>(self,
+                          sentinel: &mut PureSentinel<Request, Name, Scheme>,
+                          key_quorum: usize)
+                          -> Option<(Request, SerialisedClaim)>
+        where Scheme: SignatureScheme<PublicKey = PublicKey>
+    {
+        sentinel.add_keys(self.request, self.sender, self.keys, key_quorum)
+    }
+}
+
+impl<Request, Name, Signature, GroupClaim>
+    GroupIdentitiesMessage<Request, Name, Signature, GroupClaim>
+    where Request: Eq + PartialOrd + Ord + Clone,
+          Name: Eq + PartialOrd + Ord + Clone + Debug
+{
+    /// Adds the group claim to `sentinel`, as `KeySentinel::add_identities` does.
+    pub fn add_to<IdType, Scheme>(self,
+                                  sentinel: &mut KeySentinel<Request, Name, IdType, GroupClaim,
+                                                             Scheme>,
+                                  quorum_size: usize)
+                                  -> Result<Option<(Request, Vec<IdType>)>, Rejection>
+        where IdType: Eq + PartialOrd + Ord + Clone + IdTrait<Name, Scheme>,
+              GroupClaim: Eq + PartialOrd + Ord + Clone + GroupClaimTrait<IdType>,
+              Scheme: SignatureScheme<Signature = Signature>
+    {
+        sentinel.add_identities(self.request, self.sender, self.serialised.0, self.signature,
+                                self.claim, quorum_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cbor::CborBytes;
+    use rand::Rng;
+    use key_sentinel::KeySentinel;
+    use limits::ClaimLimits;
+    use pure_sentinel::{AddResult, PureSentinel};
+    use send_get_keys::KeyRequest;
+    use signature_scheme::Ed25519;
+    use testing::{random_claim, seeded_keypair, seeded_rng, SignedGroup, TestGroupClaim, TestId,
+                  TestRequest};
+
+    type TestMessage = Message<TestRequest<u64>, u64, sign::Signature, sign::PublicKey,
+                               TestGroupClaim<u64>>;
+
+    fn round_trip(message: TestMessage) -> TestMessage {
+        TestMessage::decode(&message.encode().unwrap(), &ClaimLimits::default()).unwrap()
+    }
+
+    fn claim_message(claim: SerialisedClaim) -> TestMessage {
+        let key_pair = seeded_keypair(52);
+        TestMessage::Claim(ClaimMessage {
+            request: TestRequest { id: 1, source: 2 },
+            claimant: 3,
+            signature: sign::sign_detached(&claim, &key_pair.1),
+            claim: CborBytes(claim),
+        })
+    }
+
+    #[test]
+    fn messages_round_trip_into_sentinels() {
+        let mut rng = seeded_rng(50);
+        let group = SignedGroup::random(4, &mut rng);
+        let request = TestRequest { id: rng.gen(), source: 1000 };
+        let claim = random_claim(16, &mut rng);
+        let mut sentinel: PureSentinel<TestRequest<u64>, u64> = PureSentinel::new();
+
+        let mut asked = None;
+        for signed in group.sign_claims(&request, &claim) {
+            let message = TestMessage::Claim(ClaimMessage {
+                request: request.clone(),
+                claimant: signed.claimant,
+                signature: signed.signature,
+                claim: CborBytes(signed.claim),
+            });
+            if let TestMessage::Claim(message) = round_trip(message) {
+                if let Some(AddResult::RequestKeys(source)) = message.add_to(&mut sentinel, 3, 2) {
+                    asked = Some(source);
+                }
+            }
+        }
+        assert_eq!(asked, Some(1000));
+
+        // Responses carry the id and signature of the key request they answer.
+        let key_pair = seeded_keypair(50);
+        let get_key = KeyRequestMessage::sign(request.clone(), KeyRequest::Group(1000),
+                                              &key_pair.1)
+                          .unwrap();
+        let get_key = match round_trip(TestMessage::GetKey(get_key)) {
+            TestMessage::GetKey(get_key) => get_key,
+            _ => panic!("expected a key request"),
+        };
+        assert_eq!(get_key.keys_of, KeyRequest::Group(1000));
+        assert!(get_key.verify::<Ed25519>(&key_pair.0));
+
+        let mut resolved = None;
+        for sender in group.names() {
+            let response = get_key.respond(sender, group.keys());
+            let preserved = KeyRequestMessage {
+                request: response.request.clone(),
+                keys_of: KeyRequest::Group(1000),
+                signature: response.signature,
+            };
+            assert!(preserved.verify::<Ed25519>(&key_pair.0));
+            if let TestMessage::GetKeyResponse(response) =
+                   round_trip(TestMessage::GetKeyResponse(response)) {
+                resolved = resolved.or(response.add_to(&mut sentinel, 2));
+            }
+        }
+        assert_eq!(resolved, Some((request, claim)));
+    }
+
+    #[test]
+    fn key_request_signature_bound_to_request() {
+        let key_pair = seeded_keypair(53);
+        let other_key_pair = seeded_keypair(54);
+        let request = TestRequest { id: 1, source: 2 };
+        let get_key = KeyRequestMessage::sign(request.clone(), KeyRequest::Client(2),
+                                              &key_pair.1)
+                          .unwrap();
+        assert!(get_key.verify::<Ed25519>(&key_pair.0));
+        assert!(!get_key.verify::<Ed25519>(&other_key_pair.0));
+
+        let mut moved = get_key.clone();
+        moved.request = TestRequest { id: 2, source: 2 };
+        assert!(!moved.verify::<Ed25519>(&key_pair.0));
+
+        let mut moved = get_key;
+        moved.keys_of = KeyRequest::Group(2);
+        assert!(!moved.verify::<Ed25519>(&key_pair.0));
+    }
+
+    #[test]
+    fn group_identities_confirmed() {
+        let mut rng = seeded_rng(51);
+        let group = SignedGroup::random(4, &mut rng);
+        let request = TestRequest { id: rng.gen(), source: 1000 };
+        let mut sentinel: KeySentinel<TestRequest<u64>, u64, TestId<u64>, TestGroupClaim<u64>> =
+            KeySentinel::new();
+
+        let mut confirmed = None;
+        for response in group.key_responses() {
+            let message = TestMessage::GroupIdentities(GroupIdentitiesMessage {
+                request: request.clone(),
+                sender: response.sender,
+                serialised: CborBytes(response.serialised),
+                signature: response.signature,
+                claim: response.group_claim,
+            });
+            if let TestMessage::GroupIdentities(message) = round_trip(message) {
+                confirmed = confirmed.or(message.add_to(&mut sentinel, 3).unwrap());
+            }
+        }
+        let mut identities = group.group_claim().identities;
+        identities.sort();
+        assert_eq!(confirmed.map(|(_, mut confirmed)| { confirmed.sort(); confirmed }),
+                   Some(identities));
+    }
+
+    #[test]
+    fn other_versions_refused() {
+        let key_pair = seeded_keypair(55);
+        let message = TestMessage::GetKey(KeyRequestMessage::sign(TestRequest { id: 1, source: 2 },
+                                                                  KeyRequest::Client(2),
+                                                                  &key_pair.1)
+                                              .unwrap());
+        let body = encode_one(&message).unwrap();
+        let bytes = encode_one(&Envelope { version: WIRE_VERSION + 1, body: CborBytes(body) })
+                        .unwrap();
+
+        match TestMessage::decode(&bytes, &ClaimLimits::default()) {
+            Err(WireError::UnsupportedVersion(version)) => assert_eq!(version, WIRE_VERSION + 1),
+            _ => panic!("expected the version to be refused"),
+        }
+        assert!(TestMessage::decode(&[], &ClaimLimits::default()).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes_refused() {
+        let mut bytes = claim_message(vec![1, 2, 3]).encode().unwrap();
+        let trailing = claim_message(vec![4, 5, 6]).encode().unwrap();
+        bytes.extend(trailing);
+
+        match TestMessage::decode(&bytes, &ClaimLimits::default()) {
+            Err(WireError::TrailingBytes) => (),
+            _ => panic!("expected the trailing bytes to be refused"),
+        }
+    }
+
+    #[test]
+    fn oversize_claims_refused() {
+        let mut rng = seeded_rng(56);
+        let limits = ClaimLimits { max_claim_size: 8, ..ClaimLimits::default() };
+        let fitting = claim_message(random_claim(8, &mut rng)).encode().unwrap();
+        assert!(TestMessage::decode(&fitting, &limits).is_ok());
+
+        let oversize = claim_message(random_claim(9, &mut rng)).encode().unwrap();
+        match TestMessage::decode(&oversize, &limits) {
+            Err(WireError::ClaimTooLarge(length)) => assert_eq!(length, 9),
+            _ => panic!("expected the claim to be refused"),
+        }
+
+        // Bytes that can't hold a claim within the limits aren't decoded at all.
+        let flood = vec![0u8; 8 + MAX_MESSAGE_OVERHEAD + 1];
+        match TestMessage::decode(&flood, &limits) {
+            Err(WireError::MessageTooLarge(length)) => assert_eq!(length, flood.len()),
+            _ => panic!("expected the bytes to be refused"),
+        }
+    }
+}